@@ -0,0 +1,42 @@
+//! Parse-time benchmarks for the built-in page parser.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use sachem::page_parser::{PageParser, ViewsRowParser};
+use std::hint::black_box;
+
+const SIGHTING_HTML: &str = include_str!("../tests/fixtures/sighting.html");
+
+/// Rows on the page built for the multi-row benchmark
+const MULTI_ROW_COUNT: usize = 50;
+
+/// A listing page holding `rows` copies of the fixture's sighting row
+fn multi_row_html(rows: usize) -> String {
+    let start = SIGHTING_HTML.find("<div class=\"views-row").unwrap();
+    let end = SIGHTING_HTML.rfind("</div>\n  </div>\n</div>").unwrap();
+    let row = &SIGHTING_HTML[start..end];
+    format!(
+        "{}{}{}",
+        &SIGHTING_HTML[..start],
+        row.repeat(rows),
+        &SIGHTING_HTML[end..]
+    )
+}
+
+fn bench_single_row(c: &mut Criterion) {
+    let parser = ViewsRowParser::default();
+    c.bench_function("views_row/single", |b| {
+        b.iter(|| parser.parse(black_box(SIGHTING_HTML)))
+    });
+}
+
+fn bench_multi_row(c: &mut Criterion) {
+    let parser = ViewsRowParser::default();
+    let html = multi_row_html(MULTI_ROW_COUNT);
+    assert_eq!(parser.parse(&html).len(), MULTI_ROW_COUNT);
+    c.bench_function("views_row/multi", |b| {
+        b.iter(|| parser.parse(black_box(&html)))
+    });
+}
+
+criterion_group!(benches, bench_single_row, bench_multi_row);
+criterion_main!(benches);
@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+
+/// False-positive rate used by `--approx-dedup`
+pub const DEFAULT_FP_RATE: f64 = 0.001;
+
+/// Approximate set of sighting IDs. `contains` never misses an inserted ID, but may claim
+/// an ID that was never inserted, at about the false-positive rate it was sized for.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Size a filter for `expected_items` IDs at a false-positive rate of `fp_rate`
+    pub fn with_rate(expected_items: usize, fp_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * fp_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    pub fn insert(&mut self, id: u64) {
+        for bit in self.bit_indexes(id) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.bit_indexes(id)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Memory held by the bit array, in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    /// Bit positions for an ID, by double hashing two independent mixes of it
+    fn bit_indexes(&self, id: u64) -> impl Iterator<Item = u64> + use<> {
+        let h1 = mix(id);
+        let h2 = mix(id ^ 0x9e37_79b9_7f4a_7c15) | 1;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+/// Sighting IDs to skip, held exactly, or with `--approx-dedup` in a bloom filter that
+/// needs far less memory but now and then holds an ID that was never added
+pub enum IdSet {
+    Exact(HashSet<u64>),
+    /// The filter, and how many IDs went into it
+    Approx(BloomFilter, usize),
+}
+
+impl Default for IdSet {
+    fn default() -> Self {
+        IdSet::Exact(HashSet::new())
+    }
+}
+
+impl IdSet {
+    /// An empty set, a bloom filter sized for `expected` IDs when `approx`
+    pub fn new(approx: bool, expected: usize) -> Self {
+        if approx {
+            IdSet::Approx(BloomFilter::with_rate(expected, DEFAULT_FP_RATE), 0)
+        } else {
+            IdSet::default()
+        }
+    }
+
+    /// A set of `ids`, sized from their count without collecting them first
+    pub fn from_ids(approx: bool, ids: impl ExactSizeIterator<Item = u64>) -> Self {
+        let mut set = IdSet::new(approx, ids.len());
+        for id in ids {
+            set.insert(id);
+        }
+        set
+    }
+
+    /// The same IDs in a bloom filter, or the set itself when it already is one
+    pub fn into_approx(self) -> Self {
+        match self {
+            IdSet::Exact(ids) => IdSet::from_ids(true, ids.into_iter()),
+            approx => approx,
+        }
+    }
+
+    /// Add `id`, returning whether it was not already there
+    pub fn insert(&mut self, id: u64) -> bool {
+        match self {
+            IdSet::Exact(ids) => ids.insert(id),
+            IdSet::Approx(filter, len) => {
+                if filter.contains(id) {
+                    return false;
+                }
+                filter.insert(id);
+                *len += 1;
+                true
+            }
+        }
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        match self {
+            IdSet::Exact(ids) => ids.contains(&id),
+            IdSet::Approx(filter, _) => filter.contains(id),
+        }
+    }
+
+    /// IDs added, less any a bloom filter took for one already there
+    pub fn len(&self) -> usize {
+        match self {
+            IdSet::Exact(ids) => ids.len(),
+            IdSet::Approx(_, len) => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Memory held by the bloom filter, when the set is one
+    pub fn filter_bytes(&self) -> Option<usize> {
+        match self {
+            IdSet::Exact(_) => None,
+            IdSet::Approx(filter, _) => Some(filter.size_bytes()),
+        }
+    }
+}
+
+/// SplitMix64 finalizer, spreading nearby IDs across the whole hash range
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter_error_rate() {
+        let fp_rate = 0.01;
+        let mut filter = BloomFilter::with_rate(10_000, fp_rate);
+        // Consecutive IDs, as in a dead range of the site
+        for id in 1_000_000..1_010_000 {
+            filter.insert(id);
+        }
+
+        assert!((1_000_000..1_010_000).all(|id| filter.contains(id)));
+        let false_positives = (2_000_000..2_100_000u64)
+            .filter(|&id| filter.contains(id))
+            .count();
+        let observed = false_positives as f64 / 100_000.0;
+        assert!(observed < 2.0 * fp_rate, "false-positive rate {}", observed);
+    }
+
+    #[test]
+    fn test_bloom_filter_is_smaller_than_a_hash_set() {
+        let ids = IdSet::from_ids(true, (0..1_000_000u32).map(u64::from));
+        // A HashSet<u64> needs at least 8 bytes per ID before any table overhead
+        assert!(ids.filter_bytes().unwrap() < 1_000_000 * 8 / 4);
+        assert!(ids.contains(999_999));
+        // Some IDs look already added to a filter, at about its false-positive rate
+        assert!(ids.len() > 999_000);
+    }
+
+    #[test]
+    fn test_id_set_into_approx_keeps_ids() {
+        let mut ids = IdSet::from_ids(false, [3, 5, 8].into_iter());
+        assert!(!ids.insert(5));
+        let ids = ids.into_approx();
+        assert!(ids.filter_bytes().is_some());
+        assert!([3, 5, 8].iter().all(|&id| ids.contains(id)));
+        assert_eq!(ids.len(), 3);
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use std::time::{Duration, Instant};
+
+/// Time source the scraper paces requests with: the delays, backoff, burst pauses and
+/// maintenance cooldowns all wait through it. Tests substitute a clock whose sleeps return
+/// at once, so retry logic can be checked without real waits.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, for the dates a server sends
+    fn utc_now(&self) -> DateTime<Utc>;
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Real time, waiting with `tokio::time::sleep`
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A clock that only moves when slept on: each sleep returns at once, advances `now` by
+/// its duration and is recorded for inspection
+#[cfg(test)]
+#[derive(Default)]
+pub struct ManualClock {
+    elapsed: std::sync::Mutex<Duration>,
+    sleeps: std::sync::Mutex<Vec<Duration>>,
+    start: std::sync::OnceLock<Instant>,
+    utc_start: std::sync::OnceLock<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// A clock whose wall-clock time starts at `utc_start`
+    pub fn at(utc_start: DateTime<Utc>) -> Self {
+        let clock = ManualClock::default();
+        clock.utc_start.set(utc_start).unwrap();
+        clock
+    }
+
+    /// Every sleep asked for so far, in order
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.start.get_or_init(Instant::now) + *self.elapsed.lock().unwrap()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        *self.utc_start.get_or_init(Utc::now) + *self.elapsed.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        *self.elapsed.lock().unwrap() += duration;
+        self.sleeps.lock().unwrap().push(duration);
+        Box::pin(std::future::ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_manual_clock_advances_only_when_slept() {
+        let clock = ManualClock::default();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        clock.sleep(Duration::from_secs(300)).await;
+        clock.sleep(Duration::from_millis(5)).await;
+        assert_eq!(clock.now() - start, Duration::from_millis(300_005));
+        assert_eq!(
+            clock.sleeps(),
+            vec![Duration::from_secs(300), Duration::from_millis(5)]
+        );
+    }
+}
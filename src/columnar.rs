@@ -0,0 +1,163 @@
+use crate::output::STDOUT;
+use crate::record::SightingRecord;
+use crate::schema::record_schema;
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Records converted to Arrow and written per row group, bounding the extra memory used
+const BATCH_ROWS: usize = 8192;
+
+/// Arrow schema for `SightingRecord`: `sighting_id` and `photo_count` are int64, the
+/// coordinates float64, the flags booleans, every other column a string
+pub fn arrow_schema() -> SchemaRef {
+    let fields: Vec<Field> = record_schema()
+        .iter()
+        .map(|field| {
+            let data_type = match field.field_type {
+                "integer" => DataType::Int64,
+                "number" => DataType::Float64,
+                "boolean" => DataType::Boolean,
+                _ => DataType::Utf8,
+            };
+            Field::new(field.name, data_type, field.nullable)
+        })
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+/// Convert records to one Arrow batch with the columns of `arrow_schema`
+fn record_batch(
+    schema: &SchemaRef,
+    records: &[SightingRecord],
+) -> Result<RecordBatch, Box<dyn std::error::Error + Send + Sync>> {
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(Int64Array::from_iter(
+            records.iter().map(|r| r.sighting_id.map(|id| id as i64)),
+        )),
+        Arc::new(StringArray::from_iter(
+            records.iter().map(|r| r.url.as_deref()),
+        )),
+    ];
+    let text_field_count = SightingRecord::default().text_fields().len();
+    for index in 0..text_field_count {
+        columns.push(Arc::new(StringArray::from_iter_values(
+            records.iter().map(|r| r.text_fields()[index].1),
+        )));
+    }
+    columns.push(Arc::new(StringArray::from_iter(
+        records.iter().map(|r| r.coordinate_precision.as_deref()),
+    )));
+    columns.push(Arc::new(Float64Array::from_iter(
+        records.iter().map(|r| r.latitude),
+    )));
+    columns.push(Arc::new(Float64Array::from_iter(
+        records.iter().map(|r| r.longitude),
+    )));
+    columns.push(Arc::new(BooleanArray::from_iter(
+        records.iter().map(|r| Some(r.location_obscured)),
+    )));
+    columns.push(Arc::new(Int64Array::from_iter_values(
+        records.iter().map(|r| r.photo_count as i64),
+    )));
+    columns.push(Arc::new(BooleanArray::from_iter(
+        records.iter().map(|r| Some(r.has_photo)),
+    )));
+    columns.push(Arc::new(StringArray::from_iter(
+        records.iter().map(|r| r.content_hash.as_deref()),
+    )));
+    columns.push(Arc::new(StringArray::from_iter_values(
+        records.iter().map(|r| r.scraped_at.as_str()),
+    )));
+
+    Ok(RecordBatch::try_new(Arc::clone(schema), columns)?)
+}
+
+/// Write records to a Snappy-compressed Parquet file, one row group per `BATCH_ROWS` records.
+/// For `-` the file is built in memory and then written to stdout.
+pub fn write_parquet(
+    records: &[SightingRecord],
+    filename: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if filename == STDOUT {
+        let bytes = parquet_to(records, Vec::new())?;
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&bytes)?;
+        stdout.flush()?;
+    } else {
+        parquet_to(records, File::create(filename)?)?;
+    }
+    Ok(())
+}
+
+/// Write records as Parquet to `sink`, returning it once the file is complete
+fn parquet_to<W: Write + Send>(
+    records: &[SightingRecord],
+    sink: W,
+) -> Result<W, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = arrow_schema();
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_row_count(Some(BATCH_ROWS))
+        .build();
+    let mut writer = ArrowWriter::try_new(sink, Arc::clone(&schema), Some(props))?;
+
+    for chunk in records.chunks(BATCH_ROWS) {
+        writer.write(&record_batch(&schema, chunk)?)?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("sightings.parquet");
+        let filename = filename.to_str().unwrap();
+        let records: Vec<SightingRecord> = (0..BATCH_ROWS as u64 + 5)
+            .map(|id| SightingRecord {
+                sighting_id: Some(id),
+                url: (id % 2 == 0).then(|| format!("https://example.org/{}", id)),
+                scientific_name: "Danaus plexippus".to_string(),
+                locality: format!("Site {}", id),
+                ..Default::default()
+            })
+            .collect();
+
+        write_parquet(&records, filename).unwrap();
+
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(filename).unwrap()).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        assert_eq!(builder.schema().fields(), arrow_schema().fields());
+        let batches: Vec<RecordBatch> = builder.build().unwrap().map(|b| b.unwrap()).collect();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).sum::<usize>(),
+            records.len()
+        );
+
+        let batch = &batches[0];
+        let column = |name: &str| batch.column(batch.schema().index_of(name).unwrap()).clone();
+        let ids = column("sighting_id");
+        let ids = ids.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(ids.value(3), 3);
+        let urls = column("url");
+        let urls = urls.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(urls.value(2), "https://example.org/2");
+        assert!(urls.is_null(3));
+        let localities = column("locality");
+        let localities = localities.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(localities.value(7), "Site 7");
+    }
+}
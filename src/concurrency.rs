@@ -0,0 +1,290 @@
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Fewest responses a window needs before the limit is reconsidered
+const MIN_WINDOW: usize = 5;
+
+/// How far a window's median latency may rise over the baseline before the limit drops
+const LATENCY_TOLERANCE: f64 = 1.5;
+
+/// Windows whose medians the baseline latency is the lowest of, so it can follow the site
+/// if it settles at a new speed
+const BASELINE_WINDOWS: usize = 10;
+
+/// Most times the soft rate limit doubles the delay between requests, a 16x slowdown
+const MAX_SLOWDOWN: u32 = 4;
+
+/// A concurrency limit tuned from the responses themselves: it starts at one request and
+/// climbs by one after each window of responses whose median latency stays near the
+/// baseline, drops by one when latency rises, and halves at once on a 429 or a failed
+/// request. Requests take permits from `semaphore`, which the limit is applied to.
+pub struct AutoConcurrency {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+    state: Mutex<TuneState>,
+}
+
+#[derive(Debug)]
+struct TuneState {
+    limit: usize,
+    peak: usize,
+    /// Permits still to be retired after a drop, taken as requests release them
+    debt: usize,
+    window: Vec<Duration>,
+    medians: VecDeque<Duration>,
+}
+
+impl AutoConcurrency {
+    /// Start at one request in flight, never going above `max`
+    pub fn new(max: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(1)),
+            max: max.max(1),
+            state: Mutex::new(TuneState {
+                limit: 1,
+                peak: 1,
+                debt: 0,
+                window: Vec::new(),
+                medians: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn semaphore(&self) -> Arc<Semaphore> {
+        Arc::clone(&self.semaphore)
+    }
+
+    /// The current limit
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// The highest limit reached
+    pub fn peak(&self) -> usize {
+        self.state.lock().unwrap().peak
+    }
+
+    /// Take in one response: how long it took, and whether the site pushed back with a 429
+    /// or the request failed
+    pub fn observe(&self, latency: Duration, pushed_back: bool) {
+        let mut state = self.state.lock().unwrap();
+        let old = state.limit;
+
+        if pushed_back {
+            state.limit = (old / 2).max(1);
+            state.window.clear();
+        } else {
+            state.window.push(latency);
+            if state.window.len() >= old.max(MIN_WINDOW) {
+                let median = median(&mut state.window);
+                state.window.clear();
+                state.medians.push_back(median);
+                if state.medians.len() > BASELINE_WINDOWS {
+                    state.medians.pop_front();
+                }
+                let baseline = state.medians.iter().min().copied().unwrap_or(median);
+                if median.as_secs_f64() > baseline.as_secs_f64() * LATENCY_TOLERANCE {
+                    state.limit = (old - 1).max(1);
+                } else if old < self.max {
+                    state.limit = old + 1;
+                }
+            }
+        }
+
+        let new = state.limit;
+        if new > old {
+            // Permits still owed from a drop cancel out before any are added
+            let repaid = state.debt.min(new - old);
+            state.debt -= repaid;
+            self.semaphore.add_permits(new - old - repaid);
+            state.peak = state.peak.max(new);
+        } else if new < old {
+            state.debt += old - new;
+        }
+        if new != old {
+            info!("Concurrency {} -> {}", old, new);
+        }
+        if state.debt > 0 {
+            state.debt -= self.semaphore.forget_permits(state.debt);
+        }
+    }
+}
+
+/// Backing off from a site that slows its responses down instead of answering 429. The
+/// average latency of the first `window` responses is the baseline; after each further
+/// `window` responses, the delay between requests doubles when the rolling average of
+/// the last `window` is over `multiplier` times the baseline, and halves back when it
+/// is not.
+pub struct SoftRateLimit {
+    multiplier: f64,
+    window: usize,
+    state: Mutex<SlowdownState>,
+}
+
+#[derive(Debug, Default)]
+struct SlowdownState {
+    baseline: Option<Duration>,
+    recent: VecDeque<Duration>,
+    /// Responses since the slowdown was last reconsidered
+    since_check: usize,
+    /// How many times the delay is doubled
+    slowdown: u32,
+    backoffs: u64,
+}
+
+impl SoftRateLimit {
+    pub fn new(multiplier: f64, window: usize) -> Self {
+        Self {
+            multiplier,
+            window: window.max(1),
+            state: Mutex::new(SlowdownState::default()),
+        }
+    }
+
+    /// What the delay between requests is multiplied by
+    pub fn factor(&self) -> u32 {
+        1 << self.state.lock().unwrap().slowdown
+    }
+
+    /// Times the delay was doubled
+    pub fn backoffs(&self) -> u64 {
+        self.state.lock().unwrap().backoffs
+    }
+
+    /// The latency the rolling average is held against, once the first window is in
+    pub fn baseline(&self) -> Option<Duration> {
+        self.state.lock().unwrap().baseline
+    }
+
+    /// Take in the latency of one response the site answered
+    pub fn observe(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.recent.push_back(latency);
+        if state.recent.len() > self.window {
+            state.recent.pop_front();
+        }
+        state.since_check += 1;
+        if state.since_check < self.window {
+            return;
+        }
+        state.since_check = 0;
+
+        let average = state.recent.iter().sum::<Duration>() / state.recent.len() as u32;
+        let Some(baseline) = state.baseline else {
+            info!("Baseline latency {}ms", average.as_millis());
+            state.baseline = Some(average);
+            return;
+        };
+        if average.as_secs_f64() > baseline.as_secs_f64() * self.multiplier {
+            if state.slowdown < MAX_SLOWDOWN {
+                state.slowdown += 1;
+                state.backoffs += 1;
+                warn!(
+                    "Average latency {}ms is over {}x the {}ms baseline, slowing to {}x the delay",
+                    average.as_millis(),
+                    self.multiplier,
+                    baseline.as_millis(),
+                    1 << state.slowdown
+                );
+            }
+        } else if state.slowdown > 0 {
+            state.slowdown -= 1;
+            info!(
+                "Average latency {}ms is back near the baseline, easing to {}x the delay",
+                average.as_millis(),
+                1 << state.slowdown
+            );
+        }
+    }
+}
+
+/// Middle value of a non-empty window, reordering it
+fn median(window: &mut [Duration]) -> Duration {
+    window.sort_unstable();
+    window[window.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe_window(tuner: &AutoConcurrency, latency_ms: u64) {
+        for _ in 0..tuner.limit().max(MIN_WINDOW) {
+            tuner.observe(Duration::from_millis(latency_ms), false);
+        }
+    }
+
+    #[test]
+    fn test_climbs_while_flat_and_backs_off() {
+        let tuner = AutoConcurrency::new(4);
+        for _ in 0..6 {
+            observe_window(&tuner, 100);
+        }
+        // Capped at the maximum
+        assert_eq!(tuner.limit(), 4);
+        assert_eq!(tuner.semaphore.available_permits(), 4);
+
+        // A latency rise drops the limit by one
+        observe_window(&tuner, 400);
+        assert_eq!(tuner.limit(), 3);
+        assert_eq!(tuner.semaphore.available_permits(), 3);
+
+        // A 429 halves it at once
+        tuner.observe(Duration::from_millis(100), true);
+        assert_eq!(tuner.limit(), 1);
+        assert_eq!(tuner.peak(), 4);
+        assert_eq!(tuner.semaphore.available_permits(), 1);
+    }
+
+    #[test]
+    fn test_drop_waits_for_permits_in_use() {
+        let tuner = AutoConcurrency::new(4);
+        for _ in 0..3 {
+            observe_window(&tuner, 100);
+        }
+        assert_eq!(tuner.limit(), 4);
+
+        let semaphore = tuner.semaphore();
+        let held: Vec<_> = (0..4).map(|_| semaphore.try_acquire().unwrap()).collect();
+        tuner.observe(Duration::from_millis(100), true);
+        assert_eq!(tuner.limit(), 2);
+        assert_eq!(semaphore.available_permits(), 0);
+
+        // Released permits pay off the drop before any become available again
+        drop(held);
+        tuner.observe(Duration::from_millis(100), false);
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[test]
+    fn test_soft_rate_limit_backs_off_on_slow_responses() {
+        let limit = SoftRateLimit::new(3.0, 4);
+        let observe = |latency_ms: u64| {
+            for _ in 0..4 {
+                limit.observe(Duration::from_millis(latency_ms));
+            }
+        };
+        observe(100);
+        assert_eq!(limit.baseline(), Some(Duration::from_millis(100)));
+        assert_eq!(limit.factor(), 1);
+
+        // Slower, but within the multiplier
+        observe(250);
+        assert_eq!(limit.factor(), 1);
+
+        observe(500);
+        observe(500);
+        assert_eq!(limit.factor(), 4);
+        assert_eq!(limit.backoffs(), 2);
+
+        // Eases back one step per window once responses recover
+        observe(100);
+        assert_eq!(limit.factor(), 2);
+        observe(100);
+        assert_eq!(limit.factor(), 1);
+        assert_eq!(limit.backoffs(), 2);
+    }
+}
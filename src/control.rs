@@ -0,0 +1,219 @@
+use crate::record::SightingRecord;
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Messages buffered per client before a slow reader starts missing them
+const EVENT_BUFFER: usize = 1024;
+
+/// A message sent to control socket clients, serialized as one JSON object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlEvent<'a> {
+    /// A sighting finished, successfully or not
+    Progress {
+        completed: u64,
+        total: u64,
+        records: u64,
+        missing: usize,
+        rate: f64,
+        current_id: u64,
+    },
+    /// A sighting was scraped
+    Record { record: &'a SightingRecord },
+    /// The run is over and the output is about to be written
+    Finished { records: usize, elapsed_secs: f64 },
+}
+
+/// Sends control events to every connected client. Cloning shares the same clients.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: broadcast::Sender<String>,
+}
+
+impl EventSender {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        Self { sender }
+    }
+
+    /// Send an event to the connected clients; without any it is dropped
+    pub fn send(&self, event: &ControlEvent) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        match serde_json::to_string(event) {
+            Ok(line) => {
+                let _ = self.sender.send(line);
+            }
+            Err(e) => warn!("Could not serialize control event: {}", e),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.sender.subscribe()
+    }
+}
+
+/// Consecutive failed accepts after which a listener stops
+const MAX_ACCEPT_FAILURES: u32 = 10;
+
+/// Pause after a failed accept, doubled with each further failure in a row
+const ACCEPT_BACKOFF: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Failed accepts in a row on a listener, so a persistent error such as running out of
+/// file descriptors neither spins the accept loop nor floods the log
+#[derive(Debug, Default)]
+pub struct AcceptFailures {
+    failures: u32,
+}
+
+impl AcceptFailures {
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Log a failed accept on `listener` and wait out the backoff before the next one.
+    /// Returns false once too many in a row mean the listener should stop.
+    pub async fn failed(&mut self, listener: &str, e: &std::io::Error) -> bool {
+        self.failures += 1;
+        if self.failures >= MAX_ACCEPT_FAILURES {
+            error!(
+                "{} stopped after {} failed accepts: {}",
+                listener, self.failures, e
+            );
+            return false;
+        }
+        warn!("{} accept failed: {}", listener, e);
+        tokio::time::sleep(ACCEPT_BACKOFF * 2u32.pow(self.failures - 1)).await;
+        true
+    }
+}
+
+/// Listen on a Unix domain socket at `path`, streaming the events sent through `events`
+/// to every client that connects. A stale socket file left at `path` is replaced, but any
+/// other file there is left alone and is an error.
+#[cfg(unix)]
+pub fn serve_control_socket(path: &str, events: &EventSender) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::UnixListener;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path),
+            ));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("Control socket listening on {}", path);
+
+    let events = events.clone();
+    tokio::spawn(async move {
+        let mut failures = AcceptFailures::default();
+        loop {
+            let mut socket = match listener.accept().await {
+                Ok((socket, _)) => {
+                    failures.succeeded();
+                    socket
+                }
+                Err(e) if failures.failed("Control socket", &e).await => continue,
+                Err(_) => break,
+            };
+            let mut receiver = events.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    let line = match receiver.recv().await {
+                        Ok(line) => line,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("Control client fell behind, skipped {} events", skipped);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if socket
+                        .write_all(format!("{}\n", line).as_bytes())
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve_control_socket(_path: &str, _events: &EventSender) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "--control-socket needs Unix domain sockets",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::UnixStream;
+    use tokio::time::{Duration, sleep, timeout};
+
+    #[tokio::test]
+    async fn test_control_socket_streams_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let path = path.to_str().unwrap();
+        let events = EventSender::new();
+        serve_control_socket(path, &events).unwrap();
+
+        let stream = UnixStream::connect(path).await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let record = SightingRecord {
+            sighting_id: Some(7),
+            ..Default::default()
+        };
+
+        // The client is subscribed once the listener accepts it, so resend until it hears
+        let line = timeout(Duration::from_secs(5), async {
+            loop {
+                events.send(&ControlEvent::Record { record: &record });
+                tokio::select! {
+                    line = lines.next_line() => break line.unwrap().unwrap(),
+                    _ = sleep(Duration::from_millis(10)) => {}
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(message["type"], "record");
+        assert_eq!(message["record"]["sighting_id"], 7);
+    }
+
+    #[tokio::test]
+    async fn test_control_socket_keeps_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.csv");
+        std::fs::write(&path, "sighting_id\n7\n").unwrap();
+        let events = EventSender::new();
+
+        let err = serve_control_socket(path.to_str().unwrap(), &events).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "sighting_id\n7\n");
+
+        // A socket left by an earlier run is replaced
+        let stale = dir.path().join("control.sock");
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        serve_control_socket(stale.to_str().unwrap(), &events).unwrap();
+        UnixStream::connect(&stale).await.unwrap();
+    }
+}
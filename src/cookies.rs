@@ -0,0 +1,117 @@
+use chrono::Utc;
+
+/// A cookie from a cookie file, as a `Set-Cookie` value and the URL it was set from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieEntry {
+    pub url: String,
+    pub set_cookie: String,
+}
+
+/// Parse a Netscape-format cookie jar, as exported by browsers and written by `curl -c`.
+/// Expired cookies are skipped; session cookies (expiry 0) are kept.
+pub fn parse_netscape_cookies(text: &str) -> Result<Vec<CookieEntry>, String> {
+    let now = Utc::now().timestamp();
+    let mut entries = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        // curl marks HttpOnly cookies with a prefix on an otherwise commented-out line
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [
+            domain,
+            include_subdomains,
+            path,
+            secure,
+            expiry,
+            name,
+            value,
+        ] = fields[..]
+        else {
+            return Err(format!(
+                "line {}: expected 7 tab-separated fields, got {}",
+                index + 1,
+                fields.len()
+            ));
+        };
+        let expiry: i64 = expiry
+            .trim()
+            .parse()
+            .map_err(|_| format!("line {}: invalid expiry \"{}\"", index + 1, expiry))?;
+        if expiry != 0 && expiry < now {
+            continue;
+        }
+
+        let host = domain.trim_start_matches('.');
+        let secure = secure.eq_ignore_ascii_case("TRUE");
+        let mut set_cookie = format!("{}={}; Path={}", name, value, path);
+        if include_subdomains.eq_ignore_ascii_case("TRUE") {
+            set_cookie.push_str(&format!("; Domain={}", host));
+        }
+        if secure {
+            set_cookie.push_str("; Secure");
+        }
+        let scheme = if secure { "https" } else { "http" };
+
+        entries.push(CookieEntry {
+            url: format!("{}://{}{}", scheme, host, path),
+            set_cookie,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Read a Netscape-format cookie file
+pub fn read_cookie_file(filename: &str) -> Result<Vec<CookieEntry>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(filename)?;
+    Ok(parse_netscape_cookies(&text).map_err(|e| format!("{}: {}", filename, e))?)
+}
+
+/// Check a `name=value` cookie given on the command line
+pub fn parse_cookie(cookie: &str) -> Result<String, String> {
+    match cookie.split_once('=') {
+        Some((name, _)) if !name.trim().is_empty() => Ok(cookie.trim().to_string()),
+        _ => Err(format!("expected \"name=value\", got \"{}\"", cookie)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_netscape_cookies() {
+        let text = "# Netscape HTTP Cookie File\n\
+            \n\
+            .example.org\tTRUE\t/\tTRUE\t0\tsession\tabc123\n\
+            #HttpOnly_mirror.example.org\tFALSE\t/sightings\tFALSE\t4102444800\ttoken\txyz\n\
+            old.example.org\tFALSE\t/\tFALSE\t1000\tstale\t1\n";
+        let entries = parse_netscape_cookies(text).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                CookieEntry {
+                    url: "https://example.org/".to_string(),
+                    set_cookie: "session=abc123; Path=/; Domain=example.org; Secure".to_string(),
+                },
+                CookieEntry {
+                    url: "http://mirror.example.org/sightings".to_string(),
+                    set_cookie: "token=xyz; Path=/sightings".to_string(),
+                },
+            ]
+        );
+        assert!(parse_netscape_cookies("example.org\tTRUE\t/\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_cookie() {
+        assert_eq!(parse_cookie("sid=a=b").unwrap(), "sid=a=b");
+        assert!(parse_cookie("novalue").is_err());
+        assert!(parse_cookie("=value").is_err());
+    }
+}
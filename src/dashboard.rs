@@ -0,0 +1,235 @@
+use crate::control::{AcceptFailures, EventSender};
+use crate::metrics::{ParseWarning, RequestTimings};
+use crate::scraper::ButterflyMothScraper;
+use log::{info, warn};
+use serde_json::{Map, Value, json};
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+/// Page served at `/`, which polls `/status.json`
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+/// Scraped records kept for the "recent records" table
+const RECENT_RECORDS: usize = 20;
+
+/// Address the dashboard listens on unless told otherwise: this machine only
+pub const DEFAULT_DASHBOARD_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// What the dashboard has heard from the control events so far
+#[derive(Default)]
+struct EventState {
+    progress: Option<Value>,
+    recent: VecDeque<Value>,
+    finished: bool,
+}
+
+impl EventState {
+    fn apply(&mut self, line: &str) {
+        let Ok(mut event) = serde_json::from_str::<Value>(line) else {
+            return;
+        };
+        match event["type"].as_str() {
+            Some("progress") => self.progress = Some(event),
+            Some("record") => {
+                if self.recent.len() == RECENT_RECORDS {
+                    self.recent.pop_back();
+                }
+                self.recent.push_front(event["record"].take());
+            }
+            Some("finished") => self.finished = true,
+            _ => {}
+        }
+    }
+}
+
+/// Shared state behind the dashboard's JSON endpoint
+#[derive(Clone)]
+struct Dashboard {
+    events: Arc<Mutex<EventState>>,
+    missing_sightings: Arc<Mutex<Vec<u64>>>,
+    parse_warnings: Arc<Mutex<Vec<(u64, ParseWarning)>>>,
+    request_timings: Arc<Mutex<RequestTimings>>,
+}
+
+impl Dashboard {
+    /// Progress, recent records and a breakdown of errors, as served at `/status.json`
+    fn status(&self) -> Value {
+        let mut errors = Map::new();
+        errors.insert(
+            "missing".to_string(),
+            json!(self.missing_sightings.lock().unwrap().len()),
+        );
+        {
+            let timings = self.request_timings.lock().unwrap();
+            errors.insert("retries".to_string(), json!(timings.retries));
+            errors.insert("timeouts".to_string(), json!(timings.timeouts));
+            errors.insert(
+                "non_html".to_string(),
+                json!(timings.non_html.values().sum::<u64>()),
+            );
+        }
+        for (_, warning) in self.parse_warnings.lock().unwrap().iter() {
+            let count = errors.entry(warning.to_string()).or_insert(json!(0));
+            *count = json!(count.as_u64().unwrap_or(0) + 1);
+        }
+
+        let events = self.events.lock().unwrap();
+        json!({
+            "progress": events.progress,
+            "recent": events.recent,
+            "finished": events.finished,
+            "errors": errors,
+        })
+    }
+
+    /// Answer one HTTP request: the page at `/`, the status at `/status.json`
+    async fn respond(&self, mut socket: TcpStream) -> std::io::Result<()> {
+        let mut request = vec![0u8; 8192];
+        let read = socket.read(&mut request).await?;
+        let request = String::from_utf8_lossy(&request[..read]);
+        let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+        let (status, content_type, body) = match path {
+            "/" | "/index.html" => (
+                "200 OK",
+                "text/html; charset=utf-8",
+                DASHBOARD_HTML.to_string(),
+            ),
+            "/status.json" => ("200 OK", "application/json", self.status().to_string()),
+            _ => ("404 Not Found", "text/plain", "not found".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        socket.write_all(response.as_bytes()).await?;
+        socket.shutdown().await
+    }
+}
+
+/// Serve a live dashboard on `port` of the interface at `bind`: an auto-refreshing page at
+/// `/` and its data at `/status.json`. It has no authentication, so `bind` should be a
+/// loopback address unless the network is trusted. Progress and recent records come from the events sent
+/// through `events`; errors are read from the scraper's own counters. Returns the address
+/// bound, which tells the caller the port when `port` is 0.
+pub fn serve_dashboard(
+    bind: IpAddr,
+    port: u16,
+    events: &EventSender,
+    scraper: &ButterflyMothScraper,
+) -> std::io::Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind((bind, port))?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let addr = listener.local_addr()?;
+    info!("Dashboard listening on http://{}", addr);
+
+    let dashboard = Dashboard {
+        events: Arc::new(Mutex::new(EventState::default())),
+        missing_sightings: Arc::clone(&scraper.missing_sightings),
+        parse_warnings: Arc::clone(&scraper.parse_warnings),
+        request_timings: Arc::clone(&scraper.request_timings),
+    };
+
+    let mut receiver = events.subscribe();
+    let state = Arc::clone(&dashboard.events);
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(line) => state.lock().unwrap().apply(&line),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut failures = AcceptFailures::default();
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => {
+                    failures.succeeded();
+                    socket
+                }
+                Err(e) if failures.failed("Dashboard", &e).await => continue,
+                Err(_) => break,
+            };
+            let dashboard = dashboard.clone();
+            tokio::spawn(async move {
+                if let Err(e) = dashboard.respond(socket).await {
+                    warn!("Dashboard request failed: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::ControlEvent;
+    use crate::record::SightingRecord;
+    use tokio::time::{Duration, sleep, timeout};
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut socket = TcpStream::connect(("127.0.0.1", addr.port()))
+            .await
+            .unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_dashboard_serves_status() {
+        let scraper = ButterflyMothScraper::new();
+        scraper.missing_sightings.lock().unwrap().push(9);
+        scraper
+            .parse_warnings
+            .lock()
+            .unwrap()
+            .push((9, ParseWarning::NoRow));
+        let events = EventSender::new();
+        let addr = serve_dashboard(DEFAULT_DASHBOARD_BIND, 0, &events, &scraper).unwrap();
+        assert!(addr.ip().is_loopback());
+
+        let record = SightingRecord {
+            sighting_id: Some(8),
+            common_name: "Monarch".to_string(),
+            ..Default::default()
+        };
+        events.send(&ControlEvent::Record { record: &record });
+
+        // The event is applied in the background, so poll until it shows up
+        let status = timeout(Duration::from_secs(5), async {
+            loop {
+                let response = get(addr, "/status.json").await;
+                let body = response.split("\r\n\r\n").nth(1).unwrap().to_string();
+                let status: Value = serde_json::from_str(&body).unwrap();
+                if !status["recent"].as_array().unwrap().is_empty() {
+                    break status;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(status["recent"][0]["sighting_id"], 8);
+        assert_eq!(status["errors"]["missing"], 1);
+        assert_eq!(status["errors"]["no sighting row found"], 1);
+        assert!(get(addr, "/").await.contains("<title>Sachem</title>"));
+        assert!(get(addr, "/nope").await.starts_with("HTTP/1.1 404"));
+    }
+}
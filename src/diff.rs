@@ -0,0 +1,202 @@
+use crate::record::SightingRecord;
+use crate::schema::record_schema;
+use crate::util::{coalesce_ranges, format_range, status};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Columns left out of the comparison: when a record was scraped says nothing about the
+/// sighting itself, and a changed hash only repeats the fields that changed
+const IGNORED_FIELDS: [&str; 3] = ["sighting_id", "content_hash", "scraped_at"];
+
+/// One field whose value differs between two outputs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// A sighting present in both outputs with at least one changed field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedSighting {
+    pub sighting_id: u64,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Sightings added, removed and changed between an older and a newer output, by ID
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct OutputDiff {
+    pub added: Vec<u64>,
+    pub removed: Vec<u64>,
+    pub changed: Vec<ChangedSighting>,
+}
+
+impl OutputDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two sets of records matched on `sighting_id`. Records without an ID are
+/// skipped, and when an ID repeats within one side its last record counts. Two records
+/// with the same `content_hash` count as unchanged without comparing their fields.
+pub fn diff_records(old: &[SightingRecord], new: &[SightingRecord]) -> OutputDiff {
+    let (old, new) = (by_id(old), by_id(new));
+    let fields: Vec<&str> = record_schema()
+        .into_iter()
+        .map(|field| field.name)
+        .filter(|name| !IGNORED_FIELDS.contains(name))
+        .collect();
+
+    let mut diff = OutputDiff {
+        added: new
+            .keys()
+            .filter(|id| !old.contains_key(id))
+            .copied()
+            .collect(),
+        removed: old
+            .keys()
+            .filter(|id| !new.contains_key(id))
+            .copied()
+            .collect(),
+        changed: Vec::new(),
+    };
+    for (&sighting_id, old_record) in &old {
+        let Some(new_record) = new.get(&sighting_id) else {
+            continue;
+        };
+        if old_record.content_hash.is_some() && old_record.content_hash == new_record.content_hash {
+            continue;
+        }
+        let changes: Vec<FieldChange> = fields
+            .iter()
+            .filter_map(|&field| {
+                let old = old_record.field_value(field).unwrap_or_default();
+                let new = new_record.field_value(field).unwrap_or_default();
+                (old != new).then(|| FieldChange {
+                    field: field.to_string(),
+                    old,
+                    new,
+                })
+            })
+            .collect();
+        if !changes.is_empty() {
+            diff.changed.push(ChangedSighting {
+                sighting_id,
+                changes,
+            });
+        }
+    }
+    diff
+}
+
+/// Records keyed by sighting ID, the last record winning for a repeated ID
+fn by_id(records: &[SightingRecord]) -> BTreeMap<u64, &SightingRecord> {
+    records
+        .iter()
+        .filter_map(|record| record.sighting_id.map(|id| (id, record)))
+        .collect()
+}
+
+/// Print the diff for reading: added and removed IDs as ranges, then each changed field
+pub fn print_diff_report(diff: &OutputDiff) {
+    if diff.is_empty() {
+        status!("No differences");
+        return;
+    }
+
+    let ranges = |ids: &[u64]| -> String {
+        let ranges: Vec<String> = coalesce_ranges(ids).iter().map(format_range).collect();
+        ranges.join(", ")
+    };
+    if !diff.added.is_empty() {
+        status!("{} added: {}", diff.added.len(), ranges(&diff.added));
+    }
+    if !diff.removed.is_empty() {
+        status!("{} removed: {}", diff.removed.len(), ranges(&diff.removed));
+    }
+    if !diff.changed.is_empty() {
+        status!("{} changed:", diff.changed.len());
+        for sighting in &diff.changed {
+            for change in &sighting.changes {
+                status!(
+                    "  {}: {} {:?} -> {:?}",
+                    sighting.sighting_id,
+                    change.field,
+                    change.old,
+                    change.new
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: Option<u64>, status: &str) -> SightingRecord {
+        SightingRecord {
+            sighting_id: id,
+            common_name: "Monarch".to_string(),
+            status: status.to_string(),
+            scraped_at: format!("2025-06-0{}T00:00:00Z", id.unwrap_or(1)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_records() {
+        let old = vec![
+            record(Some(1), "Pending"),
+            record(Some(2), "Verified"),
+            record(Some(3), "Verified"),
+            record(None, "Pending"),
+        ];
+        let mut rescraped = record(Some(3), "Verified");
+        rescraped.scraped_at = "2025-07-01T00:00:00Z".to_string();
+        let new = vec![
+            record(Some(4), "Pending"),
+            record(Some(1), "Verified"),
+            rescraped,
+        ];
+
+        let diff = diff_records(&old, &new);
+        assert_eq!(diff.added, vec![4]);
+        assert_eq!(diff.removed, vec![2]);
+        assert_eq!(
+            diff.changed,
+            vec![ChangedSighting {
+                sighting_id: 1,
+                changes: vec![FieldChange {
+                    field: "status".to_string(),
+                    old: "Pending".to_string(),
+                    new: "Verified".to_string(),
+                }],
+            }]
+        );
+        assert!(diff_records(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_diff_records_by_content_hash() {
+        let hashed = |status: &str| {
+            let mut record = record(Some(1), status);
+            record.content_hash = Some(record.compute_content_hash());
+            record
+        };
+        let mut rescraped = hashed("Pending");
+        rescraped.scraped_at = "2025-07-01T00:00:00Z".to_string();
+        assert!(diff_records(&[hashed("Pending")], &[rescraped]).is_empty());
+
+        // A changed hash reports the fields behind it, not the hash
+        let diff = diff_records(&[hashed("Pending")], &[hashed("Verified")]);
+        assert_eq!(diff.changed.len(), 1);
+        let fields: Vec<&str> = diff.changed[0]
+            .changes
+            .iter()
+            .map(|change| change.field.as_str())
+            .collect();
+        assert_eq!(fields, vec!["status"]);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::output::STDOUT;
+use crate::record::{SightingRecord, SpecimenType};
+use crate::util::parse_date;
+use log::info;
+use std::fs::File;
+use std::io::{Cursor, Seek, Write};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+const DWC: &str = "http://rs.tdwg.org/dwc/terms/";
+const DCTERMS: &str = "http://purl.org/dc/terms/";
+
+/// Columns of `occurrence.txt` after the `id` column, as term name and namespace
+const TERMS: [(&str, &str); 18] = [
+    ("occurrenceID", DWC),
+    ("basisOfRecord", DWC),
+    ("scientificName", DWC),
+    ("vernacularName", DWC),
+    ("eventDate", DWC),
+    ("verbatimEventDate", DWC),
+    ("recordedBy", DWC),
+    ("identifiedBy", DWC),
+    ("dateIdentified", DWC),
+    ("identificationVerificationStatus", DWC),
+    ("higherGeography", DWC),
+    ("locality", DWC),
+    ("decimalLatitude", DWC),
+    ("decimalLongitude", DWC),
+    ("georeferenceRemarks", DWC),
+    ("informationWithheld", DWC),
+    ("references", DCTERMS),
+    ("modified", DCTERMS),
+];
+
+/// Record columns with no Darwin Core term, left out of the archive
+const UNMAPPED: [&str; 5] = [
+    "species_link",
+    "submitted_by_url",
+    "verified_by_url",
+    "submitted_date",
+    "content_hash",
+];
+
+/// Darwin Core basis of record for a specimen type: photographs count as human
+/// observations, as GBIF asks, and anything unrecognized is a plain occurrence
+fn basis_of_record(specimen_type: Option<SpecimenType>) -> &'static str {
+    match specimen_type {
+        Some(SpecimenType::Specimen) => "PreservedSpecimen",
+        Some(SpecimenType::Photograph | SpecimenType::Sighting) => "HumanObservation",
+        _ => "Occurrence",
+    }
+}
+
+/// A date as ISO 8601, or empty when it is not in a format the site uses
+fn iso_date(text: &str) -> String {
+    parse_date(text)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// The `id` column followed by the values of `TERMS` for one record
+fn occurrence_row(record: &SightingRecord) -> Vec<String> {
+    let id = record
+        .sighting_id
+        .map(|id| id.to_string())
+        .unwrap_or_default();
+    let url = record.url.clone().unwrap_or_default();
+    vec![
+        id.clone(),
+        if url.is_empty() { id } else { url.clone() },
+        basis_of_record(record.specimen_type()).to_string(),
+        record.scientific_name.clone(),
+        record.common_name.clone(),
+        iso_date(&record.observation_date),
+        record.observation_date.clone(),
+        record.submitted_by.clone(),
+        record.verified_by.clone(),
+        iso_date(&record.verified_date),
+        record.status.clone(),
+        record.checklist_regions.clone(),
+        record.locality.clone(),
+        record.latitude.map(|x| x.to_string()).unwrap_or_default(),
+        record.longitude.map(|x| x.to_string()).unwrap_or_default(),
+        record.coordinate_precision.clone().unwrap_or_default(),
+        if record.location_obscured {
+            "location obscured".to_string()
+        } else {
+            String::new()
+        },
+        url,
+        record.scraped_at.clone(),
+    ]
+}
+
+/// The archive's `occurrence.txt`: tab-separated with a header row and no quoting, so
+/// tabs and line breaks inside values become spaces
+fn occurrence_txt(records: &[SightingRecord]) -> String {
+    let clean = |value: &str| value.replace(['\t', '\r', '\n'], " ");
+    let mut text = std::iter::once("id")
+        .chain(TERMS.iter().map(|(term, _)| *term))
+        .collect::<Vec<_>>()
+        .join("\t");
+    text.push('\n');
+    for record in records {
+        let row: Vec<String> = occurrence_row(record).iter().map(|v| clean(v)).collect();
+        text.push_str(&row.join("\t"));
+        text.push('\n');
+    }
+    text
+}
+
+/// The archive's `meta.xml`, describing `occurrence.txt` as the Occurrence core
+fn meta_xml() -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<archive xmlns=\"http://rs.tdwg.org/dwc/text/\">\n",
+        "  <core encoding=\"UTF-8\" fieldsTerminatedBy=\"\\t\" linesTerminatedBy=\"\\n\" ",
+        "fieldsEnclosedBy=\"\" ignoreHeaderLines=\"1\" ",
+        "rowType=\"http://rs.tdwg.org/dwc/terms/Occurrence\">\n",
+        "    <files><location>occurrence.txt</location></files>\n",
+        "    <id index=\"0\"/>\n",
+    ));
+    for (index, (term, namespace)) in TERMS.iter().enumerate() {
+        xml.push_str(&format!(
+            "    <field index=\"{}\" term=\"{}{}\"/>\n",
+            index + 1,
+            namespace,
+            term
+        ));
+    }
+    xml.push_str("  </core>\n</archive>\n");
+    xml
+}
+
+/// Write records as a zipped Darwin Core Archive, `occurrence.txt` plus `meta.xml`, for
+/// GBIF and other biodiversity aggregators. Columns with no Darwin Core term are dropped.
+pub fn write_dwc(
+    records: &[SightingRecord],
+    filename: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let tags = records.iter().any(|record| !record.extra.is_empty());
+    info!(
+        "Darwin Core archive leaves out {}{}",
+        UNMAPPED.join(", "),
+        if tags { " and tag columns" } else { "" }
+    );
+    if filename == STDOUT {
+        // Zip needs to seek back, which stdout can't
+        let bytes = dwc_to(records, Cursor::new(Vec::new()))?.into_inner();
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&bytes)?;
+        stdout.flush()?;
+    } else {
+        dwc_to(records, File::create(filename)?)?;
+    }
+    Ok(())
+}
+
+/// Write the archive to `sink`, returning it once the archive is complete
+fn dwc_to<W: Write + Seek>(
+    records: &[SightingRecord],
+    sink: W,
+) -> Result<W, Box<dyn std::error::Error + Send + Sync>> {
+    let mut zip = ZipWriter::new(sink);
+    let options = SimpleFileOptions::default();
+    zip.start_file("occurrence.txt", options)?;
+    zip.write_all(occurrence_txt(records).as_bytes())?;
+    zip.start_file("meta.xml", options)?;
+    zip.write_all(meta_xml().as_bytes())?;
+    Ok(zip.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    #[test]
+    fn test_dwc_archive() {
+        let record = SightingRecord {
+            sighting_id: Some(42),
+            url: Some("https://example.org/sighting_details/42".to_string()),
+            scientific_name: "Danaus plexippus".to_string(),
+            common_name: "Monarch".to_string(),
+            observation_date: "07/04/2024".to_string(),
+            specimen_type: "Photo".to_string(),
+            locality: "Travis\tCounty".to_string(),
+            latitude: Some(30.27),
+            longitude: Some(-97.74),
+            location_obscured: true,
+            ..Default::default()
+        };
+        let archive = dwc_to(&[record], Cursor::new(Vec::new())).unwrap();
+        let mut archive = ZipArchive::new(archive).unwrap();
+
+        let mut occurrences = String::new();
+        archive
+            .by_name("occurrence.txt")
+            .unwrap()
+            .read_to_string(&mut occurrences)
+            .unwrap();
+        let lines: Vec<&str> = occurrences.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let header: Vec<&str> = lines[0].split('\t').collect();
+        let row: Vec<&str> = lines[1].split('\t').collect();
+        assert_eq!(header.len(), row.len());
+        let value = |term: &str| row[header.iter().position(|t| *t == term).unwrap()];
+        assert_eq!(value("id"), "42");
+        assert_eq!(
+            value("occurrenceID"),
+            "https://example.org/sighting_details/42"
+        );
+        assert_eq!(value("basisOfRecord"), "HumanObservation");
+        assert_eq!(value("eventDate"), "2024-07-04");
+        assert_eq!(value("verbatimEventDate"), "07/04/2024");
+        assert_eq!(value("locality"), "Travis County");
+        assert_eq!(value("decimalLatitude"), "30.27");
+        assert_eq!(value("decimalLongitude"), "-97.74");
+        assert_eq!(value("informationWithheld"), "location obscured");
+
+        let mut meta = String::new();
+        archive
+            .by_name("meta.xml")
+            .unwrap()
+            .read_to_string(&mut meta)
+            .unwrap();
+        assert!(
+            meta.contains(
+                "<field index=\"3\" term=\"http://rs.tdwg.org/dwc/terms/scientificName\"/>"
+            )
+        );
+        assert!(meta.contains("<field index=\"18\" term=\"http://purl.org/dc/terms/modified\"/>"));
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why a sighting was added to the missing list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureReason {
+    /// An error status that is not retried, or still came back once retries ran out
+    Http(u16),
+    /// The site was still answering 429 once retries ran out
+    RateLimited,
+    /// The page had no sighting row
+    NoData,
+    /// Every row on the page was empty
+    EmptyRecord,
+    /// The record was blank once trimmed, so it was dropped before saving
+    Blank,
+    /// The response declared a content type other than HTML
+    NonHtml,
+    /// The body ran past `max_body_bytes`
+    Oversize,
+    /// The connection dropped while the body was read, on every attempt
+    BodyRead,
+    /// Redirects looped or ran past `max_redirects`
+    TooManyRedirects,
+    /// The request could not be sent, on every attempt
+    RequestFailed,
+    /// Every attempt ended in a failure that was retried
+    RetriesExhausted,
+    /// The sighting ran past the per-ID timeout
+    Timeout(Duration),
+    /// The site was still serving its maintenance page after `max_maintenance_pauses`
+    Maintenance,
+    /// The run was cancelled while the sighting waited before a request
+    Cancelled,
+}
+
+impl FailureReason {
+    /// Whether the page doesn't exist, which `--fail-fast` doesn't stop for
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, FailureReason::Http(404 | 410))
+    }
+}
+
+// As written to the log and the lifecycle events
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::Http(status) => write!(f, "http {}", status),
+            FailureReason::RateLimited => f.write_str("rate limited"),
+            FailureReason::NoData => f.write_str("no data found"),
+            FailureReason::EmptyRecord => f.write_str("empty record"),
+            FailureReason::Blank => f.write_str("empty"),
+            FailureReason::NonHtml => f.write_str("non-html"),
+            FailureReason::Oversize => f.write_str("oversize"),
+            FailureReason::BodyRead => f.write_str("failed to read body"),
+            FailureReason::TooManyRedirects => f.write_str("too many redirects"),
+            FailureReason::RequestFailed => f.write_str("request failed"),
+            FailureReason::RetriesExhausted => f.write_str("retries exhausted"),
+            FailureReason::Timeout(limit) => write!(f, "timeout after {}ms", limit.as_millis()),
+            FailureReason::Maintenance => f.write_str("maintenance"),
+            FailureReason::Cancelled => f.write_str("cancelled"),
+        }
+    }
+}
+
+/// Why a scraper operation failed, so a caller can tell a transient network error from a
+/// sighting that will never parse and choose its own retry policy
+#[derive(Debug, Error)]
+pub enum ScraperError {
+    /// A request could not be sent or came back with an error status
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A sitemap that isn't well-formed XML
+    #[error("sitemap error: {0}")]
+    Sitemap(#[from] quick_xml::Error),
+    /// The site has no page for the sighting
+    #[error("sighting {sighting_id} does not exist")]
+    NotFound { sighting_id: u64 },
+    /// The page was fetched, but no sighting could be read from it
+    #[error("no sighting could be parsed from the page for sighting {sighting_id}")]
+    Parse { sighting_id: u64 },
+    /// The site was still answering 429 once retries ran out
+    #[error("sighting {sighting_id} was still rate limited after its retries")]
+    RateLimited { sighting_id: u64 },
+    /// Any other failure of a single sighting
+    #[error("sighting {sighting_id} failed ({reason})")]
+    Failed {
+        sighting_id: u64,
+        reason: FailureReason,
+    },
+    /// The run was cancelled before the sighting finished
+    #[error("sighting {sighting_id} was cancelled")]
+    Cancelled { sighting_id: u64 },
+    /// None of the probed IDs exist
+    #[error("no sightings found in {first}-{last}")]
+    NoSightings { first: u64, last: u64 },
+    /// Writing records in a format other than CSV or JSON failed
+    #[error("{0}")]
+    Output(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl ScraperError {
+    /// The error for a sighting that went to the missing list for `reason`
+    pub fn from_failure(sighting_id: u64, reason: FailureReason) -> Self {
+        match reason {
+            FailureReason::Http(404 | 410) => ScraperError::NotFound { sighting_id },
+            FailureReason::NoData | FailureReason::EmptyRecord | FailureReason::Blank => {
+                ScraperError::Parse { sighting_id }
+            }
+            FailureReason::RateLimited => ScraperError::RateLimited { sighting_id },
+            FailureReason::Cancelled => ScraperError::Cancelled { sighting_id },
+            reason => ScraperError::Failed {
+                sighting_id,
+                reason,
+            },
+        }
+    }
+}
+
+// The Darwin Core and Parquet writers return boxed errors of whatever their format's
+// library raises
+impl From<Box<dyn std::error::Error + Send + Sync>> for ScraperError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        ScraperError::Output(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_failure() {
+        assert!(matches!(
+            ScraperError::from_failure(3, FailureReason::Http(410)),
+            ScraperError::NotFound { sighting_id: 3 }
+        ));
+        assert!(matches!(
+            ScraperError::from_failure(4, FailureReason::NoData),
+            ScraperError::Parse { sighting_id: 4 }
+        ));
+        assert!(matches!(
+            ScraperError::from_failure(4, FailureReason::Blank),
+            ScraperError::Parse { sighting_id: 4 }
+        ));
+        assert!(matches!(
+            ScraperError::from_failure(5, FailureReason::RateLimited),
+            ScraperError::RateLimited { sighting_id: 5 }
+        ));
+        assert_eq!(
+            ScraperError::from_failure(6, FailureReason::Http(500)).to_string(),
+            "sighting 6 failed (http 500)"
+        );
+        assert_eq!(
+            FailureReason::Timeout(Duration::from_secs(2)).to_string(),
+            "timeout after 2000ms"
+        );
+    }
+
+    #[test]
+    fn test_scraper_error_is_send_and_sync() {
+        // So a scrape's error can cross a tokio::spawn boundary
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<ScraperError>();
+    }
+}
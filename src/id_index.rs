@@ -0,0 +1,173 @@
+use crate::output::read_csv;
+use log::info;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Bytes per ID in an index file
+const ID_BYTES: usize = 8;
+
+/// Sighting IDs already scraped, kept in a sidecar file as sorted little-endian u64s.
+/// Loading it is a single read with no parsing, and lookups are binary searches, so a
+/// multi-million-ID index is ready far sooner than the CSV it was built from.
+#[derive(Debug, Default)]
+pub struct IdIndex {
+    ids: Vec<u64>,
+}
+
+impl IdIndex {
+    pub fn from_ids(ids: impl IntoIterator<Item = u64>) -> Self {
+        let mut ids: Vec<u64> = ids.into_iter().collect();
+        ids.sort_unstable();
+        ids.dedup();
+        Self { ids }
+    }
+
+    /// Decode an index file's contents
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.len().is_multiple_of(ID_BYTES) {
+            return Err(format!(
+                "length {} is not a multiple of {} bytes",
+                bytes.len(),
+                ID_BYTES
+            ));
+        }
+        let ids: Vec<u64> = bytes
+            .chunks_exact(ID_BYTES)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        if !ids.is_sorted() {
+            // Written by something else; sort rather than give wrong lookups
+            return Ok(Self::from_ids(ids));
+        }
+        Ok(Self { ids })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.ids.iter().flat_map(|id| id.to_le_bytes()).collect()
+    }
+
+    /// Read the index at `filename`, or `None` if there is no such file
+    pub fn load(filename: &str) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        match std::fs::read(filename) {
+            Ok(bytes) => Ok(Some(
+                Self::from_bytes(&bytes).map_err(|e| format!("{}: {}", filename, e))?,
+            )),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Load the index at `filename`, building it from the IDs in `csv` when the index does
+    /// not exist yet or `rebuild` is set. Without either file the index starts empty.
+    pub fn load_or_build(
+        filename: &str,
+        csv: &str,
+        rebuild: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if !rebuild && let Some(index) = Self::load(filename)? {
+            info!("Loaded {} IDs from index {}", index.ids.len(), filename);
+            return Ok(index);
+        }
+        if !Path::new(csv).exists() {
+            return Ok(Self::default());
+        }
+        let index = Self::from_ids(read_csv(csv)?.iter().filter_map(|r| r.sighting_id));
+        index.save(filename)?;
+        info!(
+            "Built index {} with {} IDs from {}",
+            filename,
+            index.ids.len(),
+            csv
+        );
+        Ok(index)
+    }
+
+    /// Write the index, replacing `filename` only once the new contents are complete
+    pub fn save(&self, filename: &str) -> std::io::Result<()> {
+        let partial = format!("{}.partial", filename);
+        std::fs::write(&partial, self.to_bytes())?;
+        std::fs::rename(&partial, filename)
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.ids.binary_search(&id).is_ok()
+    }
+
+    /// Merge in newly scraped IDs, keeping the index sorted
+    pub fn extend(&mut self, ids: impl IntoIterator<Item = u64>) {
+        let mut added: Vec<u64> = ids.into_iter().filter(|&id| !self.contains(id)).collect();
+        if added.is_empty() {
+            return;
+        }
+        added.sort_unstable();
+        added.dedup();
+        let mut merged = Vec::with_capacity(self.ids.len() + added.len());
+        let (mut old, mut new) = (self.ids.iter().peekable(), added.into_iter().peekable());
+        while let (Some(&&a), Some(&b)) = (old.peek(), new.peek()) {
+            if a < b {
+                merged.push(a);
+                old.next();
+            } else {
+                merged.push(b);
+                new.next();
+            }
+        }
+        merged.extend(old);
+        merged.extend(new);
+        self.ids = merged;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::write_csv;
+    use crate::record::SightingRecord;
+
+    #[test]
+    fn test_id_index_round_trip_and_merge() {
+        let mut index = IdIndex::from_ids([9, 3, 3, 5]);
+        index.extend([4, 9, 12, 1]);
+        assert_eq!(index.ids, vec![1, 3, 4, 5, 9, 12]);
+        assert!(index.contains(4) && !index.contains(6));
+
+        let decoded = IdIndex::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(decoded.ids, index.ids);
+        assert!(IdIndex::from_bytes(&[0; 7]).is_err());
+        // Unsorted contents are sorted on load
+        let unsorted: Vec<u8> = [7u64, 2].iter().flat_map(|id| id.to_le_bytes()).collect();
+        assert_eq!(IdIndex::from_bytes(&unsorted).unwrap().ids, vec![2, 7]);
+    }
+
+    #[test]
+    fn test_id_index_builds_from_csv_when_absent() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("sightings.csv");
+        let csv = csv.to_str().unwrap();
+        let index_file = dir.path().join("sightings.idx");
+        let index_file = index_file.to_str().unwrap();
+        let records: Vec<SightingRecord> = [8, 2]
+            .into_iter()
+            .map(|id| SightingRecord {
+                sighting_id: Some(id),
+                ..Default::default()
+            })
+            .collect();
+        write_csv(&records, csv, false, true, "").unwrap();
+
+        assert!(IdIndex::load(index_file).unwrap().is_none());
+        let index = IdIndex::load_or_build(index_file, csv, false).unwrap();
+        assert_eq!(index.ids, vec![2, 8]);
+
+        // An existing index is used as is unless a rebuild is asked for
+        IdIndex::from_ids([5]).save(index_file).unwrap();
+        assert_eq!(
+            IdIndex::load_or_build(index_file, csv, false).unwrap().ids,
+            vec![5]
+        );
+        assert_eq!(
+            IdIndex::load_or_build(index_file, csv, true).unwrap().ids,
+            vec![2, 8]
+        );
+    }
+}
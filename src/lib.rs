@@ -0,0 +1,7 @@
+//! Parsing of sighting pages into records, apart from the scraper that fetches them, so
+//! saved pages can be parsed again offline and the parser benchmarked on its own.
+
+pub mod page_parser;
+pub mod record;
+pub mod schema;
+pub mod selectors;
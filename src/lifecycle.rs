@@ -0,0 +1,127 @@
+use chrono::{SecondsFormat, Utc};
+use log::warn;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// A step in a run's lifecycle, written by `--events` as one JSON object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum LifecycleEvent<'a> {
+    RunStart {
+        ids: usize,
+        concurrent: usize,
+    },
+    IdSuccess {
+        sighting_id: u64,
+        attempts: u32,
+    },
+    IdFailure {
+        sighting_id: u64,
+        reason: &'a str,
+    },
+    RunEnd {
+        records: usize,
+        missing: usize,
+        elapsed_secs: f64,
+        cancelled: bool,
+    },
+}
+
+/// An event with the time it was emitted
+#[derive(Serialize)]
+struct Stamped<'a> {
+    timestamp: String,
+    #[serde(flatten)]
+    event: &'a LifecycleEvent<'a>,
+}
+
+/// Writes lifecycle events as JSON lines, kept apart from the record output
+pub struct EventLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl EventLog {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Append to `filename`, or write to stderr without one
+    pub fn open(filename: Option<&str>) -> std::io::Result<Self> {
+        match filename {
+            Some(filename) => Ok(Self::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(filename)?,
+            )),
+            None => Ok(Self::new(std::io::stderr())),
+        }
+    }
+
+    /// Write one event line, flushed so a consumer sees it as it happens
+    pub fn emit(&self, event: &LifecycleEvent) {
+        let stamped = Stamped {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            event,
+        };
+        let mut writer = self.writer.lock().unwrap();
+        let written = serde_json::to_writer(&mut *writer, &stamped)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            warn!("Could not write lifecycle event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A writer whose output stays readable after it is handed to the log
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_events_are_stamped_json_lines() {
+        let buffer = SharedBuffer::default();
+        let log = EventLog::new(buffer.clone());
+        log.emit(&LifecycleEvent::RunStart {
+            ids: 2,
+            concurrent: 1,
+        });
+        log.emit(&LifecycleEvent::IdFailure {
+            sighting_id: 7,
+            reason: "http 404",
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "run-start");
+        assert_eq!(lines[0]["ids"], 2);
+        assert_eq!(lines[1]["event"], "id-failure");
+        assert_eq!(lines[1]["sighting_id"], 7);
+        assert_eq!(lines[1]["reason"], "http 404");
+        assert!(lines[1]["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+}
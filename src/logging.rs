@@ -0,0 +1,74 @@
+use std::fmt;
+use std::future::Future;
+use std::io::Write;
+
+/// Identifies one sighting's task in the log: its place in the run's dispatch order and
+/// its ID. The same IDs in the same order get the same tags, run after run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskTag {
+    pub index: usize,
+    pub sighting_id: u64,
+}
+
+impl fmt::Display for TaskTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}:{}", self.index, self.sighting_id)
+    }
+}
+
+tokio::task_local! {
+    static TASK_TAG: TaskTag;
+}
+
+/// Run `future` with every line it logs, across all its retries, tagged with `tag`
+pub fn with_task_tag<F: Future>(tag: TaskTag, future: F) -> impl Future<Output = F::Output> {
+    TASK_TAG.scope(tag, future)
+}
+
+/// The tag of the task being run, outside any task `None`
+pub fn task_tag() -> Option<TaskTag> {
+    TASK_TAG.try_with(|tag| *tag).ok()
+}
+
+/// Set up logging from `RUST_LOG` as `env_logger` does, putting the task tag, when there
+/// is one, in front of each message so one sighting's lines can be grepped out
+pub fn init() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            let style = buf.default_level_style(record.level());
+            write!(
+                buf,
+                "[{} {style}{:<5}{style:#} {}] ",
+                buf.timestamp(),
+                record.level(),
+                record.target()
+            )?;
+            if let Some(tag) = task_tag() {
+                write!(buf, "[{}] ", tag)?;
+            }
+            writeln!(buf, "{}", record.args())
+        })
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_task_tag_follows_its_future() {
+        assert_eq!(task_tag(), None);
+        let tag = TaskTag {
+            index: 3,
+            sighting_id: 1000,
+        };
+        let seen = with_task_tag(tag, async {
+            tokio::task::yield_now().await;
+            task_tag()
+        })
+        .await;
+        assert_eq!(seen, Some(tag));
+        assert_eq!(tag.to_string(), "#3:1000");
+        assert_eq!(task_tag(), None);
+    }
+}
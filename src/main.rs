@@ -1,15 +1,84 @@
+mod bloom;
+mod clock;
+#[cfg(feature = "parquet")]
+mod columnar;
+mod concurrency;
+mod control;
+mod cookies;
+mod dashboard;
+mod diff;
+mod dwc;
+mod error;
+mod id_index;
+mod lifecycle;
+mod logging;
+mod merge;
+mod metrics;
+mod names;
+mod object_store;
+mod output;
 mod parse;
-mod record;
+mod pivot;
+mod reorder;
+mod replay;
+mod retry_plan;
 mod scraper;
+mod sitemap;
+mod split;
+#[cfg(feature = "sqlite")]
+mod store;
+mod summary;
 mod util;
+mod validate;
+mod warc;
 
-use crate::parse::Args;
+// The parsing modules live in the library, where the benchmarks reach them too
+use crate::control::{ControlEvent, EventSender, serve_control_socket};
+use crate::cookies::read_cookie_file;
+use crate::dashboard::serve_dashboard;
+use crate::diff::{diff_records, print_diff_report};
+use crate::id_index::IdIndex;
+use crate::lifecycle::{EventLog, LifecycleEvent};
+#[cfg(feature = "sqlite")]
+use crate::merge::{ConflictPolicy, dedup_records};
+use crate::merge::{
+    apply_verification, merge_csv_files, replace_sightings, stale_sighting_ids,
+    unverified_sighting_ids,
+};
+use crate::metrics::{print_field_fill_rates, print_parse_warnings, print_timing_summary};
+use crate::names::NameMap;
+use crate::object_store::{check_output_supported, is_s3_url, staging_file, upload_file};
+use crate::output::{
+    OutputFormat, RecordWriter, STDOUT, create_output, has_format_extension, output_filenames,
+    read_csv, read_csv_lenient, read_id_file, read_priorities, read_records, stream_records,
+    write_csv, write_ids,
+};
+use crate::parse::{Args, Command};
+use crate::pivot::Pivot;
 use crate::record::SightingRecord;
-use crate::scraper::ButterflyMothScraper;
-use crate::util::print_hms;
-use clap::Parser;
-use std::collections::HashMap;
-use std::time::Instant;
+use crate::reorder::OrderedOutput;
+use crate::replay::{read_captures, replay};
+use crate::retry_plan::RetryPlan;
+use crate::schema::{record_schema, validate_csv_header};
+use crate::scraper::{ButterflyMothScraper, DensityCheck, Pagination, sighting_id_range};
+use crate::selectors::FieldSelectors;
+use crate::split::{split_filename, split_records};
+#[cfg(feature = "sqlite")]
+use crate::store::{RunInfo, read_sqlite, write_sqlite, write_sqlite_run};
+use crate::summary::{Summary, print_summary};
+use crate::util::{coalesce_ranges, format_range, print_hms, status, status_to_stderr};
+use crate::validate::validate_csv;
+use crate::warc::WarcWriter;
+use chrono::{TimeDelta, Utc};
+use clap::{CommandFactory, FromArgMatches};
+use sachem::{page_parser, record, schema, selectors};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 
 /// Utility functions
 pub fn get_failed_ids(original_ids: &[u64], scraped_records: &[SightingRecord]) -> Vec<u64> {
@@ -25,76 +94,891 @@ pub fn get_failed_ids(original_ids: &[u64], scraped_records: &[SightingRecord])
         .collect()
 }
 
-pub fn print_summary(records: &[SightingRecord]) {
-    if records.is_empty() {
-        println!("No records to summarize");
-        return;
-    }
-
-    println!("\nSummary:");
-    println!("Total sightings: {}", records.len());
-
-    let unique_species: std::collections::HashSet<&String> = records
+/// The first of `outputs` that already holds data. A run that saves over it without
+/// --resume loses rows its --id-index counts as scraped, so they would never be fetched again.
+fn saved_output(outputs: &[(OutputFormat, String)]) -> Option<&str> {
+    outputs
         .iter()
-        .map(|r| &r.scientific_name)
-        .filter(|name| !name.is_empty())
-        .collect();
-    println!("Unique species: {}", unique_species.len());
+        .map(|(_, filename)| filename.as_str())
+        .filter(|filename| *filename != STDOUT && !is_s3_url(filename))
+        .find(|filename| std::fs::metadata(filename).is_ok_and(|meta| meta.len() > 0))
+}
 
-    // Get date range
-    let dates: Vec<&String> = records
-        .iter()
-        .map(|r| &r.observation_date)
-        .filter(|date| !date.is_empty())
-        .collect();
+/// Sort records by sighting ID, with records that have none at the end in their current
+/// order
+fn sort_by_sighting_id(records: &mut [SightingRecord]) {
+    records.sort_by_key(|record| (record.sighting_id.is_none(), record.sighting_id));
+}
 
-    if !dates.is_empty() {
-        let min_date = dates.iter().min().unwrap();
-        let max_date = dates.iter().max().unwrap();
-        println!("Date range: {} to {}", min_date, max_date);
+/// Save records to `filename` in `format`. An `s3://` output is written in full locally,
+/// then uploaded, so a failed run leaves no partial object.
+async fn save_output(
+    scraper: &ButterflyMothScraper,
+    records: &[SightingRecord],
+    filename: &str,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if is_s3_url(filename) {
+        // Removed when dropped, whether or not the upload succeeds
+        let staged = staging_file(filename)?;
+        scraper.save_to_format(records, &staged.path().to_string_lossy(), format)?;
+        upload_file(staged.path(), filename).await?;
+        status!("Uploaded to {}", filename);
+    } else {
+        scraper.save_to_format(records, filename, format)?;
     }
+    Ok(())
+}
 
-    // Top regions
-    let mut region_counts: HashMap<&String, usize> = HashMap::new();
-    for record in records {
-        if !record.checklist_regions.is_empty() {
-            *region_counts.entry(&record.checklist_regions).or_insert(0) += 1;
+/// Run a subcommand that works on local files without scraping
+fn run_command(command: &Command) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Merge {
+            inputs,
+            output,
+            dedupe_by,
+            on_conflict,
+        } => {
+            let count = merge_csv_files(inputs, output, dedupe_by, *on_conflict)?;
+            println!("Merged {} unique sightings into {}", count, output);
+        }
+        Command::Validate { input } => {
+            let (rows, issues) = validate_csv(input)?;
+            for issue in &issues {
+                match issue.sighting_id {
+                    Some(sighting_id) => println!(
+                        "Row {} (sighting {}): {}",
+                        issue.row, sighting_id, issue.problem
+                    ),
+                    None => println!("Row {}: {}", issue.row, issue.problem),
+                }
+            }
+            if !issues.is_empty() {
+                return Err(format!(
+                    "{} issues found in {} rows of {}",
+                    issues.len(),
+                    rows,
+                    input
+                )
+                .into());
+            }
+            println!("{} rows of {} passed validation", rows, input);
+        }
+        #[cfg(feature = "sqlite")]
+        Command::Import { inputs, db } => {
+            let mut records = Vec::new();
+            for input in inputs {
+                records.extend(read_csv(input)?);
+            }
+            let read = records.len();
+            let records = dedup_records(
+                records,
+                &["sighting_id".to_string()],
+                ConflictPolicy::Replace,
+            );
+            write_sqlite(&records, db, ConflictPolicy::Replace)?;
+            println!(
+                "Imported {} unique sightings ({} rows read) into {}",
+                records.len(),
+                read,
+                db
+            );
+        }
+        #[cfg(feature = "sqlite")]
+        Command::Export { db, output } => {
+            let records = read_sqlite(db)?;
+            if output == STDOUT {
+                status_to_stderr();
+            }
+            write_csv(&records, output, false, true, "")?;
+            status!("Exported {} sightings to {}", records.len(), output);
+        }
+        Command::Diff { old, new, json } => {
+            let diff = diff_records(&read_records(old)?, &read_records(new)?);
+            if let Some(filename) = json {
+                if filename == STDOUT {
+                    status_to_stderr();
+                }
+                let mut writer = create_output(filename)?;
+                serde_json::to_writer_pretty(&mut writer, &diff)?;
+                writeln!(writer)?;
+                writer.flush()?;
+            }
+            print_diff_report(&diff);
+        }
+        Command::Summarize {
+            input,
+            json,
+            split_regions,
+        } => {
+            let mut summary = Summary::new(*split_regions);
+            for record in stream_records(input)? {
+                summary.add(&record?);
+            }
+            if let Some(filename) = json {
+                if filename == STDOUT {
+                    status_to_stderr();
+                }
+                let mut writer = create_output(filename)?;
+                serde_json::to_writer_pretty(&mut writer, &summary.report())?;
+                writeln!(writer)?;
+                writer.flush()?;
+            }
+            summary.print();
+        }
+        Command::Pivot {
+            input,
+            rows,
+            cols,
+            max_cols,
+            output,
+        } => {
+            if rows == cols {
+                return Err("--rows and --cols must group by different fields".into());
+            }
+            let mut pivot = Pivot::new(*rows, *cols);
+            for record in stream_records(input)? {
+                pivot.add(&record?);
+            }
+            pivot.write_csv(create_output(output)?, *max_cols as usize)?;
+            if output != STDOUT {
+                status!("Pivot table saved to {}", output);
+            }
+        }
+        Command::Replay {
+            inputs,
+            output,
+            parser_version,
+            selectors,
+            multi_value_sep,
+            name_map,
+            all_rows,
+        } => {
+            if output == STDOUT {
+                status_to_stderr();
+            }
+            let selectors = match selectors {
+                Some(filename) => FieldSelectors::load(filename)?,
+                None => FieldSelectors::default(),
+            };
+            let parser = parser_version.parser(multi_value_sep, selectors);
+            let name_map = name_map.as_deref().map(NameMap::load).transpose()?;
+            let mut captures = Vec::new();
+            for input in inputs {
+                captures.extend(read_captures(input)?);
+            }
+            let count = captures.len();
+            let replayed = replay(captures, parser.as_ref(), name_map.as_ref(), *all_rows);
+            write_csv(&replayed.records, output, false, true, "")?;
+            status!(
+                "Replayed {} records from {} captures into {}",
+                replayed.records.len(),
+                count,
+                output
+            );
+            if !replayed.unparsed.is_empty() {
+                let ranges: Vec<String> = coalesce_ranges(&replayed.unparsed)
+                    .iter()
+                    .map(format_range)
+                    .collect();
+                status!(
+                    "{} captures had no sighting: {}",
+                    replayed.unparsed.len(),
+                    ranges.join(", ")
+                );
+            }
+        }
+        Command::FindMax { .. } => unreachable!("find-max probes the site and runs in main"),
+        Command::Schema { validate_csv: None } => {
+            println!("{}", serde_json::to_string_pretty(&record_schema())?);
+        }
+        Command::Schema {
+            validate_csv: Some(filename),
+        } => {
+            let diff = validate_csv_header(filename)?;
+            for column in &diff.added {
+                println!("Added column: {}", column);
+            }
+            for column in &diff.removed {
+                println!("Removed column: {}", column);
+            }
+            if !diff.is_empty() {
+                return Err(format!("{} does not match the current schema", filename).into());
+            }
+            println!("{} matches the current schema", filename);
         }
     }
+    Ok(())
+}
 
-    let mut sorted_regions: Vec<_> = region_counts.into_iter().collect();
-    sorted_regions.sort_by(|a, b| b.1.cmp(&a.1));
+/// Cancel `cancel` on Ctrl+C or SIGTERM, so either signal stops new requests, cuts short
+/// any backoff, and lets in-flight requests finish and partial results be saved
+fn cancel_on_shutdown(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        if let Some(signal) = shutdown_signal().await {
+            eprintln!("\n{}, finishing in-flight requests...", signal);
+            cancel.cancel();
+        }
+    });
+}
+
+/// Wait for Ctrl+C or, on Unix, SIGTERM, as sent by orchestrators before killing a
+/// process. Returns what was received, or `None` if no handler could be installed.
+async fn shutdown_signal() -> Option<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+            return tokio::signal::ctrl_c().await.ok().map(|_| "Interrupted");
+        };
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.ok().map(|_| "Interrupted"),
+            _ = terminate.recv() => Some("Terminated"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.ok().map(|_| "Interrupted")
+    }
+}
 
-    println!("Top regions:");
-    for (region, count) in sorted_regions.iter().take(3) {
-        println!("  {}: {}", region, count);
+/// Print missing IDs coalesced into ranges, or write them to `filename` one range per line
+fn report_missing_ranges(
+    missing: &[u64],
+    filename: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ranges: Vec<String> = coalesce_ranges(missing).iter().map(format_range).collect();
+    match filename {
+        Some(filename) => {
+            let mut contents = ranges.join("\n");
+            if !contents.is_empty() {
+                contents.push('\n');
+            }
+            std::fs::write(filename, contents)?;
+            println!("Wrote {} missing ranges to {}", ranges.len(), filename);
+        }
+        None => {
+            println!(
+                "{} missing sightings in {} ranges:",
+                missing.len(),
+                ranges.len()
+            );
+            println!("{}", ranges.join(", "));
+        }
     }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::try_parse()?;
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    args.apply_preset(&matches);
     // Initialize logger
-    env_logger::init();
+    logging::init();
 
-    let scraper = ButterflyMothScraper::new()
+    if let Some(Command::FindMax {
+        low,
+        high,
+        gap,
+        base_url,
+        headers,
+        delay,
+        retries,
+    }) = &args.command
+    {
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(base_url)
+            .with_headers(headers.iter().cloned().collect())
+            .with_delay(*delay)
+            .with_max_retries(*retries);
+        let max = scraper.find_max_sighting(*low, *high, *gap).await?;
+        eprintln!("Highest sighting ID: {}", max);
+        println!("{}", max);
+        return Ok(());
+    }
+    if let Some(command) = &args.command {
+        return run_command(command);
+    }
+    if args.output == STDOUT {
+        if output_filenames(&args.output, &args.format).len() > 1 {
+            return Err("--output - writes a single format; pass one --format".into());
+        }
+        status_to_stderr();
+    }
+    if args.summary_json.as_deref() == Some(STDOUT) {
+        if args.output == STDOUT {
+            return Err("--output - and --summary-json - can't both write to stdout".into());
+        }
+        status_to_stderr();
+    }
+    if let [(format, filename)] = output_filenames(&args.output, &args.format).as_slice()
+        && filename != STDOUT
+        && !has_format_extension(filename, *format)
+    {
+        // Written as asked, but a loader going by the extension would misread it
+        status!(
+            "Warning: writing {} output to {}, which doesn't end in .{}",
+            format.name(),
+            filename,
+            format.extension()
+        );
+    }
+    if args.split_by.is_some() && args.output == STDOUT {
+        return Err("--split-by writes one file per taxon; give --output a file name".into());
+    }
+    check_output_supported(&args.output)?;
+    if is_s3_url(&args.output) && (args.rescrape_older_than.is_some() || args.refresh_status) {
+        return Err("--rescrape-older-than and --refresh-status read a local --output".into());
+    }
+    if is_s3_url(&args.output) && args.resume {
+        return Err("--resume reads a local --output".into());
+    }
+    let selectors = match &args.selectors {
+        Some(filename) => FieldSelectors::load(filename)?,
+        None => FieldSelectors::default(),
+    };
+    let mut scraper = ButterflyMothScraper::new()
+        .with_base_url(&args.base_url)
+        .with_parser(args.parser_version.parser(&args.multi_value_sep, selectors))
+        .with_headers(args.header_map())
+        .with_request_id(args.send_request_id)
+        .with_max_redirects(args.max_redirects)
+        .with_max_body_bytes(args.max_body_bytes)
+        .with_maintenance_cooldown(args.maintenance_cooldown)
+        .with_max_maintenance_pauses(args.max_maintenance_pauses)
+        .with_max_retry_after(args.max_retry_after)
+        .with_cookies(&args.cookies)
         .with_delay(args.delay) // 500ms base delay
         .with_max_retries(args.retries)
-        .with_missing_sightings_file(&args.missing);
+        .with_retry_statuses(&args.retry_statuses)
+        .with_include_empty(args.include_empty)
+        .with_all_rows(args.all_rows)
+        .with_min_fields(args.min_fields)
+        .with_specimen_types(&args.specimen_type)
+        .with_excel_csv(args.excel_csv)
+        .with_flush_every(args.flush_every)
+        .with_csv_headers(!args.no_headers)
+        .with_compact_csv(args.compact_csv)
+        .with_null_value(&args.null_value)
+        .with_approx_dedup(args.approx_dedup)
+        .with_fail_fast(args.fail_fast)
+        .with_trim_whitespace_columns(args.trim_whitespace_columns)
+        .with_content_hash(args.with_hash)
+        .with_missing_sightings_file(&args.missing_file())
+        .with_dead_letter_file(&args.dead_letter_file());
+    if let Some(filename) = &args.priorities {
+        scraper = scraper.with_priorities(read_priorities(filename)?);
+    }
+    if let Some(filename) = &args.name_map {
+        scraper = scraper.with_name_map(NameMap::load(filename)?);
+    }
+    if let Some(filename) = &args.cookie_file {
+        scraper = scraper.with_cookie_entries(&read_cookie_file(filename)?);
+    }
+    if let Some(total) = args.total_retry_budget {
+        scraper = scraper.with_retry_budget(total);
+    }
+    if let Some(template) = &args.progress_template {
+        scraper = scraper.with_progress_template(template);
+    }
+    if let Some(min_density) = args.min_density {
+        scraper = scraper.with_density_check(DensityCheck {
+            window: args.density_window,
+            min_density,
+            abort: args.density_abort,
+        });
+    }
+    if args.follow_pagination {
+        scraper = scraper.with_pagination(Pagination {
+            max_pages: args.max_pages as usize,
+            records_per_page: args.records_per_page.map(|rows| rows as usize),
+        });
+    }
+    if args.concurrency_auto {
+        scraper = scraper.with_auto_concurrency(args.concurrent);
+    }
+    if let Some(multiplier) = args.soft_limit_multiplier {
+        scraper = scraper.with_soft_rate_limit(multiplier, args.soft_limit_window as usize);
+    }
+    if let Some(size) = args.burst {
+        scraper = scraper.with_bursts(size as usize, Duration::from_millis(args.burst_pause));
+    }
+    if let Some(seconds) = args.per_id_timeout {
+        scraper = scraper.with_per_id_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(dir) = &args.dump_failed_html {
+        scraper = scraper.with_dump_failed_html(dir);
+    }
+    if let Some(filename) = &args.warc {
+        scraper = scraper.with_warc(WarcWriter::create(
+            filename,
+            args.warc_max_size * 1_000_000,
+        )?);
+    }
+    if let Some(filename) = &args.id_index {
+        let outputs = output_filenames(&args.output, &args.format);
+        if !args.resume
+            && let Some(saved) = saved_output(&outputs)
+        {
+            return Err(format!(
+                "--id-index would overwrite the rows saved in {}; add --resume to add to them, \
+                 or pick a new --output",
+                saved
+            )
+            .into());
+        }
+        // The CSV output the index falls back to; a run without one starts it empty
+        let csv = outputs
+            .into_iter()
+            .find(|(format, _)| *format == OutputFormat::Csv)
+            .map(|(_, csv)| csv)
+            .unwrap_or_default();
+        scraper = scraper.with_id_index(IdIndex::load_or_build(
+            filename,
+            &csv,
+            args.rebuild_id_index,
+        )?);
+    }
+
+    if let Some(filename) = &args.report_missing_ranges {
+        return report_missing_ranges(&scraper.get_missing_sightings(), filename.as_deref());
+    }
+
+    let events = EventSender::new();
+    if let Some(path) = &args.control_socket {
+        serve_control_socket(path, &events)?;
+    }
+    if let Some(port) = args.dashboard_port {
+        let addr = serve_dashboard(args.dashboard_bind, port, &events, &scraper)?;
+        status!("Dashboard at http://{}", addr);
+    }
+    if args.control_socket.is_some() || args.dashboard_port.is_some() {
+        scraper = scraper.with_events(events.clone());
+    }
+
+    let event_log = match &args.events {
+        Some(filename) => Some(Arc::new(EventLog::open(filename.as_deref())?)),
+        None => None,
+    };
+    if let Some(event_log) = &event_log {
+        scraper = scraper.with_event_log(Arc::clone(event_log));
+    }
+
+    let cancel = CancellationToken::new();
+    scraper = scraper.with_cancellation(cancel.clone());
+    cancel_on_shutdown(cancel);
 
-    // Example 2: Scrape multiple specific sightings
-    println!("\nScraping multiple sightings...");
     let start = Instant::now();
-    let records = scraper
-        .scrape_sighting_range(args.min, args.max, args.concurrent)
-        .await;
+    #[cfg(feature = "sqlite")]
+    let started_at = Utc::now();
+    // Records already saved, which --rescrape-older-than refreshes in place
+    let mut existing = Vec::new();
+    // First and last ID when the IDs are a range, in the order they are scraped
+    let mut range = None;
+    // IDs come from the missing file, the saved records, the sitemap or the ID file when
+    // given, otherwise from the range
+    let sighting_ids = if args.retry_missing {
+        scraper.get_missing_sightings()
+    } else if args.rescrape_older_than.is_some() || args.refresh_status {
+        let (_, filename) = output_filenames(&args.output, &args.format)
+            .into_iter()
+            .find(|(format, _)| *format == OutputFormat::Csv)
+            .ok_or("--rescrape-older-than and --refresh-status read the CSV output; add csv to --format")?;
+        existing = read_csv(&filename)?;
+        match args.rescrape_older_than {
+            Some(age) => stale_sighting_ids(&existing, Utc::now() - TimeDelta::from_std(age)?),
+            None => unverified_sighting_ids(&existing),
+        }
+    } else if let Some(url) = &args.sitemap {
+        scraper.fetch_sitemap_ids(url).await?
+    } else if let Some(filename) = &args.ids {
+        let entries = read_id_file(filename)?;
+        let sighting_ids = entries.iter().map(|(id, _)| *id).collect();
+        scraper = scraper.with_id_tags(
+            entries
+                .into_iter()
+                .filter(|(_, tags)| !tags.is_empty())
+                .collect(),
+        );
+        sighting_ids
+    } else {
+        // Required without another source of IDs or a subcommand, which returned above
+        let max = args.max.expect("--max is required");
+        let (first, last) = if args.descending {
+            (max, args.min)
+        } else {
+            (args.min, max)
+        };
+        range = Some((first, last));
+        sighting_id_range(first, last)
+    };
 
+    // The CSV output a run stopped partway through, to skip what it saved and add to
+    let resumed_csv = if args.resume {
+        let (_, filename) = output_filenames(&args.output, &args.format)
+            .into_iter()
+            .find(|(format, _)| *format == OutputFormat::Csv)
+            .ok_or("--resume reads the CSV output; add csv to --format")?;
+        scraper = scraper.with_resume_from_output(&filename);
+        if scraper.resumed_count() > 0 {
+            status!(
+                "Resuming: skipping {} sightings already in {}",
+                scraper.resumed_count(),
+                filename
+            );
+        }
+        Some(filename)
+    } else {
+        None
+    };
+
+    if let Some(filename) = &args.head_check {
+        status!("\nProbing sightings...");
+        let existing = scraper
+            .find_existing_sightings(&sighting_ids, args.concurrent)
+            .await;
+        print_hms(&start);
+        write_ids(&existing, filename)?;
+        status!(
+            "Wrote {} existing sighting IDs to {}",
+            existing.len(),
+            filename
+        );
+        return Ok(());
+    }
+
+    // The file records went straight into, if it can be read back for --summary-json
+    let mut streamed_file = None;
+    if let Some(capacity) = args.reorder_buffer {
+        // Records go straight into the file, so there can be only the one, and local
+        let outputs = output_filenames(&args.output, &args.format);
+        let [(format, filename)] = outputs.as_slice() else {
+            return Err("--reorder-buffer writes a single format; pass one --format".into());
+        };
+        if is_s3_url(filename) {
+            return Err("--reorder-buffer writes a local --output, not s3://".into());
+        }
+        let writer = RecordWriter::create(
+            filename,
+            *format,
+            &scraper.tag_names(),
+            args.excel_csv,
+            !args.no_headers,
+            &args.null_value,
+        )?;
+        let output =
+            OrderedOutput::new(writer, capacity as usize).with_flush_every(args.flush_every);
+        scraper = scraper.with_ordered_output(output);
+        if matches!(format, OutputFormat::Csv | OutputFormat::Ndjson) {
+            streamed_file = Some(filename.clone());
+        }
+    }
+
+    // The one local CSV a --stream range scrape writes to
+    let stream_path = if args.stream {
+        let outputs = output_filenames(&args.output, &args.format);
+        let [(OutputFormat::Csv, filename)] = outputs.as_slice() else {
+            return Err("--stream writes a single CSV; pass --format csv".into());
+        };
+        if is_s3_url(filename) {
+            return Err("--stream writes a local --output, not s3://".into());
+        }
+        streamed_file = Some(filename.clone());
+        Some(filename.clone())
+    } else {
+        None
+    };
+
+    if let Some(event_log) = &event_log {
+        event_log.emit(&LifecycleEvent::RunStart {
+            ids: sighting_ids.len(),
+            concurrent: args.concurrent,
+        });
+    }
+
+    // Missing before this run, so a second pass retries only what failed in it
+    let previously_missing: HashSet<u64> = scraper.get_missing_sightings().into_iter().collect();
+
+    // Example 2: Scrape multiple specific sightings
+    let mut stream_written = None;
+    let scraped = if args.retry_missing {
+        status!("\nRetrying missing sightings...");
+        scraper
+            .retry_missing_sightings(args.concurrent, args.max_lifetime_attempts)
+            .await
+    } else if args.refresh_status {
+        status!(
+            "\nRefreshing the status of {} unverified of {} saved sightings...",
+            sighting_ids.len(),
+            existing.len()
+        );
+        scraper
+            .scrape_multiple_sightings(&sighting_ids, args.concurrent)
+            .await
+    } else if args.rescrape_older_than.is_some() {
+        status!(
+            "\nRescraping {} of {} saved sightings...",
+            sighting_ids.len(),
+            existing.len()
+        );
+        scraper
+            .scrape_multiple_sightings(&sighting_ids, args.concurrent)
+            .await
+    } else if let Some((first, last)) = range {
+        match &stream_path {
+            Some(filename) => {
+                status!("\nStreaming sightings to {}...", filename);
+                scraper
+                    .scrape_range_streaming(first, last, args.concurrent, filename)
+                    .await
+                    .map(|written| {
+                        stream_written = Some(written);
+                        Vec::new()
+                    })
+            }
+            None => {
+                status!("\nScraping sightings {}-{}...", first, last);
+                scraper
+                    .scrape_sighting_range(first, last, args.concurrent)
+                    .await
+            }
+        }
+    } else {
+        status!("\nScraping multiple sightings...");
+        scraper
+            .scrape_multiple_sightings(&sighting_ids, args.concurrent)
+            .await
+    };
+
+    // Streamed records are already in the file; this writes any held behind a sighting
+    // the run never got to
+    let streamed = match stream_written {
+        Some(written) => Some(written),
+        None => scraper.finish_ordered_output().transpose()?,
+    };
+    // A second pass below rewrites the missing file itself
+    scraper.finish_failure_files()?;
+
+    let mut records = match scraped {
+        // Nothing is saved, leaving the run as it stood for inspection
+        Err(e) if scraper.get_first_failure().is_some() => {
+            status!("Stopped by --fail-fast");
+            return Err(e.into());
+        }
+        result => result?,
+    };
+
+    if args.retry_missing_on_finish && !scraper.is_cancelled() {
+        let failed: Vec<u64> = scraper
+            .get_missing_sightings()
+            .into_iter()
+            .filter(|id| !previously_missing.contains(id))
+            .collect();
+        if !failed.is_empty() {
+            status!("\nRetrying {} failed sightings...", failed.len());
+            let recovered = scraper
+                .retry_failed_sightings(&failed, args.concurrent)
+                .await?;
+            status!(
+                "Second pass recovered {} of {} failed sightings",
+                recovered.len(),
+                failed.len()
+            );
+            records.extend(recovered);
+        }
+    }
+
+    let saved = streamed.unwrap_or(records.len());
     print_hms(&start);
-    // Save to CSV
-    scraper.save_to_csv(&records, &args.output)?;
+    print_timing_summary(&scraper.get_request_timings(), start.elapsed(), saved);
+    if let Some(budget) = scraper.retry_budget() {
+        status!(
+            "Retry budget: {} of {} used, {} failures not retried",
+            budget.used(),
+            budget.total(),
+            budget.denied()
+        );
+    }
+    if let Some(tuner) = scraper.auto_concurrency() {
+        status!(
+            "Concurrency settled at {} (peak {}, limit {})",
+            tuner.limit(),
+            tuner.peak(),
+            args.concurrent
+        );
+    }
+    if let Some(limit) = scraper.soft_rate_limit()
+        && limit.backoffs() > 0
+    {
+        status!(
+            "Slowed down {} times for latency over the {}ms baseline (delay {}x at the end)",
+            limit.backoffs(),
+            limit.baseline().unwrap_or_default().as_millis(),
+            limit.factor()
+        );
+    }
+    let other_specimen_types = scraper.other_specimen_types.load(Ordering::SeqCst);
+    if other_specimen_types > 0 {
+        status!(
+            "Dropped {} records of other specimen types",
+            other_specimen_types
+        );
+    }
+    let below_min_fields = scraper.below_min_fields.load(Ordering::SeqCst);
+    if below_min_fields > 0 {
+        status!(
+            "Dropped {} records with fewer than {} fields filled",
+            below_min_fields,
+            args.min_fields
+        );
+    }
+    events.send(&ControlEvent::Finished {
+        records: saved,
+        elapsed_secs: start.elapsed().as_secs_f64(),
+    });
+    if let Some(event_log) = &event_log {
+        event_log.emit(&LifecycleEvent::RunEnd {
+            records: saved,
+            missing: scraper.get_missing_sightings().len(),
+            elapsed_secs: start.elapsed().as_secs_f64(),
+            cancelled: scraper.is_cancelled(),
+        });
+    }
+    // Fresh records replace their stale rows; rows that failed to rescrape are kept
+    let mut records = if args.rescrape_older_than.is_some() {
+        replace_sightings(existing, records)
+    } else if args.refresh_status {
+        // Only the verification fields change; the rest of each row is kept as saved
+        let changed = apply_verification(&mut existing, &records);
+        status!("Updated the status of {} sightings", changed);
+        existing
+    } else if let Some(filename) = resumed_csv.filter(|filename| Path::new(filename).exists()) {
+        // The saved rows come first, then what this run added after them
+        let (mut saved, _) = read_csv_lenient(&filename)?;
+        saved.extend(records);
+        saved
+    } else {
+        records
+    };
+    let blank = scraper.clean_records(&mut records);
+    if blank > 0 {
+        status!("Dropped {} blank records", blank);
+    }
+    if args.sort_output {
+        sort_by_sighting_id(&mut records);
+    }
+    if let Some(written) = streamed {
+        status!(
+            "Wrote {} records to {} as they finished",
+            written,
+            args.output
+        );
+    } else if let Some(split_by) = args.split_by {
+        // One file per taxon in every requested format
+        let groups = split_records(&mut records, split_by);
+        for (format, filename) in output_filenames(&args.output, &args.format) {
+            for (key, group) in &groups {
+                save_output(&scraper, group, &split_filename(&filename, key), format).await?;
+            }
+        }
+        status!(
+            "Split records into {} files by {}",
+            groups.len(),
+            split_by.name()
+        );
+    } else {
+        // Save in every requested format
+        for (format, filename) in output_filenames(&args.output, &args.format) {
+            save_output(&scraper, &records, &filename, format).await?;
+        }
+    }
+    if let (Some(filename), Some(mut index)) = (&args.id_index, scraper.take_id_index()) {
+        index.extend(records.iter().filter_map(|record| record.sighting_id));
+        index.save(filename)?;
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = &args.db {
+        if args.record_run {
+            let run = RunInfo {
+                started_at,
+                finished_at: Utc::now(),
+                range,
+                count: saved,
+                missing_count: scraper.get_missing_sightings().len(),
+            };
+            let run_id = write_sqlite_run(&records, db, &run, args.on_conflict)?;
+            status!(
+                "Saved {} records to {} as run {}",
+                records.len(),
+                db,
+                run_id
+            );
+        } else {
+            write_sqlite(&records, db, args.on_conflict)?;
+            status!("Saved {} records to {}", records.len(), db);
+        }
+    }
 
-    // Print summary
-    print_summary(&records);
+    if let Some(filename) = &args.emit_retry_plan {
+        // A retry run's targets all failed before it started, so everything still missing
+        let attempts = scraper.missing_attempts.lock().unwrap().clone();
+        let failed: Vec<(u64, u32)> = scraper
+            .get_missing_sightings()
+            .into_iter()
+            .filter(|id| args.retry_missing || !previously_missing.contains(id))
+            .map(|id| (id, attempts.get(&id).copied().unwrap_or(1)))
+            .collect();
+        if failed.is_empty() {
+            // Nothing to retry, and a plan left from an earlier run must not be rerun
+            if std::path::Path::new(filename).exists() {
+                std::fs::remove_file(filename)?;
+            }
+            status!("No failures, so no retry plan");
+        } else {
+            let argv: Vec<String> = std::env::args().collect();
+            let plan = RetryPlan::new(&argv, &args, &failed);
+            plan.write(filename, &failed)?;
+            status!(
+                "Retry plan for {} failed sightings written to {}",
+                failed.len(),
+                filename
+            );
+        }
+    }
+
+    // Print summary of the records still in memory, which streamed ones are not
+    if streamed.is_none() {
+        print_summary(&records, args.split_regions);
+    }
+    print_field_fill_rates(&scraper.get_field_fill_stats());
+
+    if let Some(filename) = &args.summary_json {
+        let mut summary = Summary::new(args.split_regions);
+        match (streamed, &streamed_file) {
+            (None, _) => records.iter().for_each(|record| summary.add(record)),
+            // Streamed records are only in the file, so read them back
+            (Some(_), Some(streamed_file)) => {
+                for record in stream_records(streamed_file)? {
+                    summary.add(&record?);
+                }
+            }
+            (Some(_), None) => status!("The summary JSON counts no records of this output format"),
+        }
+        let summary = summary
+            .with_fill_stats(scraper.get_field_fill_stats())
+            .with_timings(scraper.get_request_timings().report(start.elapsed(), saved));
+        let mut writer = create_output(filename)?;
+        serde_json::to_writer_pretty(&mut writer, &summary.report())?;
+        writeln!(writer)?;
+        writer.flush()?;
+    }
+
+    let warnings = scraper.get_parse_warnings();
+    print_parse_warnings(&warnings);
+    if args.strict && !warnings.is_empty() {
+        return Err(format!("Strict mode: {} parse warnings", warnings.len()).into());
+    }
 
     Ok(())
 }
@@ -102,7 +986,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
 
     #[tokio::test]
     async fn test_scraper_creation() {
@@ -121,17 +1004,108 @@ mod tests {
         assert_eq!(scraper.max_retries, 5);
     }
 
+    #[test]
+    fn test_args_definition() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_preset_does_not_override_explicit_flags() {
+        let matches = Args::command()
+            .try_get_matches_from(["sachem", "--max", "10", "--polite", "--delay", "750"])
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        args.apply_preset(&matches);
+
+        assert_eq!(args.delay, 750);
+        assert_eq!(args.concurrent, 2);
+        assert_eq!(args.retries, 5);
+        assert!(!args.concurrency_auto);
+        assert_eq!(args.soft_limit_multiplier, Some(2.0));
+    }
+
+    #[test]
+    fn test_preset_sets_concurrency_tuning() {
+        let preset = |argv: &[&str]| {
+            let matches = Args::command()
+                .try_get_matches_from(["sachem", "--max", "10"].iter().chain(argv))
+                .unwrap();
+            let mut args = Args::from_arg_matches(&matches).unwrap();
+            args.apply_preset(&matches);
+            args
+        };
+
+        let args = preset(&["--aggressive"]);
+        assert!(args.concurrency_auto);
+        assert_eq!(args.soft_limit_multiplier, Some(3.0));
+        // Polite leaves concurrency fixed at its own
+        let args = preset(&["--polite"]);
+        assert!(!args.concurrency_auto);
+        let args = preset(&["--polite", "--soft-limit-multiplier", "4"]);
+        assert_eq!(args.soft_limit_multiplier, Some(4.0));
+        // Auto tuning asked for explicitly still stops at the preset's ceiling
+        let args = preset(&["--polite", "--concurrency-auto"]);
+        assert!(args.concurrency_auto);
+        assert_eq!(args.concurrent, 2);
+    }
+
     #[test]
     fn test_get_failed_ids() {
         let original_ids = vec![1, 2, 3, 4, 5];
-        let mut record1 = SightingRecord::default();
-        record1.sighting_id = Some(1);
-        let mut record3 = SightingRecord::default();
-        record3.sighting_id = Some(3);
+        let record1 = SightingRecord {
+            sighting_id: Some(1),
+            ..Default::default()
+        };
+        let record3 = SightingRecord {
+            sighting_id: Some(3),
+            ..Default::default()
+        };
 
         let scraped_records = vec![record1, record3];
         let failed_ids = get_failed_ids(&original_ids, &scraped_records);
 
         assert_eq!(failed_ids, vec![2, 4, 5]);
     }
+
+    #[test]
+    fn test_saved_output_finds_files_holding_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        std::fs::write(path("empty.csv"), "").unwrap();
+        std::fs::write(path("saved.ndjson"), "{}\n").unwrap();
+        let outputs = |names: &[&str]| -> Vec<(OutputFormat, String)> {
+            names
+                .iter()
+                .map(|name| (OutputFormat::Csv, path(name)))
+                .collect()
+        };
+
+        assert_eq!(saved_output(&outputs(&["new.csv", "empty.csv"])), None);
+        assert_eq!(
+            saved_output(&outputs(&["new.csv", "saved.ndjson"])),
+            Some(path("saved.ndjson").as_str())
+        );
+        let stdout = [(OutputFormat::Csv, STDOUT.to_string())];
+        assert_eq!(saved_output(&stdout), None);
+    }
+
+    #[test]
+    fn test_sort_by_sighting_id_puts_missing_ids_last() {
+        let record = |id: Option<u64>, name: &str| SightingRecord {
+            sighting_id: id,
+            common_name: name.to_string(),
+            ..Default::default()
+        };
+        let mut records = vec![
+            record(Some(30), "c"),
+            record(None, "x"),
+            record(Some(4), "a"),
+            record(None, "y"),
+            record(Some(12), "b"),
+        ];
+
+        sort_by_sighting_id(&mut records);
+        let names: Vec<&str> = records.iter().map(|r| r.common_name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c", "x", "y"]);
+    }
 }
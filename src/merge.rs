@@ -0,0 +1,372 @@
+use crate::output::{read_csv, write_csv};
+use crate::record::SightingRecord;
+use crate::util::parse_date;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use log::info;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+/// Which record wins when an incoming record shares a dedup key with an existing one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Keep the incoming record
+    #[value(alias = "last")]
+    Replace,
+    /// Keep the record already seen
+    #[value(alias = "first")]
+    KeepExisting,
+    /// Keep whichever record has the later verified_date, then the later scraped_at,
+    /// preferring the incoming one on ties
+    KeepNewer,
+}
+
+impl ConflictPolicy {
+    /// Whether `incoming` should replace `existing`
+    pub fn prefers_incoming(&self, existing: &SightingRecord, incoming: &SightingRecord) -> bool {
+        match self {
+            ConflictPolicy::Replace => true,
+            ConflictPolicy::KeepExisting => false,
+            ConflictPolicy::KeepNewer => {
+                recency_key(&incoming.verified_date, &incoming.scraped_at)
+                    >= recency_key(&existing.verified_date, &existing.scraped_at)
+            }
+        }
+    }
+}
+
+/// A key ordering records by parsed verified_date, then by scraped_at, so the newer
+/// record's key is the greater. A date or time that doesn't parse sorts before any that
+/// does.
+pub fn recency_key(verified_date: &str, scraped_at: &str) -> String {
+    let verified = parse_date(verified_date)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    // In UTC with fixed-width fractions, so the strings sort as the times do
+    let scraped = DateTime::parse_from_rfc3339(scraped_at)
+        .map(|at| {
+            at.with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Nanos, true)
+        })
+        .unwrap_or_default();
+    format!("{} {}", verified, scraped)
+}
+
+/// Deduplicate records on the combination of `key_fields`, returning them sorted by ID.
+/// Records whose key fields are all empty cannot be matched and are always kept.
+pub fn dedup_records(
+    records: Vec<SightingRecord>,
+    key_fields: &[String],
+    on_conflict: ConflictPolicy,
+) -> Vec<SightingRecord> {
+    let mut deduped: Vec<SightingRecord> = Vec::new();
+    let mut index_by_key: HashMap<String, usize> = HashMap::new();
+
+    for record in records {
+        let values: Vec<String> = key_fields
+            .iter()
+            .map(|field| record.field_value(field).unwrap_or_default())
+            .collect();
+        if values.iter().all(|value| value.is_empty()) {
+            deduped.push(record);
+            continue;
+        }
+
+        match index_by_key.entry(values.join("\u{1f}")) {
+            Entry::Occupied(entry) => {
+                let existing = &mut deduped[*entry.get()];
+                if on_conflict.prefers_incoming(existing, &record) {
+                    *existing = record;
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(deduped.len());
+                deduped.push(record);
+            }
+        }
+    }
+
+    // Stable sort keeps input order among records without an ID, which go last
+    deduped.sort_by_key(|record| (record.sighting_id.is_none(), record.sighting_id));
+    deduped
+}
+
+/// Replace every saved row of each sighting in `fresh` with its fresh rows, however many
+/// the page now has, returning them sorted by ID. Saved sightings without fresh rows are
+/// kept as they were.
+pub fn replace_sightings(
+    existing: Vec<SightingRecord>,
+    fresh: Vec<SightingRecord>,
+) -> Vec<SightingRecord> {
+    let fresh_ids: HashSet<u64> = fresh
+        .iter()
+        .filter_map(|record| record.sighting_id)
+        .collect();
+    let mut records: Vec<SightingRecord> = existing
+        .into_iter()
+        .filter(|record| record.sighting_id.is_none_or(|id| !fresh_ids.contains(&id)))
+        .chain(fresh)
+        .collect();
+    records.sort_by_key(|record| (record.sighting_id.is_none(), record.sighting_id));
+    records
+}
+
+/// IDs of records scraped before `cutoff`, in input order and once each however many rows
+/// share them. Records with no `scraped_at`, which predate the column, or an unreadable
+/// one count as stale.
+pub fn stale_sighting_ids(records: &[SightingRecord], cutoff: DateTime<Utc>) -> Vec<u64> {
+    let mut seen = HashSet::new();
+    records
+        .iter()
+        .filter(
+            |record| match DateTime::parse_from_rfc3339(&record.scraped_at) {
+                Ok(scraped_at) => scraped_at < cutoff,
+                Err(_) => true,
+            },
+        )
+        .filter_map(|record| record.sighting_id)
+        .filter(|id| seen.insert(*id))
+        .collect()
+}
+
+/// Status of a sighting whose verification is settled
+const VERIFIED_STATUS: &str = "Verified";
+
+/// IDs of records whose status is anything but verified, in input order and once each
+pub fn unverified_sighting_ids(records: &[SightingRecord]) -> Vec<u64> {
+    let mut seen = HashSet::new();
+    records
+        .iter()
+        .filter(|record| !record.status.trim().eq_ignore_ascii_case(VERIFIED_STATUS))
+        .filter_map(|record| record.sighting_id)
+        .filter(|id| seen.insert(*id))
+        .collect()
+}
+
+/// Copy the verification fields (`status`, `verified_by`, `verified_by_url` and
+/// `verified_date`) of each fresh record onto the existing record with its ID, leaving
+/// every other field as it was. Returns how many records changed.
+pub fn apply_verification(existing: &mut [SightingRecord], fresh: &[SightingRecord]) -> usize {
+    let fresh_by_id: HashMap<u64, &SightingRecord> = fresh
+        .iter()
+        .filter_map(|record| Some((record.sighting_id?, record)))
+        .collect();
+    let mut changed = 0;
+    for record in existing.iter_mut() {
+        let Some(fresh) = record.sighting_id.and_then(|id| fresh_by_id.get(&id)) else {
+            continue;
+        };
+        let verification = |record: &SightingRecord| {
+            (
+                record.status.clone(),
+                record.verified_by.clone(),
+                record.verified_by_url.clone(),
+                record.verified_date.clone(),
+            )
+        };
+        if verification(record) != verification(fresh) {
+            (
+                record.status,
+                record.verified_by,
+                record.verified_by_url,
+                record.verified_date,
+            ) = verification(fresh);
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Merge several CSV outputs into one deduplicated file sorted by ID
+pub fn merge_csv_files(
+    inputs: &[String],
+    output: &str,
+    key_fields: &[String],
+    on_conflict: ConflictPolicy,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let probe = SightingRecord::default();
+    if let Some(field) = key_fields.iter().find(|f| probe.field_value(f).is_none()) {
+        return Err(format!("Unknown field for --dedupe-by: {}", field).into());
+    }
+
+    let mut records = Vec::new();
+    for input in inputs {
+        let input_records = read_csv(input)?;
+        info!("Read {} records from {}", input_records.len(), input);
+        records.extend(input_records);
+    }
+
+    let read_count = records.len();
+    let merged = dedup_records(records, key_fields, on_conflict);
+    write_csv(&merged, output, false, true, "")?;
+    info!(
+        "Merged {} records into {} unique records in {}",
+        read_count,
+        merged.len(),
+        output
+    );
+
+    Ok(merged.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id_key() -> Vec<String> {
+        vec!["sighting_id".to_string()]
+    }
+
+    fn record(id: Option<u64>, name: &str) -> SightingRecord {
+        SightingRecord {
+            sighting_id: id,
+            common_name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dedup_records_sorts_and_replaces() {
+        let records = vec![
+            record(Some(3), "a"),
+            record(None, "b"),
+            record(Some(1), "c"),
+            record(Some(3), "d"),
+        ];
+        let deduped = dedup_records(records, &id_key(), ConflictPolicy::Replace);
+
+        let ids: Vec<_> = deduped.iter().map(|r| r.sighting_id).collect();
+        assert_eq!(ids, vec![Some(1), Some(3), None]);
+        assert_eq!(deduped[1].common_name, "d");
+    }
+
+    #[test]
+    fn test_dedup_records_keeps_existing() {
+        let records = vec![record(Some(3), "a"), record(Some(3), "d")];
+        let deduped = dedup_records(records, &id_key(), ConflictPolicy::KeepExisting);
+
+        assert_eq!(deduped.len(), 1);
+        assert_eq!(deduped[0].common_name, "a");
+    }
+
+    #[test]
+    fn test_dedup_records_by_composite_key() {
+        let sighting = |id, species: &str, date: &str| SightingRecord {
+            sighting_id: Some(id),
+            scientific_name: species.to_string(),
+            observation_date: date.to_string(),
+            ..Default::default()
+        };
+        let records = vec![
+            sighting(1, "Danaus plexippus", "2024-06-01"),
+            sighting(2, "Danaus plexippus", "2024-06-01"),
+            sighting(3, "Danaus plexippus", "2024-06-02"),
+            sighting(4, "", ""),
+            sighting(5, "", ""),
+        ];
+        let key = vec![
+            "scientific_name".to_string(),
+            "observation_date".to_string(),
+        ];
+        let deduped = dedup_records(records, &key, ConflictPolicy::KeepExisting);
+
+        let ids: Vec<_> = deduped.iter().filter_map(|r| r.sighting_id).collect();
+        assert_eq!(ids, vec![1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_apply_verification_leaves_other_fields() {
+        let record = |id, status: &str, verified_by: &str, locality: &str| SightingRecord {
+            sighting_id: Some(id),
+            status: status.to_string(),
+            verified_by: verified_by.to_string(),
+            locality: locality.to_string(),
+            ..Default::default()
+        };
+        let mut existing = vec![
+            record(1, "Pending", "", "Point Pelee"),
+            record(2, "verified", "coordinator456", "Rondeau"),
+            record(3, "Pending", "", "Long Point"),
+        ];
+        assert_eq!(unverified_sighting_ids(&existing), vec![1, 3]);
+
+        let mut fresh = vec![
+            record(1, "Verified", "coordinator456", "Renamed"),
+            record(3, "Pending", "", "Long Point"),
+        ];
+        fresh[0].verified_date = "2024-02-01".to_string();
+        assert_eq!(apply_verification(&mut existing, &fresh), 1);
+
+        assert_eq!(existing[0].status, "Verified");
+        assert_eq!(existing[0].verified_by, "coordinator456");
+        assert_eq!(existing[0].verified_date, "2024-02-01");
+        assert_eq!(existing[0].locality, "Point Pelee");
+        assert_eq!(existing[2].status, "Pending");
+    }
+
+    #[test]
+    fn test_replace_sightings_swaps_every_row() {
+        let existing = vec![
+            record(Some(2), "a"),
+            record(Some(1), "b"),
+            record(Some(1), "c"),
+            record(None, "d"),
+        ];
+        let fresh = vec![record(Some(1), "e"), record(Some(3), "f")];
+        let replaced = replace_sightings(existing, fresh);
+
+        let names: Vec<&str> = replaced.iter().map(|r| r.common_name.as_str()).collect();
+        assert_eq!(names, vec!["e", "a", "f", "d"]);
+    }
+
+    #[test]
+    fn test_stale_sighting_ids() {
+        let scraped = |id, at: &str| SightingRecord {
+            sighting_id: Some(id),
+            scraped_at: at.to_string(),
+            ..Default::default()
+        };
+        let records = vec![
+            scraped(1, "2024-01-01T00:00:00Z"),
+            scraped(2, "2024-03-01T00:00:00Z"),
+            scraped(3, ""),
+            scraped(4, "2024-02-01T06:00:00+07:00"),
+            scraped(5, "yesterday"),
+            scraped(1, "2024-01-01T00:00:00Z"),
+        ];
+        let cutoff = DateTime::parse_from_rfc3339("2024-02-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(stale_sighting_ids(&records, cutoff), vec![1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_dedup_records_keeps_newer() {
+        let verified = |date: &str| SightingRecord {
+            sighting_id: Some(7),
+            verified_date: date.to_string(),
+            ..Default::default()
+        };
+        let records = vec![verified("2024-03-01"), verified("2024-01-01"), verified("")];
+        let deduped = dedup_records(records, &id_key(), ConflictPolicy::KeepNewer);
+        assert_eq!(deduped[0].verified_date, "2024-03-01");
+
+        let records = vec![verified(""), verified("2024-01-01")];
+        let deduped = dedup_records(records, &id_key(), ConflictPolicy::KeepNewer);
+        assert_eq!(deduped[0].verified_date, "2024-01-01");
+
+        // The same verified_date falls back to the later scrape, across time zones
+        let scraped = |at: &str, name: &str| SightingRecord {
+            scraped_at: at.to_string(),
+            common_name: name.to_string(),
+            ..verified("01/15/2024")
+        };
+        let records = vec![
+            scraped("2024-02-01T06:00:00+07:00", "later"),
+            scraped("2024-01-31T22:00:00Z", "earlier"),
+        ];
+        let deduped = dedup_records(records, &id_key(), ConflictPolicy::KeepNewer);
+        assert_eq!(deduped[0].common_name, "later");
+    }
+}
@@ -0,0 +1,301 @@
+use crate::record::SightingRecord;
+use crate::util::{parse_date, status};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// How often each text field was filled across parsed records
+#[derive(Debug, Clone, Default)]
+pub struct FieldFillStats {
+    pub parsed: usize,
+    pub filled: Vec<(&'static str, usize)>,
+}
+
+impl FieldFillStats {
+    /// Count the non-empty fields of a freshly parsed record
+    pub fn record(&mut self, record: &SightingRecord) {
+        self.parsed += 1;
+        for (name, value) in record.text_fields() {
+            let filled = usize::from(!value.is_empty());
+            match self.filled.iter_mut().find(|(field, _)| *field == name) {
+                Some((_, count)) => *count += filled,
+                None => self.filled.push((name, filled)),
+            }
+        }
+    }
+
+    /// Percentage of parsed records in which each field was filled
+    pub fn fill_rates(&self) -> Vec<(&'static str, f64)> {
+        self.filled
+            .iter()
+            .map(|&(name, count)| (name, 100.0 * count as f64 / self.parsed.max(1) as f64))
+            .collect()
+    }
+}
+
+pub fn print_field_fill_rates(stats: &FieldFillStats) {
+    if stats.parsed == 0 {
+        return;
+    }
+
+    status!("Field fill rates ({} parsed):", stats.parsed);
+    for (name, rate) in stats.fill_rates() {
+        status!("  {}: {:.0}% filled", name, rate);
+    }
+}
+
+/// Latency of each HTTP request and the number of retries over a run
+#[derive(Debug, Clone, Default)]
+pub struct RequestTimings {
+    pub latencies: Vec<Duration>,
+    pub retries: u64,
+    /// Sightings abandoned because they ran past the per-ID time budget
+    pub timeouts: u64,
+    /// Times the run paused because the site served its maintenance page
+    pub maintenance_pauses: u64,
+    /// Sightings skipped because the response wasn't HTML, by media type
+    pub non_html: BTreeMap<String, u64>,
+}
+
+impl RequestTimings {
+    pub fn average(&self) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        self.latencies.iter().sum::<Duration>() / self.latencies.len() as u32
+    }
+
+    /// Nearest-rank percentile of the request latencies, `pct` in 0-100
+    pub fn percentile(&self, pct: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let mut sorted = self.latencies.clone();
+        sorted.sort();
+        let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+}
+
+/// Request counts, latency percentiles and throughput of a run, as written in the JSON
+/// summary
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimingReport {
+    pub requests: usize,
+    pub retries: u64,
+    pub timeouts: u64,
+    pub maintenance_pauses: u64,
+    pub avg_ms: u128,
+    pub p50_ms: u128,
+    pub p95_ms: u128,
+    pub records_per_sec: f64,
+}
+
+impl RequestTimings {
+    /// The timings of a run that saved `records` in `elapsed`
+    pub fn report(&self, elapsed: Duration, records: usize) -> TimingReport {
+        TimingReport {
+            requests: self.latencies.len(),
+            retries: self.retries,
+            timeouts: self.timeouts,
+            maintenance_pauses: self.maintenance_pauses,
+            avg_ms: self.average().as_millis(),
+            p50_ms: self.percentile(50.0).as_millis(),
+            p95_ms: self.percentile(95.0).as_millis(),
+            records_per_sec: records as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+}
+
+pub fn print_timing_summary(timings: &RequestTimings, elapsed: Duration, records: usize) {
+    if timings.latencies.is_empty() {
+        return;
+    }
+
+    if timings.timeouts > 0 {
+        status!(
+            "Requests: {} ({} retries, {} sightings timed out)",
+            timings.latencies.len(),
+            timings.retries,
+            timings.timeouts
+        );
+    } else {
+        status!(
+            "Requests: {} ({} retries)",
+            timings.latencies.len(),
+            timings.retries
+        );
+    }
+    if timings.maintenance_pauses > 0 {
+        status!(
+            "Paused {} times for site maintenance",
+            timings.maintenance_pauses
+        );
+    }
+    if !timings.non_html.is_empty() {
+        let types: Vec<String> = timings
+            .non_html
+            .iter()
+            .map(|(media_type, count)| format!("{} {}", media_type, count))
+            .collect();
+        status!(
+            "Non-HTML responses: {} ({})",
+            timings.non_html.values().sum::<u64>(),
+            types.join(", ")
+        );
+    }
+    status!(
+        "Latency: avg {}ms, p50 {}ms, p95 {}ms",
+        timings.average().as_millis(),
+        timings.percentile(50.0).as_millis(),
+        timings.percentile(95.0).as_millis()
+    );
+    status!(
+        "Throughput: {:.2} records/sec",
+        records as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+}
+
+/// A problem noticed while parsing a page, which may hint that the site's markup changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseWarning {
+    /// The page had no sighting row at all
+    NoRow,
+    /// A core field was empty
+    EmptyField(&'static str),
+    /// A date field was filled but in no recognized format
+    UnparseableDate(&'static str),
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::NoRow => write!(f, "no sighting row found"),
+            ParseWarning::EmptyField(field) => write!(f, "empty {}", field),
+            ParseWarning::UnparseableDate(field) => write!(f, "unparseable {}", field),
+        }
+    }
+}
+
+/// Check a parsed record for empty core fields and unparseable dates
+pub fn check_record(record: &SightingRecord) -> Vec<ParseWarning> {
+    let mut warnings = Vec::new();
+
+    let core_fields = [
+        ("common_name", &record.common_name),
+        ("scientific_name", &record.scientific_name),
+        ("observation_date", &record.observation_date),
+    ];
+    for (name, value) in core_fields {
+        if value.is_empty() {
+            warnings.push(ParseWarning::EmptyField(name));
+        }
+    }
+
+    let date_fields = [
+        ("observation_date", &record.observation_date),
+        ("verified_date", &record.verified_date),
+        ("submitted_date", &record.submitted_date),
+    ];
+    for (name, value) in date_fields {
+        if !value.is_empty() && parse_date(value).is_none() {
+            warnings.push(ParseWarning::UnparseableDate(name));
+        }
+    }
+
+    warnings
+}
+
+pub fn print_parse_warnings(warnings: &[(u64, ParseWarning)]) {
+    if warnings.is_empty() {
+        return;
+    }
+
+    let mut counts: Vec<(&ParseWarning, Vec<u64>)> = Vec::new();
+    for (sighting_id, warning) in warnings {
+        match counts.iter_mut().find(|(w, _)| *w == warning) {
+            Some((_, ids)) => ids.push(*sighting_id),
+            None => counts.push((warning, vec![*sighting_id])),
+        }
+    }
+
+    status!("Parse warnings ({} total):", warnings.len());
+    for (warning, ids) in counts {
+        let examples: Vec<String> = ids.iter().take(5).map(|id| id.to_string()).collect();
+        status!(
+            "  {}: {} (e.g. {})",
+            warning,
+            ids.len(),
+            examples.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_timing_percentiles() {
+        let timings = RequestTimings {
+            latencies: (1..=100).rev().map(Duration::from_millis).collect(),
+            ..Default::default()
+        };
+        assert_eq!(timings.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(timings.percentile(95.0), Duration::from_millis(95));
+        assert_eq!(timings.percentile(100.0), Duration::from_millis(100));
+        assert_eq!(timings.average(), Duration::from_micros(50_500));
+        assert_eq!(RequestTimings::default().percentile(95.0), Duration::ZERO);
+
+        let report = timings.report(Duration::from_secs(4), 10);
+        assert_eq!((report.p50_ms, report.p95_ms, report.avg_ms), (50, 95, 50));
+        assert_eq!(report.requests, 100);
+        assert_eq!(report.records_per_sec, 2.5);
+    }
+
+    #[test]
+    fn test_check_record() {
+        let record = SightingRecord {
+            common_name: "Monarch".to_string(),
+            scientific_name: "Danaus plexippus".to_string(),
+            observation_date: "2024-01-15".to_string(),
+            verified_date: "sometime".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_record(&record),
+            vec![ParseWarning::UnparseableDate("verified_date")]
+        );
+
+        let record = SightingRecord::default();
+        assert_eq!(
+            check_record(&record),
+            vec![
+                ParseWarning::EmptyField("common_name"),
+                ParseWarning::EmptyField("scientific_name"),
+                ParseWarning::EmptyField("observation_date"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_field_fill_rates() {
+        let mut stats = FieldFillStats::default();
+        stats.record(&SightingRecord {
+            scientific_name: "Danaus plexippus".to_string(),
+            verified_by: "coordinator".to_string(),
+            ..Default::default()
+        });
+        stats.record(&SightingRecord {
+            scientific_name: "Vanessa cardui".to_string(),
+            ..Default::default()
+        });
+
+        let rates = stats.fill_rates();
+        let rate = |field| rates.iter().find(|(name, _)| *name == field).unwrap().1;
+        assert_eq!(rate("scientific_name"), 100.0);
+        assert_eq!(rate("verified_by"), 50.0);
+        assert_eq!(rate("common_name"), 0.0);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::record::SightingRecord;
+use csv::Reader;
+use log::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+
+/// One row of a name map file
+#[derive(Deserialize)]
+struct NameMapRow {
+    scientific_name: String,
+    common_name: String,
+}
+
+/// Canonical common names keyed by scientific name, from a CSV with `scientific_name` and
+/// `common_name` columns. Scientific names match ignoring case and surrounding whitespace.
+#[derive(Debug, Default)]
+pub struct NameMap {
+    names: HashMap<String, String>,
+}
+
+impl NameMap {
+    pub fn from_reader(reader: impl Read) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut names = HashMap::new();
+        for row in Reader::from_reader(reader).deserialize() {
+            let row: NameMapRow = row?;
+            names.insert(normalize(&row.scientific_name), row.common_name);
+        }
+        Ok(Self { names })
+    }
+
+    pub fn load(filename: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(filename)?;
+        let map = Self::from_reader(file).map_err(|e| format!("{}: {}", filename, e))?;
+        info!(
+            "Loaded {} canonical names from {}",
+            map.names.len(),
+            filename
+        );
+        Ok(map)
+    }
+
+    /// Replace the record's common name with the canonical one for its species, if mapped.
+    /// Returns whether the name changed.
+    pub fn apply(&self, record: &mut SightingRecord) -> bool {
+        match self.names.get(&normalize(&record.scientific_name)) {
+            Some(name) if *name != record.common_name => {
+                record.common_name = name.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn normalize(scientific_name: &str) -> String {
+    scientific_name.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_map_canonicalizes_common_names() {
+        let map = NameMap::from_reader(
+            "scientific_name,common_name\n\
+             Danaus plexippus,Monarch\n\
+             Vanessa cardui,Painted Lady\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let mut monarch = SightingRecord {
+            common_name: "Monarch Butterfly".to_string(),
+            scientific_name: " danaus plexippus".to_string(),
+            ..Default::default()
+        };
+        assert!(map.apply(&mut monarch));
+        assert_eq!(monarch.common_name, "Monarch");
+
+        let mut unnamed = SightingRecord {
+            scientific_name: "Vanessa cardui".to_string(),
+            ..Default::default()
+        };
+        assert!(map.apply(&mut unnamed));
+        assert_eq!(unnamed.common_name, "Painted Lady");
+
+        let mut unmapped = SightingRecord {
+            common_name: "Luna Moth".to_string(),
+            scientific_name: "Actias luna".to_string(),
+            ..Default::default()
+        };
+        assert!(!map.apply(&mut unmapped));
+        assert_eq!(unmapped.common_name, "Luna Moth");
+    }
+
+    #[test]
+    fn test_name_map_requires_both_columns() {
+        assert!(NameMap::from_reader("scientific_name\nDanaus plexippus\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::error::Error;
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+/// Scheme of outputs written to S3 or S3-compatible object storage
+pub const S3_SCHEME: &str = "s3://";
+
+/// Files at or below this size go up in one request; larger ones in parts of this size
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+const PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Where an `s3://bucket/key` output goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    pub key: String,
+}
+
+/// Whether `output` names an object rather than a local file
+pub fn is_s3_url(output: &str) -> bool {
+    output.starts_with(S3_SCHEME)
+}
+
+/// Split `s3://bucket/key` into its bucket and key, both of which must be present
+pub fn parse_s3_url(url: &str) -> Result<S3Location, String> {
+    let rest = url
+        .strip_prefix(S3_SCHEME)
+        .ok_or_else(|| format!("{} is not an s3:// URL", url))?;
+    match rest.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() && !key.ends_with('/') => {
+            Ok(S3Location {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            })
+        }
+        _ => Err(format!(
+            "{} must name a bucket and key, as s3://bucket/key",
+            url
+        )),
+    }
+}
+
+/// Fail early, before any scraping, when `output` is an object this build cannot write
+pub fn check_output_supported(output: &str) -> Result<(), String> {
+    if !is_s3_url(output) {
+        return Ok(());
+    }
+    parse_s3_url(output)?;
+    if cfg!(feature = "s3") {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} needs the s3 feature; rebuild with --features s3",
+            output
+        ))
+    }
+}
+
+/// Local file an object is written to before it is uploaded, created under a random name
+/// that no other file can already hold, and removed when dropped
+pub fn staging_file(url: &str) -> std::io::Result<NamedTempFile> {
+    let name = url.rsplit('/').next().unwrap_or_default();
+    tempfile::Builder::new()
+        .prefix("sachem-")
+        .suffix(&format!("-{}", name))
+        .tempfile()
+}
+
+/// Upload the file at `path` to `url`, with credentials and region from the standard
+/// AWS chain: environment, shared config and profile, then instance or container roles
+#[cfg(feature = "s3")]
+pub async fn upload_file(path: &Path, url: &str) -> Result<(), Box<dyn Error>> {
+    use aws_sdk_s3::primitives::{ByteStream, Length};
+    use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+
+    let location = parse_s3_url(url)?;
+    let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let client = aws_sdk_s3::Client::new(&config);
+    let size = std::fs::metadata(path)?.len();
+
+    if size <= PART_SIZE {
+        client
+            .put_object()
+            .bucket(&location.bucket)
+            .key(&location.key)
+            .body(ByteStream::from_path(path).await?)
+            .send()
+            .await?;
+        return Ok(());
+    }
+
+    let upload = client
+        .create_multipart_upload()
+        .bucket(&location.bucket)
+        .key(&location.key)
+        .send()
+        .await?;
+    let upload_id = upload.upload_id().ok_or("S3 returned no upload ID")?;
+    let parts = async {
+        let mut parts = Vec::new();
+        let mut offset = 0;
+        while offset < size {
+            let length = PART_SIZE.min(size - offset);
+            let part_number = (offset / PART_SIZE) as i32 + 1;
+            let body = ByteStream::read_from()
+                .path(path)
+                .offset(offset)
+                .length(Length::Exact(length))
+                .build()
+                .await?;
+            let part = client
+                .upload_part()
+                .bucket(&location.bucket)
+                .key(&location.key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(body)
+                .send()
+                .await?;
+            parts.push(
+                CompletedPart::builder()
+                    .set_e_tag(part.e_tag().map(str::to_string))
+                    .part_number(part_number)
+                    .build(),
+            );
+            offset += length;
+        }
+        Ok::<_, Box<dyn Error>>(parts)
+    }
+    .await;
+    let parts = match parts {
+        Ok(parts) => parts,
+        Err(e) => {
+            // Parts left behind are billed until the upload is aborted
+            let _ = client
+                .abort_multipart_upload()
+                .bucket(&location.bucket)
+                .key(&location.key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+            return Err(e);
+        }
+    };
+    client
+        .complete_multipart_upload()
+        .bucket(&location.bucket)
+        .key(&location.key)
+        .upload_id(upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Without the `s3` feature there is no client to upload with
+#[cfg(not(feature = "s3"))]
+pub async fn upload_file(_path: &Path, url: &str) -> Result<(), Box<dyn Error>> {
+    Err(format!("{} needs the s3 feature; rebuild with --features s3", url).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_s3_url() {
+        assert_eq!(
+            parse_s3_url("s3://bucket/runs/sightings.csv"),
+            Ok(S3Location {
+                bucket: "bucket".to_string(),
+                key: "runs/sightings.csv".to_string(),
+            })
+        );
+        assert!(!is_s3_url("sightings.csv"));
+        for url in [
+            "s3://bucket",
+            "s3://bucket/",
+            "s3:///key",
+            "s3://bucket/runs/",
+        ] {
+            assert!(parse_s3_url(url).is_err(), "{}", url);
+        }
+        assert!(check_output_supported("sightings.csv").is_ok());
+        assert!(check_output_supported("s3://bucket").is_err());
+        assert_eq!(
+            check_output_supported("s3://bucket/sightings.csv").is_ok(),
+            cfg!(feature = "s3")
+        );
+        let staged = staging_file("s3://bucket/runs/sightings.csv").unwrap();
+        assert!(staged.path().to_string_lossy().ends_with("-sightings.csv"));
+        let again = staging_file("s3://bucket/runs/sightings.csv").unwrap();
+        assert_ne!(staged.path(), again.path());
+    }
+}
@@ -0,0 +1,903 @@
+use crate::error::ScraperError;
+use crate::record::{META_PREFIX, SightingRecord, Tags};
+use crate::schema::record_schema;
+use clap::ValueEnum;
+use csv::{DeserializeRecordsIntoIter, Reader, Terminator, Writer, WriterBuilder};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// File formats scraped records can be saved in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// One JSON object per line
+    #[value(alias = "jsonl")]
+    Ndjson,
+    /// A single JSON array of record objects
+    Json,
+    /// Zipped Darwin Core Archive, for GBIF and other biodiversity aggregators
+    Dwc,
+    /// Columnar Parquet file, for DuckDB, Polars and other analytics tools
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl OutputFormat {
+    /// The name given to `--format`
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Json => "json",
+            OutputFormat::Dwc => "dwc",
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Json => "json",
+            OutputFormat::Dwc => "zip",
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Whether `filename` ends in an extension used for `format`, ignoring case
+pub fn has_format_extension(filename: &str, format: OutputFormat) -> bool {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    match format {
+        OutputFormat::Ndjson => extension == "ndjson" || extension == "jsonl",
+        _ => extension == format.extension(),
+    }
+}
+
+/// Output filename that writes to stdout instead of a file
+pub const STDOUT: &str = "-";
+
+/// Open `filename` for writing, or lock stdout when it is `-`
+pub fn create_output(filename: &str) -> std::io::Result<Box<dyn Write>> {
+    if filename == STDOUT {
+        Ok(Box::new(std::io::stdout().lock()))
+    } else {
+        Ok(Box::new(File::create(filename)?))
+    }
+}
+
+/// Pair each requested format with the file it is written to.
+/// A single format uses `output` as given; several share its stem with per-format extensions.
+pub fn output_filenames(output: &str, formats: &[OutputFormat]) -> Vec<(OutputFormat, String)> {
+    let mut unique: Vec<OutputFormat> = Vec::new();
+    for format in formats {
+        if !unique.contains(format) {
+            unique.push(*format);
+        }
+    }
+
+    if unique.len() == 1 {
+        return vec![(unique[0], output.to_string())];
+    }
+    unique
+        .into_iter()
+        .map(|format| {
+            let filename = Path::new(output).with_extension(format.extension());
+            (format, filename.to_string_lossy().into_owned())
+        })
+        .collect()
+}
+
+/// UTF-8 byte order mark, which Excel needs to detect the encoding of a CSV file
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Write records to a CSV file, one row per record, with a `meta_` column after the
+/// schema's for each tag any record carries.
+/// With `excel` the file starts with a UTF-8 BOM and uses CRLF line endings. Without
+/// `headers` the header row is left out. Empty string fields are written as `null_value`.
+pub fn write_csv(
+    records: &[SightingRecord],
+    filename: &str,
+    excel: bool,
+    headers: bool,
+    null_value: &str,
+) -> Result<(), ScraperError> {
+    write_columns(
+        records,
+        &output_columns(records),
+        filename,
+        excel,
+        headers,
+        null_value,
+    )
+}
+
+/// Write records to a CSV file like `write_csv`, leaving out every column that is empty in
+/// all of them, with `false` counting as empty for a flag. `sighting_id` is always kept.
+/// Returns the names of the columns left out.
+pub fn write_compact_csv(
+    records: &[SightingRecord],
+    filename: &str,
+    excel: bool,
+    null_value: &str,
+) -> Result<Vec<String>, ScraperError> {
+    // A column left out reads back as its default, so only defaults count as empty
+    let empty = SightingRecord::default();
+    let (columns, dropped): (Vec<String>, Vec<String>) =
+        output_columns(records).into_iter().partition(|name| {
+            name == "sighting_id"
+                || records
+                    .iter()
+                    .any(|record| record.field_value(name) != empty.field_value(name))
+        });
+
+    write_columns(records, &columns, filename, excel, true, null_value)?;
+    Ok(dropped)
+}
+
+/// The schema's columns, then `meta_<tag>` for every tag in any record, sorted by tag
+fn output_columns(records: &[SightingRecord]) -> Vec<String> {
+    let tags: BTreeSet<&String> = records
+        .iter()
+        .flat_map(|record| record.extra.keys())
+        .collect();
+    columns_with_tags(tags)
+}
+
+/// The schema's columns, then `meta_<tag>` for each of `tags` in order
+fn columns_with_tags<'a>(tags: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    record_schema()
+        .into_iter()
+        .map(|field| field.name.to_string())
+        .chain(
+            tags.into_iter()
+                .map(|tag| format!("{}{}", META_PREFIX, tag)),
+        )
+        .collect()
+}
+
+/// A record's value for each of `columns`, with `null_value` for empty string fields
+fn csv_row<'a>(
+    record: &'a SightingRecord,
+    columns: &'a [String],
+    string_fields: &'a [bool],
+    null_value: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    columns
+        .iter()
+        .zip(string_fields)
+        .map(
+            move |(name, &string_field)| match record.field_value(name).unwrap_or_default() {
+                value if string_field && value.is_empty() => null_value.to_string(),
+                value => value,
+            },
+        )
+}
+
+/// Write a header of `columns`, unless `headers` is false, and each record's value for
+/// them, with `null_value` for empty string fields
+fn write_columns(
+    records: &[SightingRecord],
+    columns: &[String],
+    filename: &str,
+    excel: bool,
+    headers: bool,
+    null_value: &str,
+) -> Result<(), ScraperError> {
+    let string_fields: Vec<bool> = columns
+        .iter()
+        .map(|name| SightingRecord::is_string_field(name))
+        .collect();
+    let mut writer = csv_writer(filename, excel, headers)?;
+    if headers {
+        writer.write_record(columns)?;
+    }
+    for record in records {
+        writer.write_record(csv_row(record, columns, &string_fields, null_value))?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// CSV writer for `filename` (stdout for `-`), which with `excel` starts with a BOM and
+/// uses CRLF line endings. The header row is written by the caller, so `headers` only
+/// keeps the writer from adding one of its own.
+fn csv_writer(
+    filename: &str,
+    excel: bool,
+    headers: bool,
+) -> Result<Writer<Box<dyn Write>>, ScraperError> {
+    csv_writer_to(create_output(filename)?, excel, headers)
+}
+
+fn csv_writer_to(
+    mut file: Box<dyn Write>,
+    excel: bool,
+    headers: bool,
+) -> Result<Writer<Box<dyn Write>>, ScraperError> {
+    let mut builder = WriterBuilder::new();
+    builder.has_headers(headers);
+    if excel {
+        file.write_all(UTF8_BOM)?;
+        builder.terminator(Terminator::CRLF);
+    }
+    Ok(builder.from_writer(file))
+}
+
+/// Read records back from a CSV file written by `write_csv`. A leading BOM is skipped.
+pub fn read_csv(filename: &str) -> Result<Vec<SightingRecord>, Box<dyn std::error::Error>> {
+    let file = File::open(filename)?;
+    let mut reader = Reader::from_reader(file);
+
+    let mut records = Vec::new();
+    for record in reader.deserialize() {
+        records.push(record?);
+    }
+
+    Ok(records)
+}
+
+/// Read the records of a CSV an interrupted run may have cut off mid-row, skipping rows
+/// that don't parse, such as a partial last one. Returns the records and the number of
+/// rows skipped.
+pub fn read_csv_lenient(
+    filename: &str,
+) -> Result<(Vec<SightingRecord>, usize), Box<dyn std::error::Error>> {
+    let mut records = Vec::new();
+    let mut skipped = 0;
+    for record in stream_csv(filename)? {
+        match record {
+            Ok(record) => records.push(record),
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(_) => skipped += 1,
+        }
+    }
+
+    Ok((records, skipped))
+}
+
+/// Records of a CSV read one row at a time, each as `read_csv_lenient` would read it
+pub fn stream_csv(filename: &str) -> csv::Result<DeserializeRecordsIntoIter<File, SightingRecord>> {
+    Ok(Reader::from_path(filename)?.into_deserialize())
+}
+
+/// Read records from a CSV, or from NDJSON when the file ends in `.ndjson`, `.jsonl` or
+/// `.json`. A `.json` file holding a JSON array is read as one.
+pub fn read_records(filename: &str) -> Result<Vec<SightingRecord>, Box<dyn std::error::Error>> {
+    stream_records(filename)?.collect()
+}
+
+/// A record read from a file, or why it could not be
+pub type RecordResult = Result<SightingRecord, Box<dyn std::error::Error>>;
+
+/// Records read one at a time from a file `read_records` accepts, so a large file need
+/// not fit in memory. Blank NDJSON lines are skipped. A JSON array is read whole.
+pub fn stream_records(
+    filename: &str,
+) -> Result<Box<dyn Iterator<Item = RecordResult>>, Box<dyn std::error::Error>> {
+    let file = File::open(filename)?;
+    if !is_ndjson(filename) {
+        return Ok(Box::new(
+            Reader::from_reader(file)
+                .into_deserialize()
+                .map(|record| record.map_err(Into::into)),
+        ));
+    }
+
+    let mut reader = BufReader::new(file);
+    if starts_with_array(&mut reader)? {
+        let records: Vec<SightingRecord> =
+            serde_json::from_reader(reader).map_err(|e| format!("{}: {}", filename, e))?;
+        return Ok(Box::new(records.into_iter().map(Ok)));
+    }
+
+    let filename = filename.to_string();
+    let lines = reader.lines().enumerate();
+    Ok(Box::new(lines.filter_map(move |(index, line)| {
+        match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => Some(
+                serde_json::from_str(&line)
+                    .map_err(|e| format!("{} line {}: {}", filename, index + 1, e).into()),
+            ),
+            Err(e) => Some(Err(e.into())),
+        }
+    })))
+}
+
+/// Whether the JSON in `reader` opens with an array, looking past leading whitespace
+/// without consuming anything else
+fn starts_with_array(reader: &mut impl BufRead) -> std::io::Result<bool> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        let Some(&byte) = buffer.first() else {
+            return Ok(false);
+        };
+        if !byte.is_ascii_whitespace() {
+            return Ok(byte == b'[');
+        }
+        reader.consume(1);
+    }
+}
+
+/// Whether `filename` is read as NDJSON rather than CSV, going by its extension
+fn is_ndjson(filename: &str) -> bool {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    matches!(extension.as_deref(), Some("ndjson" | "jsonl" | "json"))
+}
+
+/// Write records as newline-delimited JSON, one object per line, with empty string fields
+/// written as `null_value`
+pub fn write_ndjson(
+    records: &[SightingRecord],
+    filename: &str,
+    null_value: &str,
+) -> Result<(), ScraperError> {
+    let mut writer = BufWriter::new(create_output(filename)?);
+
+    for record in records {
+        serde_json::to_writer(&mut writer, &ndjson_value(record, null_value)?)?;
+        writeln!(writer)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write records as one JSON array, each record's object on a line of its own, with
+/// empty string fields written as `null_value`
+pub fn write_json(
+    records: &[SightingRecord],
+    filename: &str,
+    null_value: &str,
+) -> Result<(), ScraperError> {
+    let mut writer = BufWriter::new(create_output(filename)?);
+
+    write!(writer, "[")?;
+    for (index, record) in records.iter().enumerate() {
+        writer.write_all(if index == 0 { b"\n" } else { b",\n" })?;
+        serde_json::to_writer(&mut writer, &ndjson_value(record, null_value)?)?;
+    }
+    writeln!(writer, "{}]", if records.is_empty() { "" } else { "\n" })?;
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// A record as the JSON object written on its NDJSON line
+fn ndjson_value(
+    record: &SightingRecord,
+    null_value: &str,
+) -> Result<serde_json::Value, serde_json::Error> {
+    let mut object = serde_json::to_value(record)?;
+    if !null_value.is_empty()
+        && let Some(fields) = object.as_object_mut()
+    {
+        for (name, value) in fields.iter_mut() {
+            if SightingRecord::is_string_field(name) && value.as_str() == Some("") {
+                *value = null_value.into();
+            }
+        }
+    }
+    // Left out of the CSV columns, which hold only the joined string
+    object["checklist_regions_list"] = record.checklist_regions_list.clone().into();
+    for (tag, value) in &record.extra {
+        object[format!("{}{}", META_PREFIX, tag)] = value.clone().into();
+    }
+    Ok(object)
+}
+
+/// Writes records one at a time, as CSV or NDJSON, for output saved as the run goes
+/// rather than all at once at the end. `file` is the output file, none for stdout, kept
+/// to sync it to disk.
+pub enum RecordWriter {
+    Csv {
+        writer: Box<Writer<Box<dyn Write>>>,
+        columns: Vec<String>,
+        string_fields: Vec<bool>,
+        null_value: String,
+        file: Option<File>,
+    },
+    Ndjson {
+        writer: BufWriter<Box<dyn Write>>,
+        null_value: String,
+        file: Option<File>,
+    },
+}
+
+impl RecordWriter {
+    /// Start writing `filename` in `format`, a CSV header first unless `headers` is false.
+    /// `tags` names every tag the records may carry, since the header can't grow once
+    /// written.
+    pub fn create(
+        filename: &str,
+        format: OutputFormat,
+        tags: &BTreeSet<String>,
+        excel: bool,
+        headers: bool,
+        null_value: &str,
+    ) -> Result<Self, ScraperError> {
+        let file = match filename {
+            STDOUT => None,
+            _ => Some(File::create(filename)?),
+        };
+        let output: Box<dyn Write> = match &file {
+            Some(file) => Box::new(file.try_clone()?),
+            None => Box::new(std::io::stdout().lock()),
+        };
+        match format {
+            OutputFormat::Csv => {
+                let columns = columns_with_tags(tags);
+                let string_fields = columns
+                    .iter()
+                    .map(|name| SightingRecord::is_string_field(name))
+                    .collect();
+                let mut writer = csv_writer_to(output, excel, headers)?;
+                if headers {
+                    writer.write_record(&columns)?;
+                }
+                Ok(Self::Csv {
+                    writer: Box::new(writer),
+                    columns,
+                    string_fields,
+                    null_value: null_value.to_string(),
+                    file,
+                })
+            }
+            OutputFormat::Ndjson => Ok(Self::Ndjson {
+                writer: BufWriter::new(output),
+                null_value: null_value.to_string(),
+                file,
+            }),
+            _ => Err(ScraperError::Output(
+                format!(
+                    "{} output can't be written a record at a time; use csv or ndjson",
+                    format.extension()
+                )
+                .into(),
+            )),
+        }
+    }
+
+    /// Write one record after those already written
+    pub fn write(&mut self, record: &SightingRecord) -> Result<(), ScraperError> {
+        match self {
+            Self::Csv {
+                writer,
+                columns,
+                string_fields,
+                null_value,
+                ..
+            } => writer.write_record(csv_row(record, columns, string_fields, null_value))?,
+            Self::Ndjson {
+                writer, null_value, ..
+            } => {
+                serde_json::to_writer(&mut *writer, &ndjson_value(record, null_value)?)?;
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Push everything written so far out to the file
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Csv { writer, .. } => writer.flush(),
+            Self::Ndjson { writer, .. } => writer.flush(),
+        }
+    }
+
+    /// Flush, then have the file written through to disk, so a crash loses none of it
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        self.flush()?;
+        match self {
+            Self::Csv { file, .. } | Self::Ndjson { file, .. } => match file {
+                Some(file) => file.sync_data(),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// Write sighting IDs to a file, one per line, in the same format as the missing file
+pub fn write_ids(ids: &[u64], filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+
+    for id in ids {
+        writeln!(writer, "{}", id)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read sighting IDs and their tags from a CSV whose first column is the ID. Each further
+/// column is a tag named by its header, carried into the records as `meta_<name>`.
+pub fn read_id_file(filename: &str) -> Result<Vec<(u64, Tags)>, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_reader(File::open(filename)?);
+    let tags: Vec<String> = reader.headers()?.iter().skip(1).map(String::from).collect();
+
+    let mut ids = Vec::new();
+    for (index, row) in reader.records().enumerate() {
+        let row = row?;
+        let id = row.get(0).unwrap_or_default().trim();
+        let sighting_id = id.parse().map_err(|_| {
+            format!(
+                "{} row {}: invalid sighting ID \"{}\"",
+                filename,
+                index + 2,
+                id
+            )
+        })?;
+        let extra = tags
+            .iter()
+            .zip(row.iter().skip(1))
+            .map(|(tag, value)| (tag.clone(), value.to_string()))
+            .collect();
+        ids.push((sighting_id, extra));
+    }
+
+    Ok(ids)
+}
+
+/// One line of a priorities file
+#[derive(Deserialize)]
+struct PriorityLine {
+    sighting_id: u64,
+    priority: i64,
+}
+
+/// Read sighting priorities from JSON lines such as `{"sighting_id": 1000, "priority": 5}`.
+/// Blank lines are skipped; a later line for the same ID wins.
+pub fn read_priorities(filename: &str) -> Result<HashMap<u64, i64>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(filename)?);
+    let mut priorities = HashMap::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: PriorityLine = serde_json::from_str(&line)
+            .map_err(|e| format!("{} line {}: {}", filename, index + 1, e))?;
+        priorities.insert(entry.sighting_id, entry.priority);
+    }
+
+    Ok(priorities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scraper::ButterflyMothScraper;
+
+    #[test]
+    fn test_excel_csv_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("excel.csv");
+        let filename = filename.to_str().unwrap();
+        let records = vec![
+            SightingRecord {
+                sighting_id: Some(1),
+                common_name: "Citron à queue".to_string(),
+                checklist_regions: "Québec, Canada".to_string(),
+                ..Default::default()
+            },
+            SightingRecord {
+                sighting_id: Some(2),
+                common_name: "Mariposa monarca".to_string(),
+                checklist_regions: "Michoacán, México".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        write_csv(&records, filename, true, true, "").unwrap();
+        let bytes = std::fs::read(filename).unwrap();
+        assert!(bytes.starts_with(UTF8_BOM));
+        assert!(!bytes[UTF8_BOM.len()..].windows(3).any(|w| w == UTF8_BOM));
+        let text = String::from_utf8(bytes).unwrap();
+        assert_eq!(text.matches("\r\n").count(), text.matches('\n').count());
+
+        let read_back = read_csv(filename).unwrap();
+        assert_eq!(read_back.len(), 2);
+        assert_eq!(read_back[0].sighting_id, Some(1));
+        assert_eq!(read_back[0].common_name, "Citron à queue");
+        assert_eq!(read_back[1].checklist_regions, "Michoacán, México");
+    }
+
+    #[test]
+    fn test_compact_csv_drops_empty_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("compact.csv");
+        let filename = filename.to_str().unwrap();
+        let records = vec![
+            SightingRecord {
+                sighting_id: Some(1),
+                common_name: "Monarch".to_string(),
+                ..Default::default()
+            },
+            SightingRecord {
+                sighting_id: Some(2),
+                verified_by: "J. Smith".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        let dropped = write_compact_csv(&records, filename, false, "").unwrap();
+        assert!(dropped.iter().any(|name| name == "scientific_name"));
+        assert!(!dropped.iter().any(|name| name == "verified_by"));
+        let text = std::fs::read_to_string(filename).unwrap();
+        assert_eq!(
+            text,
+            "sighting_id,common_name,verified_by\n1,Monarch,\n2,,J. Smith\n"
+        );
+
+        let dropped = write_compact_csv(&[], filename, false, "").unwrap();
+        assert!(!dropped.iter().any(|name| name == "sighting_id"));
+        assert_eq!(std::fs::read_to_string(filename).unwrap(), "sighting_id\n");
+    }
+
+    #[test]
+    fn test_null_value_fills_empty_string_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = vec![SightingRecord {
+            sighting_id: Some(1),
+            common_name: "Monarch".to_string(),
+            ..Default::default()
+        }];
+
+        let csv = dir.path().join("out.csv");
+        write_csv(&records, csv.to_str().unwrap(), false, true, "\\N").unwrap();
+        let text = std::fs::read_to_string(&csv).unwrap();
+        let row = text.lines().nth(1).unwrap();
+        // Option and flag columns keep their own empty and false values
+        assert!(row.starts_with("1,,Monarch,\\N,\\N,"));
+        assert!(row.ends_with(",\\N,,,,false,0,false,,\\N"));
+
+        let ndjson = dir.path().join("out.ndjson");
+        write_ndjson(&records, ndjson.to_str().unwrap(), "NA").unwrap();
+        let object: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&ndjson).unwrap()).unwrap();
+        assert_eq!(object["common_name"], "Monarch");
+        assert_eq!(object["locality"], "NA");
+        assert_eq!(object["url"], serde_json::Value::Null);
+        assert_eq!(object["checklist_regions_list"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_csv_without_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = vec![
+            SightingRecord {
+                sighting_id: Some(1),
+                common_name: "Monarch".to_string(),
+                ..Default::default()
+            },
+            SightingRecord {
+                sighting_id: Some(2),
+                scientific_name: "Vanessa cardui".to_string(),
+                ..Default::default()
+            },
+        ];
+        let with_headers = dir.path().join("with.csv");
+        let without_headers = dir.path().join("without.csv");
+        write_csv(&records, with_headers.to_str().unwrap(), false, true, "").unwrap();
+        write_csv(
+            &records,
+            without_headers.to_str().unwrap(),
+            false,
+            false,
+            "",
+        )
+        .unwrap();
+
+        // The same rows, in the schema's column order, with no header in front
+        let with_headers = std::fs::read_to_string(&with_headers).unwrap();
+        let without_headers = std::fs::read_to_string(&without_headers).unwrap();
+        let (header, rows) = with_headers.split_once('\n').unwrap();
+        assert_eq!(without_headers, rows);
+        let schema: Vec<&str> = record_schema().iter().map(|field| field.name).collect();
+        assert_eq!(header.split(',').collect::<Vec<_>>(), schema);
+        assert!(without_headers.starts_with("1,,Monarch,"));
+    }
+
+    #[test]
+    fn test_read_csv_lenient_skips_partial_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("sightings.csv");
+        let csv = csv.to_str().unwrap();
+        let records: Vec<SightingRecord> = [4, 9]
+            .into_iter()
+            .map(|id| SightingRecord {
+                sighting_id: Some(id),
+                ..Default::default()
+            })
+            .collect();
+        write_csv(&records, csv, false, true, "").unwrap();
+        // A run killed mid-write leaves the start of a row behind
+        let mut file = std::fs::OpenOptions::new().append(true).open(csv).unwrap();
+        file.write_all(b"12,Monarch,Danaus").unwrap();
+
+        assert!(read_csv(csv).is_err());
+        let (read, skipped) = read_csv_lenient(csv).unwrap();
+        let ids: Vec<Option<u64>> = read.iter().map(|r| r.sighting_id).collect();
+        assert_eq!(ids, vec![Some(4), Some(9)]);
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_json_array_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = vec![
+            SightingRecord {
+                sighting_id: Some(1),
+                common_name: "Monarch".to_string(),
+                checklist_regions: "Ontario, Canada".to_string(),
+                checklist_regions_list: vec!["Ontario".to_string(), "Canada".to_string()],
+                ..Default::default()
+            },
+            SightingRecord {
+                sighting_id: Some(2),
+                ..Default::default()
+            },
+        ];
+
+        let json = dir.path().join("out.json");
+        let json = json.to_str().unwrap();
+        write_json(&records, json, "").unwrap();
+        let text = std::fs::read_to_string(json).unwrap();
+        assert!(text.starts_with("[\n{\"") && text.ends_with("}\n]\n"));
+        assert_eq!(text.lines().count(), 4);
+        let read_back = read_records(json).unwrap();
+        assert_eq!(read_back.len(), 2);
+        assert_eq!(read_back[0].common_name, "Monarch");
+        assert_eq!(read_back[0].checklist_regions_list, ["Ontario", "Canada"]);
+
+        write_json(&[], json, "").unwrap();
+        assert_eq!(std::fs::read_to_string(json).unwrap(), "[]\n");
+        assert!(read_records(json).unwrap().is_empty());
+
+        assert!(has_format_extension("out.JSON", OutputFormat::Json));
+        assert!(has_format_extension("out.jsonl", OutputFormat::Ndjson));
+        assert!(!has_format_extension("sightings.csv", OutputFormat::Json));
+    }
+
+    #[test]
+    fn test_jsonl_writes_one_record_per_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = vec![
+            SightingRecord {
+                sighting_id: Some(1),
+                common_name: "Monarch".to_string(),
+                ..Default::default()
+            },
+            SightingRecord {
+                sighting_id: Some(2),
+                ..Default::default()
+            },
+        ];
+
+        let jsonl = dir.path().join("out.jsonl");
+        let jsonl = jsonl.to_str().unwrap();
+        ButterflyMothScraper::new()
+            .save_to_ndjson(&records, jsonl)
+            .unwrap();
+        let text = std::fs::read_to_string(jsonl).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["sighting_id"], 2);
+        let read_back = read_records(jsonl).unwrap();
+        assert_eq!(read_back[0].common_name, "Monarch");
+    }
+
+    #[test]
+    fn test_output_filenames() {
+        assert_eq!(
+            output_filenames("out.csv", &[OutputFormat::Ndjson]),
+            vec![(OutputFormat::Ndjson, "out.csv".to_string())]
+        );
+        assert_eq!(
+            output_filenames(
+                "data/out.csv",
+                &[OutputFormat::Csv, OutputFormat::Ndjson, OutputFormat::Csv]
+            ),
+            vec![
+                (OutputFormat::Csv, "data/out.csv".to_string()),
+                (OutputFormat::Ndjson, "data/out.ndjson".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_id_file_tags_become_meta_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let ids = dir.path().join("ids.csv");
+        std::fs::write(&ids, "id,survey,observer\n5,spring,ann\n6,fall,\n").unwrap();
+
+        let entries = read_id_file(ids.to_str().unwrap()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, 5);
+        assert_eq!(entries[1].1["survey"], "fall");
+
+        let records: Vec<SightingRecord> = entries
+            .into_iter()
+            .map(|(id, extra)| SightingRecord {
+                sighting_id: Some(id),
+                extra,
+                ..Default::default()
+            })
+            .collect();
+        let csv = dir.path().join("out.csv");
+        write_csv(&records, csv.to_str().unwrap(), false, true, "").unwrap();
+        let text = std::fs::read_to_string(&csv).unwrap();
+        let mut lines = text.lines();
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .ends_with(",scraped_at,meta_observer,meta_survey")
+        );
+        assert!(lines.next().unwrap().ends_with(",ann,spring"));
+        // The scraped columns still read back
+        assert_eq!(
+            read_csv(csv.to_str().unwrap()).unwrap()[1].sighting_id,
+            Some(6)
+        );
+
+        let ndjson = dir.path().join("out.ndjson");
+        write_ndjson(&records, ndjson.to_str().unwrap(), "").unwrap();
+        let first: serde_json::Value = serde_json::from_str(
+            std::fs::read_to_string(&ndjson)
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(first["meta_survey"], "spring");
+        // Both formats read back by extension, with the meta_ keys ignored
+        let read_back = read_records(ndjson.to_str().unwrap()).unwrap();
+        assert_eq!(read_back.len(), 2);
+        assert_eq!(read_back[1].sighting_id, Some(6));
+        assert_eq!(read_records(csv.to_str().unwrap()).unwrap().len(), 2);
+
+        std::fs::write(&ids, "id,survey\nabc,spring\n").unwrap();
+        assert!(read_id_file(ids.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_read_priorities() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("priorities.jsonl");
+        let filename = filename.to_str().unwrap();
+        std::fs::write(
+            filename,
+            "{\"sighting_id\": 5, \"priority\": 2}\n\n{\"sighting_id\": 6, \"priority\": -1}\n",
+        )
+        .unwrap();
+
+        let priorities = read_priorities(filename).unwrap();
+        assert_eq!(priorities, HashMap::from([(5, 2), (6, -1)]));
+
+        std::fs::write(filename, "{\"sighting_id\": 5}\n").unwrap();
+        let err = read_priorities(filename).unwrap_err().to_string();
+        assert!(err.contains("line 1"), "{}", err);
+    }
+}
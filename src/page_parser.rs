@@ -0,0 +1,444 @@
+use crate::record::{SightingRecord, SpecimenType};
+use crate::selectors::FieldSelectors;
+use clap::ValueEnum;
+use scraper::{ElementRef, Html, Selector};
+use unicode_normalization::UnicodeNormalization;
+
+/// Turns the HTML of a sighting details page into records
+pub trait PageParser: Send + Sync {
+    /// Parse every sighting found on the page, in document order
+    fn parse(&self, html: &str) -> Vec<SightingRecord>;
+
+    /// The `href` of the page's link to its next page, as found, if it is paginated
+    fn next_page(&self, _html: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Built-in page layouts, selectable with `--parser-version`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ParserVersion {
+    /// Drupal views markup served by the site since the scraper was written
+    V1,
+}
+
+impl ParserVersion {
+    /// Build the parser, reading fields with `selectors` and joining multi-valued fields
+    /// such as regions with `multi_value_sep`
+    pub fn parser(&self, multi_value_sep: &str, selectors: FieldSelectors) -> Box<dyn PageParser> {
+        match self {
+            ParserVersion::V1 => {
+                Box::new(ViewsRowParser::new(multi_value_sep).with_selectors(selectors))
+            }
+        }
+    }
+}
+
+/// Separator placed between the values of a multi-valued field unless configured otherwise
+pub const DEFAULT_MULTI_VALUE_SEP: &str = ", ";
+
+/// Parser for pages laid out as Drupal `views-row` blocks, one per sighting
+pub struct ViewsRowParser {
+    selectors: FieldSelectors,
+    multi_value_sep: String,
+}
+
+impl ViewsRowParser {
+    pub fn new(multi_value_sep: &str) -> Self {
+        Self {
+            selectors: FieldSelectors::default(),
+            multi_value_sep: multi_value_sep.to_string(),
+        }
+    }
+
+    /// Read rows and fields with `selectors` instead of the built-in ones
+    pub fn with_selectors(mut self, selectors: FieldSelectors) -> Self {
+        self.selectors = selectors;
+        self
+    }
+
+    /// Extract the fields of one sighting from its views row
+    fn parse_row(&self, row: ElementRef) -> SightingRecord {
+        let selectors = &self.selectors;
+        let text = |element: ElementRef| normalize_text(&element.text().collect::<String>());
+        let field = |selector: &Selector| row.select(selector).next().map(text).unwrap_or_default();
+
+        // The common name is the heading's own text, ahead of the scientific name inside it
+        let common_name = row
+            .select(&selectors.common_name)
+            .next()
+            .and_then(|heading| heading.text().next())
+            .map(normalize_text)
+            .unwrap_or_default();
+        let href = |selector: &Selector| {
+            row.select(selector)
+                .next()
+                .and_then(|link| link.value().attr("href"))
+                .unwrap_or_default()
+                .to_string()
+        };
+        let regions: Vec<String> = row.select(&selectors.checklist_regions).map(text).collect();
+        // A thumbnail link and the image inside it are one photo
+        let photos: Vec<ElementRef> = row.select(&selectors.photos).collect();
+        let photo_count = photos
+            .iter()
+            .filter(|photo| {
+                !photo
+                    .ancestors()
+                    .any(|ancestor| photos.iter().any(|other| other.id() == ancestor.id()))
+            })
+            .count() as u32;
+
+        // Half a point can't be mapped, so both are kept or neither
+        let (latitude, longitude) = match (
+            parse_degrees(&field(&selectors.latitude), 90.0),
+            parse_degrees(&field(&selectors.longitude), 180.0),
+        ) {
+            (Some(latitude), Some(longitude)) => (Some(latitude), Some(longitude)),
+            _ => (None, None),
+        };
+
+        SightingRecord {
+            common_name,
+            scientific_name: field(&selectors.scientific_name),
+            species_link: href(&selectors.species_link),
+            observation_date: field(&selectors.observation_date),
+            submitted_by: field(&selectors.submitted_by),
+            // Known types under one spelling, so counts and filters group them together
+            specimen_type: SpecimenType::parse(&field(&selectors.specimen_type))
+                .map(|specimen_type| specimen_type.to_string())
+                .unwrap_or_default(),
+            status: field(&selectors.status),
+            verified_by: field(&selectors.verified_by),
+            verified_date: field(&selectors.verified_date),
+            submitted_date: field(&selectors.submitted_date),
+            checklist_regions: regions.join(&self.multi_value_sep),
+            checklist_regions_list: regions,
+            locality: field(&selectors.locality),
+            // As linked from the page; the scraper makes them absolute
+            submitted_by_url: href(&selectors.submitted_by_url),
+            verified_by_url: href(&selectors.verified_by_url),
+            coordinate_precision: Some(field(&selectors.coordinate_precision))
+                .filter(|note| !note.is_empty()),
+            latitude,
+            longitude,
+            location_obscured: row.select(&selectors.location_obscured).next().is_some(),
+            photo_count,
+            has_photo: photo_count > 0,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for ViewsRowParser {
+    fn default() -> Self {
+        Self::new(DEFAULT_MULTI_VALUE_SEP)
+    }
+}
+
+impl PageParser for ViewsRowParser {
+    fn parse(&self, html: &str) -> Vec<SightingRecord> {
+        let document = Html::parse_document(html);
+        document
+            .select(&self.selectors.row)
+            .map(|row| self.parse_row(row))
+            .collect()
+    }
+
+    fn next_page(&self, html: &str) -> Option<String> {
+        let document = Html::parse_document(html);
+        document
+            .select(&self.selectors.next_page)
+            .find_map(|link| link.value().attr("href"))
+            .map(str::trim)
+            .filter(|href| !href.is_empty())
+            .map(str::to_string)
+    }
+}
+
+/// Text as stored in a record: composed to Unicode NFC, so an accent typed as a combining
+/// mark matches the precomposed letter, with every run of whitespace (non-breaking spaces
+/// included) collapsed to one space and none at either end. Nothing else is dropped.
+pub fn normalize_text(text: &str) -> String {
+    text.nfc()
+        .collect::<String>()
+        .split(char::is_whitespace)
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The first record on a saved sighting page, parsed with the built-in layout and
+/// selectors, or `None` for a maintenance page or one without a sighting row. Nothing is
+/// fetched, and fields the page can't supply (`sighting_id`, `url`, `scraped_at`) are left
+/// empty.
+pub fn parse_html_to_record(html: &str) -> Option<SightingRecord> {
+    parse_html_to_records(html, &ViewsRowParser::default())
+        .into_iter()
+        .next()
+}
+
+/// Every record on a saved sighting page, one per row, parsed with `parser`, or none for a
+/// maintenance page or one without a sighting row
+pub fn parse_html_to_records(html: &str, parser: &dyn PageParser) -> Vec<SightingRecord> {
+    if is_maintenance_page(html) {
+        return Vec::new();
+    }
+    parser.parse(html)
+}
+
+/// A coordinate written as plain decimal degrees, such as `-79.52`, or `None` when the
+/// text is empty, isn't a plain number (degree symbols and minutes included) or is beyond
+/// `limit` either way
+fn parse_degrees(text: &str, limit: f64) -> Option<f64> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|degrees| degrees.abs() <= limit)
+}
+
+/// Whether `html` is the site's "down for maintenance" page rather than a sighting page:
+/// Drupal serves it with a `maintenance-page` body class and a title mentioning maintenance
+pub fn is_maintenance_page(html: &str) -> bool {
+    if !html.to_ascii_lowercase().contains("maintenance") {
+        return false;
+    }
+    let document = Html::parse_document(html);
+    let body = Selector::parse("body.maintenance-page").unwrap();
+    let title = Selector::parse("title").unwrap();
+    document.select(&body).next().is_some()
+        || document.select(&title).any(|title| {
+            title
+                .text()
+                .collect::<String>()
+                .to_lowercase()
+                .contains("maintenance")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGHTING_HTML: &str = include_str!("../tests/fixtures/sighting.html");
+    const OBSCURED_HTML: &str = include_str!("../tests/fixtures/sighting_obscured.html");
+    const NO_LOCALITY_HTML: &str = include_str!("../tests/fixtures/sighting_no_locality.html");
+    const PROFILE_LINKS_HTML: &str = include_str!("../tests/fixtures/sighting_profile_links.html");
+    const ALL_DATES_HTML: &str = include_str!("../tests/fixtures/sighting_all_dates.html");
+    const PHOTOS_HTML: &str = include_str!("../tests/fixtures/sighting_photos.html");
+
+    #[test]
+    fn test_maintenance_page_detected() {
+        let maintenance = r#"<html><head><title>Site under maintenance | Butterflies and Moths of North America</title></head>
+<body class="maintenance-page in-maintenance"><p>The site is currently down for maintenance.</p></body></html>"#;
+        assert!(is_maintenance_page(maintenance));
+        assert!(is_maintenance_page(
+            "<html><head><title>Down for Maintenance</title></head><body></body></html>"
+        ));
+        assert!(!is_maintenance_page(SIGHTING_HTML));
+        // A sighting whose notes mention maintenance is still a sighting
+        assert!(!is_maintenance_page(
+            "<html><head><title>Sighting</title></head><body><p>Seen during trail maintenance</p></body></html>"
+        ));
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(
+            normalize_text("\u{a0}Danaus\u{a0}\u{a0}plexippus \n\t"),
+            "Danaus plexippus"
+        );
+        // A combining acute accent composes into the same character as the precomposed one
+        assert_eq!(normalize_text("Que\u{301}bec"), "Qu\u{e9}bec");
+        assert_eq!(normalize_text("Qu\u{e9}bec"), "Qu\u{e9}bec");
+        assert_eq!(
+            normalize_text("Papilio  glaucus\u{202f}(f.)"),
+            "Papilio glaucus (f.)"
+        );
+        assert_eq!(normalize_text(" \u{a0} "), "");
+    }
+
+    #[test]
+    fn test_parsed_fields_are_normalized() {
+        let html = "<div class=\"views-row\">\
+            <div class=\"views-field-field-sciname\"><h4>Monarch\u{a0} <em>Danaus\u{a0}plexippus</em></h4></div>\
+            <div class=\"views-field-field-region\"><a>Que\u{301}bec,\u{a0}Canada</a><a> Qu\u{e9}bec,  Canada</a></div>\
+            </div>";
+        let record = &ViewsRowParser::default().parse(html)[0];
+        assert_eq!(record.common_name, "Monarch");
+        assert_eq!(record.scientific_name, "Danaus plexippus");
+        assert_eq!(
+            record.checklist_regions,
+            "Qu\u{e9}bec, Canada, Qu\u{e9}bec, Canada"
+        );
+    }
+
+    #[test]
+    fn test_parse_views_row() {
+        let records = ViewsRowParser::default().parse(SIGHTING_HTML);
+        let record = &records[0];
+
+        assert_eq!(record.common_name, "Monarch");
+        assert_eq!(record.scientific_name, "Danaus plexippus");
+        assert_eq!(record.species_link, "/species/Danaus-plexippus");
+        assert_eq!(record.observation_date, "2024-01-15");
+        assert_eq!(record.submitted_by, "observer123");
+        assert_eq!(record.verified_by, "coordinator456");
+        assert_eq!(record.checklist_regions, "Ontario, Canada");
+        assert_eq!(record.checklist_regions_list, ["Ontario", "Canada"]);
+        assert_eq!(record.locality, "Point Pelee National Park");
+        assert_eq!(record.submitted_by_url, "");
+        assert_eq!(record.verified_by_url, "");
+    }
+
+    #[test]
+    fn test_parse_html_to_record() {
+        let record = parse_html_to_record(SIGHTING_HTML).unwrap();
+        assert_eq!(record.scientific_name, "Danaus plexippus");
+        assert_eq!(record.sighting_id, None);
+        assert!(parse_html_to_record("<html><body></body></html>").is_none());
+
+        let html = include_str!("../tests/fixtures/sighting_multi_row.html");
+        let records = parse_html_to_records(html, &ViewsRowParser::default());
+        let names: Vec<&str> = records.iter().map(|r| r.scientific_name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["Danaus plexippus", "Danaus gilippus", "Limenitis archippus"]
+        );
+    }
+
+    #[test]
+    fn test_parse_views_row_ignores_label_language() {
+        // Fields are found by class, so translated labels parse the same
+        let localized = SIGHTING_HTML
+            .replace("Observation Date: ", "Date d'observation : ")
+            .replace("Submitted by: ", "Soumis par : ")
+            .replace("Checklist Region(s): ", "Région(s) : ");
+
+        let json =
+            |html: &str| serde_json::to_value(ViewsRowParser::default().parse(html)).unwrap();
+        assert_eq!(json(&localized), json(SIGHTING_HTML));
+    }
+
+    #[test]
+    fn test_parse_profile_links() {
+        let records = ViewsRowParser::default().parse(PROFILE_LINKS_HTML);
+
+        assert_eq!(records[0].submitted_by, "observer123");
+        assert_eq!(records[0].submitted_by_url, "/users/observer123");
+        assert_eq!(
+            records[0].verified_by_url,
+            "https://www.butterfliesandmoths.org/users/coordinator456"
+        );
+    }
+
+    #[test]
+    fn test_parse_coordinate_precision_and_obscured_location() {
+        let records = ViewsRowParser::default().parse(OBSCURED_HTML);
+        assert_eq!(
+            records[0].coordinate_precision.as_deref(),
+            Some("Generalized to 10 km")
+        );
+        assert!(records[0].location_obscured);
+
+        let records = ViewsRowParser::default().parse(SIGHTING_HTML);
+        assert_eq!(records[0].coordinate_precision, None);
+        assert!(!records[0].location_obscured);
+    }
+
+    #[test]
+    fn test_parse_submitted_date_apart_from_verified_date() {
+        let records = ViewsRowParser::default().parse(ALL_DATES_HTML);
+        assert_eq!(records[0].observation_date, "2024-01-15");
+        assert_eq!(records[0].submitted_date, "2024-03-02");
+        assert_eq!(records[0].verified_date, "2024-03-05");
+
+        // Pages without a submission date leave it empty
+        let records = ViewsRowParser::default().parse(SIGHTING_HTML);
+        assert_eq!(records[0].submitted_date, "");
+    }
+
+    #[test]
+    fn test_parse_coordinates() {
+        let page = |latitude: &str, longitude: &str| {
+            format!(
+                r#"<div class="views-row">
+                <div class="views-field-field-latitude"><span class="field-content">{}</span></div>
+                <div class="views-field-field-longitude"><span class="field-content">{}</span></div>
+                </div>"#,
+                latitude, longitude
+            )
+        };
+        let parse = |html: &str| {
+            let record = ViewsRowParser::default().parse(html).remove(0);
+            (record.latitude, record.longitude)
+        };
+        assert_eq!(
+            parse(&page(" 41.96 ", "-82.51")),
+            (Some(41.96), Some(-82.51))
+        );
+        // Anything but plain decimal degrees is left out without losing the record
+        assert_eq!(parse(&page("41.96°", "-82.51")), (None, None));
+        assert_eq!(parse(&page("41°57'N", "91.2")), (None, None));
+        assert_eq!(parse(&page("N/A", "200")), (None, None));
+        assert_eq!(parse(SIGHTING_HTML), (None, None));
+    }
+
+    #[test]
+    fn test_parse_photo_count() {
+        let parser = ViewsRowParser::default();
+        // Two thumbnails wrapping their images, and one bare image
+        let records = parser.parse(PHOTOS_HTML);
+        assert_eq!(records[0].photo_count, 3);
+        assert!(records[0].has_photo);
+
+        let records = parser.parse(SIGHTING_HTML);
+        assert_eq!(records[0].photo_count, 0);
+        assert!(!records[0].has_photo);
+    }
+
+    #[test]
+    fn test_parse_views_row_without_locality() {
+        let records = ViewsRowParser::default().parse(NO_LOCALITY_HTML);
+
+        assert_eq!(records[0].scientific_name, "Vanessa cardui");
+        assert_eq!(records[0].locality, "");
+    }
+
+    #[test]
+    fn test_parse_views_row_with_multi_value_sep() {
+        let records = ViewsRowParser::new("|").parse(SIGHTING_HTML);
+
+        assert_eq!(records[0].checklist_regions, "Ontario|Canada");
+    }
+
+    #[test]
+    fn test_parse_views_row_with_selector_overrides() {
+        let html =
+            r#"<article class="sighting"><h2>Monarch <i>Danaus plexippus</i></h2></article>"#;
+        let selectors = FieldSelectors::from_toml(
+            r#"
+            row = "article.sighting"
+            common_name = "h2"
+            scientific_name = "h2 i"
+            "#,
+        )
+        .unwrap();
+        let records = ViewsRowParser::default()
+            .with_selectors(selectors)
+            .parse(html);
+
+        assert_eq!(records[0].common_name, "Monarch");
+        assert_eq!(records[0].scientific_name, "Danaus plexippus");
+        assert_eq!(records[0].locality, "");
+    }
+
+    #[test]
+    fn test_parse_page_without_rows() {
+        assert!(
+            ViewsRowParser::default()
+                .parse("<html><body></body></html>")
+                .is_empty()
+        );
+    }
+}
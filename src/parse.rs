@@ -1,38 +1,894 @@
-use clap::Parser;
+use crate::cookies::parse_cookie;
+use crate::dashboard::DEFAULT_DASHBOARD_BIND;
+use crate::merge::ConflictPolicy;
+use crate::output::OutputFormat;
+use crate::page_parser::{DEFAULT_MULTI_VALUE_SEP, ParserVersion};
+use crate::pivot::PivotField;
+use crate::record::SpecimenType;
+use crate::reorder::DEFAULT_FLUSH_EVERY;
+use crate::scraper::{
+    DEFAULT_BASE_URL, DEFAULT_MAX_BODY_BYTES, DEFAULT_MAX_MAINTENANCE_PAUSES, DEFAULT_MAX_REDIRECTS,
+};
+use crate::split::SplitBy;
+use crate::util::prefix_file_name;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Parser, Subcommand};
+use reqwest::header::{ACCEPT_LANGUAGE, HeaderMap, HeaderName, HeaderValue};
+use std::net::IpAddr;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "butterfly-scraper")]
 #[command(about = "A CLI tool for scraping butterfly and moth sighting data")]
 #[command(version = "1.0")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub(crate) struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Minimum sighting ID to scrape
-    #[arg(short, long, default_value = "0")]
+    #[arg(short, long, default_value = "0", env = "SACHEM_MIN")]
     pub min: u64,
 
     /// Maximum sighting ID to scrape
-    #[arg(short = 'M', long)]
-    pub max: u64,
+    #[arg(
+        short = 'M',
+        long,
+        required_unless_present_any = ["sitemap", "ids", "retry_missing", "report_missing_ranges", "rescrape_older_than", "refresh_status"],
+        env = "SACHEM_MAX"
+    )]
+    pub max: Option<u64>,
+
+    /// Scrape the sighting pages listed in this sitemap (or sitemap index) instead of a range
+    #[arg(long, conflicts_with_all = ["min", "max", "descending"], env = "SACHEM_SITEMAP")]
+    pub sitemap: Option<String>,
+
+    /// Scrape the IDs in the first column of this CSV instead of a range; further columns are
+    /// tags written to the output as meta_<header> columns
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["min", "max", "descending", "sitemap"], env = "SACHEM_IDS")]
+    pub ids: Option<String>,
+
+    /// Retry the sightings listed in the --missing file instead of scraping a range
+    #[arg(long, conflicts_with_all = ["min", "max", "descending", "sitemap", "ids", "head_check"], env = "SACHEM_RETRY_MISSING")]
+    pub retry_missing: bool,
+
+    /// Once the main pass is done, retry the sightings that failed in it one more time
+    #[arg(long, conflicts_with_all = ["retry_missing", "head_check"], env = "SACHEM_RETRY_MISSING_ON_FINISH")]
+    pub retry_missing_on_finish: bool,
+
+    /// Runs a missing sighting may fail in before it moves to the dead-letter file
+    #[arg(long, default_value = "3", value_parser = clap::value_parser!(u32).range(1..), env = "SACHEM_MAX_LIFETIME_ATTEMPTS")]
+    pub max_lifetime_attempts: u32,
+
+    /// File of sighting IDs that are never retried again
+    #[arg(long, default_value = "dead_letter.txt", env = "SACHEM_DEAD_LETTER")]
+    pub dead_letter: String,
+
+    /// Print the IDs in the --missing file as coalesced ranges, or write them to FILE, and exit
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        conflicts_with_all = ["min", "max", "descending", "sitemap", "ids", "retry_missing", "head_check"],
+        env = "SACHEM_REPORT_MISSING_RANGES"
+    )]
+    pub report_missing_ranges: Option<Option<String>>,
+
+    /// Re-scrape the sightings in --output scraped longer ago than this, e.g. 30d or 12h,
+    /// and update them in place
+    #[arg(
+        long,
+        value_name = "AGE",
+        value_parser = parse_age,
+        conflicts_with_all = ["min", "max", "descending", "sitemap", "retry_missing", "report_missing_ranges", "head_check"],
+        env = "SACHEM_RESCRAPE_OLDER_THAN"
+    )]
+    pub rescrape_older_than: Option<Duration>,
+
+    /// Re-scrape the sightings in --output that are not yet verified and update only their
+    /// status, verifier and verification date in place
+    #[arg(
+        long,
+        conflicts_with_all = ["min", "max", "descending", "sitemap", "ids", "retry_missing", "report_missing_ranges", "head_check", "rescrape_older_than"],
+        env = "SACHEM_REFRESH_STATUS"
+    )]
+    pub refresh_status: bool,
+
+    /// Skip the sightings already in the CSV output, as an interrupted run left it, and
+    /// add this run's records to them
+    #[arg(long, conflicts_with_all = ["retry_missing", "rescrape_older_than", "refresh_status", "head_check", "report_missing_ranges", "reorder_buffer", "split_by", "no_headers"], env = "SACHEM_RESUME")]
+    pub resume: bool,
+
+    /// After the run, write a plan that retries exactly the sightings it failed on with the
+    /// same settings: a shell script for a .sh file name, otherwise JSON
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["head_check", "report_missing_ranges"], env = "SACHEM_EMIT_RETRY_PLAN")]
+    pub emit_retry_plan: Option<String>,
+
+    /// Skip missing and dead-lettered IDs with a bloom filter, using far less memory for
+    /// millions of IDs at the cost of occasionally skipping an ID that was never missing
+    #[arg(long, env = "SACHEM_APPROX_DEDUP")]
+    pub approx_dedup: bool,
+
+    /// Warn when less than this share (0 to 1) of the first --density-window IDs exist
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction, env = "SACHEM_MIN_DENSITY")]
+    pub min_density: Option<f64>,
+
+    /// IDs that must finish before --min-density is checked
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "500",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "min_density",
+        env = "SACHEM_DENSITY_WINDOW"
+    )]
+    pub density_window: u64,
+
+    /// Stop the run, saving what was found, when the density is below --min-density
+    #[arg(long, requires = "min_density", env = "SACHEM_DENSITY_ABORT")]
+    pub density_abort: bool,
+
+    /// Follow each sighting page's next-page links and merge their rows into its record
+    #[arg(long, env = "SACHEM_FOLLOW_PAGINATION")]
+    pub follow_pagination: bool,
+
+    /// Pages read per sighting at most with --follow-pagination, the first included
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "follow_pagination",
+        env = "SACHEM_MAX_PAGES"
+    )]
+    pub max_pages: u64,
+
+    /// Rows a full page shows; with --follow-pagination, a page with fewer is the last
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "follow_pagination",
+        env = "SACHEM_RECORDS_PER_PAGE"
+    )]
+    pub records_per_page: Option<u64>,
+
+    /// Binary index of IDs already scraped, which are skipped; built from the CSV output
+    /// when absent and updated with each run's records
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["rescrape_older_than", "refresh_status"],
+        env = "SACHEM_ID_INDEX"
+    )]
+    pub id_index: Option<String>,
+
+    /// Rebuild --id-index from the CSV output instead of loading it
+    #[arg(long, requires = "id_index", env = "SACHEM_REBUILD_ID_INDEX")]
+    pub rebuild_id_index: bool,
+
+    /// Scrape from --max down to --min, newest sightings first
+    #[arg(long, env = "SACHEM_DESCENDING")]
+    pub descending: bool,
+
+    /// Site to fetch sighting pages from, e.g. a mirror
+    #[arg(long, default_value = DEFAULT_BASE_URL, env = "SACHEM_BASE_URL")]
+    pub base_url: String,
+
+    /// Page layout to parse sighting pages as
+    #[arg(long, value_enum, default_value = "v1", env = "SACHEM_PARSER_VERSION")]
+    pub parser_version: ParserVersion,
+
+    /// TOML file mapping record fields to CSS selectors, overriding the built-in ones
+    #[arg(long, value_name = "TOML", env = "SACHEM_SELECTORS")]
+    pub selectors: Option<String>,
+
+    /// Separator between the values of multi-valued fields such as checklist_regions; `\t` is a tab
+    #[arg(long, default_value = DEFAULT_MULTI_VALUE_SEP, value_parser = parse_separator, env = "SACHEM_MULTI_VALUE_SEP")]
+    pub multi_value_sep: String,
+
+    /// Count each checklist region on its own in the summary's top regions, rather than
+    /// each record's whole list of them
+    #[arg(long, env = "SACHEM_SPLIT_REGIONS")]
+    pub split_regions: bool,
+
+    /// After the run, also write its summary as JSON to this file, or to stdout for -
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["head_check", "report_missing_ranges"], env = "SACHEM_SUMMARY_JSON")]
+    pub summary_json: Option<String>,
+
+    /// JSON lines of {"sighting_id": ..., "priority": ...}; higher priorities are scraped first
+    #[arg(long, value_name = "FILE", env = "SACHEM_PRIORITIES")]
+    pub priorities: Option<String>,
+
+    /// CSV of scientific_name,common_name pairs; mapped species get the canonical common name
+    #[arg(long, value_name = "FILE", env = "SACHEM_NAME_MAP")]
+    pub name_map: Option<String>,
+
+    /// Extra header sent with every request, as "Key: Value" (repeatable)
+    #[arg(long = "header", value_name = "KEY: VALUE", value_parser = parse_header, env = "SACHEM_HEADER")]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Accept-Language sent with every request, e.g. "en-US, en;q=0.9", so runs ask the site
+    /// for the same language; replaces an Accept-Language given with --header
+    #[arg(long, value_name = "LANGUAGES", value_parser = parse_accept_language, env = "SACHEM_ACCEPT_LANGUAGE")]
+    pub accept_language: Option<HeaderValue>,
+
+    /// Send a random X-Request-Id with every request and log it with the sighting ID, so a
+    /// mirror operator can match their logs to ours
+    #[arg(long, env = "SACHEM_SEND_REQUEST_ID")]
+    pub send_request_id: bool,
+
+    /// How long to pause the run when the site serves its maintenance page, e.g. "5m"
+    #[arg(long, value_name = "AGE", default_value = "5m", value_parser = parse_age, env = "SACHEM_MAINTENANCE_COOLDOWN")]
+    pub maintenance_cooldown: Duration,
+
+    /// Longest wait a 429's Retry-After header is obeyed for, e.g. "5m"
+    #[arg(long, value_name = "AGE", default_value = "5m", value_parser = parse_age, env = "SACHEM_MAX_RETRY_AFTER")]
+    pub max_retry_after: Duration,
+
+    /// Maintenance pages a sighting waits out before it fails with reason `maintenance`
+    #[arg(long, default_value_t = DEFAULT_MAX_MAINTENANCE_PAUSES, env = "SACHEM_MAX_MAINTENANCE_PAUSES")]
+    pub max_maintenance_pauses: u32,
+
+    /// Redirects followed per request; longer chains and loops mark the sighting missing
+    #[arg(long, default_value_t = DEFAULT_MAX_REDIRECTS, env = "SACHEM_MAX_REDIRECTS")]
+    pub max_redirects: usize,
+
+    /// Largest page body read; longer ones are abandoned and the sighting marked missing
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_BODY_BYTES, env = "SACHEM_MAX_BODY_BYTES")]
+    pub max_body_bytes: u64,
+
+    /// Cookie sent with every request, as "name=value" (repeatable)
+    #[arg(long = "cookie", value_name = "NAME=VALUE", value_parser = parse_cookie, env = "SACHEM_COOKIE")]
+    pub cookies: Vec<String>,
+
+    /// Load cookies from a Netscape-format cookie file, as exported by a browser or curl
+    #[arg(long, value_name = "FILE", env = "SACHEM_COOKIE_FILE")]
+    pub cookie_file: Option<String>,
 
     /// Base delay between requests in milliseconds
-    #[arg(short, long, default_value = "500")]
+    #[arg(short, long, default_value = "500", env = "SACHEM_DELAY")]
     pub delay: u64,
 
+    /// Scrape in bursts of this many sightings, pausing for --burst-pause between them
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), env = "SACHEM_BURST")]
+    pub burst: Option<u64>,
+
+    /// Minimum pause between bursts in milliseconds; each pause adds up to half again at random
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value = "10000",
+        requires = "burst",
+        env = "SACHEM_BURST_PAUSE"
+    )]
+    pub burst_pause: u64,
+
     /// Maximum number of concurrent requests
-    #[arg(short, long, default_value = "5")]
+    #[arg(short, long, default_value = "5", env = "SACHEM_CONCURRENT")]
     pub concurrent: usize,
 
-    #[arg(short, long, default_value = "missing.txt")]
+    /// Tune concurrency from latency and 429s, starting at 1 and going no higher than
+    /// --concurrent
+    #[arg(long, env = "SACHEM_CONCURRENCY_AUTO")]
+    pub concurrency_auto: bool,
+
+    /// Treat responses averaging this many times slower than at the start of the run as
+    /// throttling, doubling the delay between requests until they recover
+    #[arg(long, value_name = "MULTIPLIER", value_parser = parse_slowdown_multiplier, env = "SACHEM_SOFT_LIMIT_MULTIPLIER")]
+    pub soft_limit_multiplier: Option<f64>,
+
+    /// Responses averaged for --soft-limit-multiplier, both for the baseline and for each
+    /// later check
+    #[arg(long, value_name = "N", default_value = "20", value_parser = clap::value_parser!(u64).range(1..), requires = "soft_limit_multiplier", env = "SACHEM_SOFT_LIMIT_WINDOW")]
+    pub soft_limit_window: u64,
+
+    /// File to track failed sighting IDs
+    #[arg(long, default_value = "missing.txt", env = "SACHEM_MISSING")]
     pub missing: String,
 
+    /// Label put in front of the --missing and --dead-letter file names, to keep the
+    /// failures of unrelated runs in one directory apart
+    #[arg(long, value_name = "LABEL", env = "SACHEM_MISSING_PREFIX")]
+    pub missing_prefix: Option<String>,
+
     /// Maximum number of retry attempts
-    #[arg(short, long, default_value = "3")]
+    #[arg(short, long, default_value = "3", env = "SACHEM_RETRIES")]
     pub retries: u32,
 
-    /// Output CSV filename
-    #[arg(short, long, default_value = "sightings.csv")]
+    /// Retries the whole run may use between all its sightings; once spent, failures go
+    /// straight to the missing file
+    #[arg(long, value_name = "N", env = "SACHEM_TOTAL_RETRY_BUDGET")]
+    pub total_retry_budget: Option<u64>,
+
+    /// Comma-separated HTTP statuses that are retried; other error statuses mark the
+    /// sighting missing on the first attempt
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "429,500,502,503,504",
+        value_parser = clap::value_parser!(u16).range(400..=599),
+        env = "SACHEM_RETRY_STATUSES"
+    )]
+    pub retry_statuses: Vec<u16>,
+
+    /// Seconds a sighting may take across all its retries before it is marked missing
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..), env = "SACHEM_PER_ID_TIMEOUT")]
+    pub per_id_timeout: Option<u64>,
+
+    /// Stop at the first sighting that fails for any reason but a 404 or 410, saving nothing
+    #[arg(long, env = "SACHEM_FAIL_FAST")]
+    pub fail_fast: bool,
+
+    /// Output CSV filename, - for stdout, or s3://bucket/key with the s3 feature
+    #[arg(short, long, default_value = "sightings.csv", env = "SACHEM_OUTPUT")]
     pub output: String,
 
+    /// Records --stream and --reorder-buffer write between flushes of the output to disk;
+    /// fewer lose less in a crash but write more slowly
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_FLUSH_EVERY, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), env = "SACHEM_FLUSH_EVERY")]
+    pub flush_every: usize,
+
+    /// Comma-separated output formats; several share the --output stem
+    #[arg(
+        short,
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "csv",
+        env = "SACHEM_FORMAT"
+    )]
+    pub format: Vec<OutputFormat>,
+
+    /// Write records to one file per taxon instead of a single file, named by the key
+    /// after the --output stem; records without a recognizable taxon go to "unknown"
+    #[arg(long, value_enum, value_name = "KEY", conflicts_with_all = ["reorder_buffer", "rescrape_older_than", "refresh_status", "id_index"], env = "SACHEM_SPLIT_BY")]
+    pub split_by: Option<SplitBy>,
+
+    /// Drop records with fewer than this many page fields filled, without marking them missing
+    #[arg(long, default_value = "0", env = "SACHEM_MIN_FIELDS")]
+    pub min_fields: usize,
+
+    /// Write CSV output with a UTF-8 BOM and CRLF line endings for Excel
+    #[arg(long, env = "SACHEM_EXCEL_CSV")]
+    pub excel_csv: bool,
+
+    /// Leave columns that are empty in every record out of the CSV output
+    #[arg(long, env = "SACHEM_COMPACT_CSV")]
+    pub compact_csv: bool,
+
+    /// Write CSV output without a header row, data rows only, in schema column order
+    #[arg(long, visible_alias = "csv-no-headers", conflicts_with_all = ["compact_csv", "rescrape_older_than", "refresh_status", "id_index"], env = "SACHEM_NO_HEADERS")]
+    pub no_headers: bool,
+
+    /// What empty string fields are written as in CSV and NDJSON output, e.g. NA or \N
+    #[arg(
+        long,
+        value_name = "TEXT",
+        default_value = "",
+        env = "SACHEM_NULL_VALUE"
+    )]
+    pub null_value: String,
+
+    /// Sort saved records by sighting ID, records without an ID last
+    #[arg(long, env = "SACHEM_SORT_OUTPUT")]
+    pub sort_output: bool,
+
+    /// Write records to --output as they finish, in dispatch order, holding at most N
+    /// that finished ahead of an earlier sighting
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = [
+            "retry_missing", "retry_missing_on_finish", "rescrape_older_than", "refresh_status",
+            "report_missing_ranges", "head_check", "sort_output", "compact_csv", "id_index"
+        ],
+        env = "SACHEM_REORDER_BUFFER"
+    )]
+    pub reorder_buffer: Option<u64>,
+
+    /// Write each record of a range scrape to the CSV --output once every earlier ID has
+    /// finished, holding at most --concurrent out of order
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "reorder_buffer", "sitemap", "ids", "resume", "split_by", "retry_missing",
+            "retry_missing_on_finish", "rescrape_older_than", "refresh_status",
+            "report_missing_ranges", "head_check", "sort_output", "compact_csv", "id_index"
+        ],
+        env = "SACHEM_STREAM"
+    )]
+    pub stream: bool,
+
+    /// Also save the records into this SQLite database, replacing rows by sighting ID
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["reorder_buffer", "stream", "head_check", "report_missing_ranges"], env = "SACHEM_DB")]
+    pub db: Option<String>,
+
+    /// Log the run in the runs table of --db, with its start and end time, range and
+    /// counts, and tie each row it saves to it
+    #[cfg(feature = "sqlite")]
+    #[arg(long, requires = "db", env = "SACHEM_RECORD_RUN")]
+    pub record_run: bool,
+
+    /// Which record to keep when --db already has a row with a record's sighting ID
+    #[cfg(feature = "sqlite")]
+    #[arg(
+        long,
+        value_enum,
+        default_value = "replace",
+        requires = "db",
+        env = "SACHEM_ON_CONFLICT"
+    )]
+    pub on_conflict: ConflictPolicy,
+
+    /// Only probe which IDs exist with HEAD requests, writing the existing IDs to this file
+    #[arg(long, env = "SACHEM_HEAD_CHECK")]
+    pub head_check: Option<String>,
+
+    /// Directory to save the raw HTML of pages that fail to parse
+    #[arg(long, env = "SACHEM_DUMP_FAILED_HTML")]
+    pub dump_failed_html: Option<String>,
+
+    /// Archive the raw request and response behind every page read to this gzipped WARC file
+    #[arg(long, value_name = "FILE", env = "SACHEM_WARC")]
+    pub warc: Option<String>,
+
+    /// Megabytes a WARC file may reach before the archive continues in a numbered file
+    #[arg(long, value_name = "MB", default_value = "1000", value_parser = clap::value_parser!(u64).range(1..), requires = "warc", env = "SACHEM_WARC_MAX_SIZE")]
+    pub warc_max_size: u64,
+
+    /// Comma-separated specimen types to keep, e.g. photograph; other records are dropped
+    #[arg(long, value_name = "TYPES", value_delimiter = ',', value_parser = parse_specimen_type, env = "SACHEM_SPECIMEN_TYPE")]
+    pub specimen_type: Vec<SpecimenType>,
+
+    /// Keep records with neither a common nor a scientific name
+    #[arg(long, env = "SACHEM_INCLUDE_EMPTY")]
+    pub include_empty: bool,
+
+    /// Keep every sighting row on a page as its own record, not only the first
+    #[arg(long, env = "SACHEM_ALL_ROWS")]
+    pub all_rows: bool,
+
+    /// Unix domain socket to stream progress and scraped records to as JSON lines
+    #[arg(long, value_name = "PATH", env = "SACHEM_CONTROL_SOCKET")]
+    pub control_socket: Option<String>,
+
+    /// Serve a live dashboard of the run's progress on this port, at / and /status.json
+    #[arg(long, value_name = "PORT", env = "SACHEM_DASHBOARD_PORT")]
+    pub dashboard_port: Option<u16>,
+
+    /// Address to serve --dashboard-port on; the dashboard has no authentication, so give
+    /// 0.0.0.0 or another interface only on a trusted network
+    #[arg(long, value_name = "ADDR", default_value_t = DEFAULT_DASHBOARD_BIND, requires = "dashboard_port", env = "SACHEM_DASHBOARD_BIND")]
+    pub dashboard_bind: IpAddr,
+
+    /// Write run-start, id-success, id-failure and run-end events as JSON lines to this
+    /// file (e.g. /dev/fd/3), or to stderr when no file is given
+    #[arg(long, value_name = "FILE", num_args = 0..=1, env = "SACHEM_EVENTS")]
+    pub events: Option<Option<String>>,
+
+    /// Exit with an error at the end of the run if any page raised a parse warning
+    #[arg(long, env = "SACHEM_STRICT")]
+    pub strict: bool,
+
+    /// Conservative, site-friendly settings: low concurrency, long delays, patient retries
+    #[arg(long, conflicts_with = "aggressive", env = "SACHEM_POLITE")]
+    pub polite: bool,
+
+    /// Fast settings for trusted mirrors: high concurrency, short delays
+    #[arg(long, env = "SACHEM_AGGRESSIVE")]
+    pub aggressive: bool,
+
+    /// indicatif template for the progress bar, e.g. "{pos}/{len} {per_sec}"
+    #[arg(long, value_name = "TEMPLATE", env = "SACHEM_PROGRESS_TEMPLATE")]
+    pub progress_template: Option<String>,
+
+    /// Trim leading and trailing whitespace from every string field before saving
+    #[arg(long, env = "SACHEM_TRIM_WHITESPACE_COLUMNS")]
+    pub trim_whitespace_columns: bool,
+
+    /// Add a content_hash column: a SHA-256 of each record's fields, leaving out url and
+    /// scraped_at, so an unchanged sighting hashes the same on every scrape
+    #[arg(long, env = "SACHEM_WITH_HASH")]
+    pub with_hash: bool,
+
     /// Enable verbose logging
-    #[arg(short, long)]
+    #[arg(short, long, env = "SACHEM_VERBOSE")]
     pub verbose: bool,
 }
+
+/// Parse a "Key: Value" header given on the command line
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"Key: Value\", got \"{}\"", header))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|e| format!("invalid header name \"{}\": {}", name.trim(), e))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|e| format!("invalid value for header {}: {}", name, e))?;
+    Ok((name, value))
+}
+
+/// Parse an Accept-Language value such as "en-US, en;q=0.9"
+fn parse_accept_language(languages: &str) -> Result<HeaderValue, String> {
+    let languages = languages.trim();
+    if languages.is_empty() {
+        return Err("expected languages such as \"en-US, en;q=0.9\"".to_string());
+    }
+    HeaderValue::from_str(languages).map_err(|e| format!("invalid Accept-Language: {}", e))
+}
+
+/// Parse an age such as "30d", "12h", "90m", "45s" or "2w"
+fn parse_age(age: &str) -> Result<Duration, String> {
+    let age = age.trim();
+    let split = age.len() - age.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (count, unit) = age.split_at(split);
+    let count: u64 = count
+        .parse()
+        .map_err(|_| format!("expected a number and unit like \"30d\", got \"{}\"", age))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "unknown unit \"{}\", expected s, m, h, d or w",
+                unit
+            ));
+        }
+    };
+    count
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("age \"{}\" is too large", age))
+}
+
+fn parse_specimen_type(specimen_type: &str) -> Result<SpecimenType, String> {
+    SpecimenType::parse(specimen_type).ok_or_else(|| "specimen type cannot be empty".to_string())
+}
+
+/// Parse a share between 0 and 1, such as "0.05"
+fn parse_fraction(fraction: &str) -> Result<f64, String> {
+    match fraction.trim().parse::<f64>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(format!(
+            "expected a number from 0 to 1, got \"{}\"",
+            fraction
+        )),
+    }
+}
+
+/// Parse a latency multiplier, which has to be over 1 to mean a slowdown
+fn parse_slowdown_multiplier(multiplier: &str) -> Result<f64, String> {
+    match multiplier.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 1.0 => Ok(value),
+        _ => Err(format!(
+            "expected a number greater than 1, got \"{}\"",
+            multiplier
+        )),
+    }
+}
+
+/// Read a separator given on the command line, expanding `\t` to a tab
+fn parse_separator(sep: &str) -> Result<String, String> {
+    if sep.is_empty() {
+        return Err("separator cannot be empty".to_string());
+    }
+    Ok(sep.replace("\\t", "\t"))
+}
+
+impl Args {
+    /// Headers from every --header flag, then --accept-language; repeated keys keep all
+    /// their values
+    pub fn header_map(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            headers.append(name, value.clone());
+        }
+        if let Some(languages) = &self.accept_language {
+            headers.insert(ACCEPT_LANGUAGE, languages.clone());
+        }
+        headers
+    }
+
+    /// The missing file, with the --missing-prefix label if given
+    pub fn missing_file(&self) -> String {
+        match &self.missing_prefix {
+            Some(prefix) => prefix_file_name(&self.missing, prefix),
+            None => self.missing.clone(),
+        }
+    }
+
+    /// The dead-letter file, with the --missing-prefix label if given
+    pub fn dead_letter_file(&self) -> String {
+        match &self.missing_prefix {
+            Some(prefix) => prefix_file_name(&self.dead_letter, prefix),
+            None => self.dead_letter.clone(),
+        }
+    }
+
+    /// Apply the --polite or --aggressive preset to every setting not given explicitly
+    pub fn apply_preset(&mut self, matches: &ArgMatches) {
+        // Polite keeps concurrency fixed, so nothing raises it past the preset's, and backs
+        // off at a smaller slowdown
+        let (delay, concurrent, retries, concurrency_auto, soft_limit_multiplier) = if self.polite {
+            (2000, 2, 5, false, 2.0)
+        } else if self.aggressive {
+            (100, 20, 3, true, 3.0)
+        } else {
+            return;
+        };
+
+        // Options without a default have no value source until given
+        let is_default = |id| {
+            matches
+                .value_source(id)
+                .is_none_or(|source| source == ValueSource::DefaultValue)
+        };
+        if is_default("delay") {
+            self.delay = delay;
+        }
+        if is_default("concurrent") {
+            self.concurrent = concurrent;
+        }
+        if is_default("retries") {
+            self.retries = retries;
+        }
+        if is_default("concurrency_auto") {
+            self.concurrency_auto = concurrency_auto;
+        }
+        if is_default("soft_limit_multiplier") {
+            self.soft_limit_multiplier = Some(soft_limit_multiplier);
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Merge several CSV outputs into one file deduplicated by sighting ID
+    Merge {
+        /// Input CSV files; later files are incoming records for --on-conflict
+        #[arg(required = true)]
+        inputs: Vec<String>,
+
+        /// Output CSV filename
+        #[arg(short, long, default_value = "sightings.csv")]
+        output: String,
+
+        /// Comma-separated fields whose combined values identify a duplicate
+        #[arg(long, value_delimiter = ',', default_value = "sighting_id")]
+        dedupe_by: Vec<String>,
+
+        /// Which record to keep when several share a dedup key
+        #[arg(
+            short = 'k',
+            long,
+            alias = "keep",
+            value_enum,
+            default_value = "replace"
+        )]
+        on_conflict: ConflictPolicy,
+    },
+    /// Check a saved CSV for empty core fields, unparseable dates and missing or duplicate IDs
+    Validate {
+        /// CSV file to check
+        input: String,
+    },
+    /// Load CSV outputs into a SQLite database, deduplicated by sighting ID
+    #[cfg(feature = "sqlite")]
+    Import {
+        /// Input CSV files; a later record replaces an earlier one with the same ID
+        #[arg(required = true)]
+        inputs: Vec<String>,
+
+        /// SQLite database to write, created if it does not exist
+        #[arg(long, default_value = "sightings.db")]
+        db: String,
+    },
+    /// Write the records in a SQLite database to a CSV file, sorted by sighting ID
+    #[cfg(feature = "sqlite")]
+    Export {
+        /// SQLite database to read
+        db: String,
+
+        /// Output CSV filename
+        #[arg(short, long, default_value = "sightings.csv")]
+        output: String,
+    },
+    /// Compare two outputs by sighting ID: added and removed sightings, and changed fields
+    Diff {
+        /// Older CSV or NDJSON output
+        old: String,
+
+        /// Newer CSV or NDJSON output
+        new: String,
+
+        /// Also write the differences as JSON to this file, or to stdout for -
+        #[arg(long, value_name = "FILE")]
+        json: Option<String>,
+    },
+    /// Print the summary of a saved CSV or NDJSON output without scraping
+    Summarize {
+        /// CSV or NDJSON output to summarize, read one record at a time
+        input: String,
+
+        /// Also write the summary as JSON to this file, or to stdout for -
+        #[arg(long, value_name = "FILE")]
+        json: Option<String>,
+
+        /// Count each checklist region on its own, for NDJSON and JSON input that lists them
+        #[arg(long)]
+        split_regions: bool,
+    },
+    /// Count sightings by species, region or month in a CSV matrix, e.g. species by region
+    Pivot {
+        /// CSV or NDJSON output to count, read one record at a time
+        input: String,
+
+        /// What each row counts sightings for
+        #[arg(long, value_enum, default_value = "species")]
+        rows: PivotField,
+
+        /// What each column counts sightings for
+        #[arg(long, value_enum, default_value = "region")]
+        cols: PivotField,
+
+        /// Columns kept, those with the most sightings; the rest are summed into "other"
+        #[arg(long, default_value = "20", value_parser = clap::value_parser!(u64).range(1..))]
+        max_cols: u64,
+
+        /// Output CSV filename, or - for stdout
+        #[arg(short, long, default_value = "-")]
+        output: String,
+    },
+    /// Find the highest existing sighting ID by probing with HEAD requests, and print it
+    FindMax {
+        /// Lowest ID to search from; a sighting must exist within --gap of it
+        #[arg(long, default_value = "1")]
+        low: u64,
+
+        /// First guess at the highest ID; raised automatically when sightings exist above it
+        #[arg(long, default_value = "1000000")]
+        high: u64,
+
+        /// Consecutive missing IDs that mark the end, bridging deleted sightings
+        #[arg(long, default_value = "20", value_parser = clap::value_parser!(u64).range(1..))]
+        gap: u64,
+
+        /// Site to probe, e.g. a mirror
+        #[arg(long, default_value = DEFAULT_BASE_URL, env = "SACHEM_BASE_URL")]
+        base_url: String,
+
+        /// Extra header sent with every probe, as "Key: Value" (repeatable)
+        #[arg(long = "header", value_name = "KEY: VALUE", value_parser = parse_header)]
+        headers: Vec<(HeaderName, HeaderValue)>,
+
+        /// Base delay between probes in milliseconds
+        #[arg(short, long, default_value = "500", env = "SACHEM_DELAY")]
+        delay: u64,
+
+        /// Maximum number of retry attempts per probe
+        #[arg(short, long, default_value = "3", env = "SACHEM_RETRIES")]
+        retries: u32,
+    },
+    /// Parse saved pages again without fetching them: a --warc archive or a directory of
+    /// {id}.html files, written as a CSV with the current parser and selectors
+    #[command(visible_alias = "parse-dir")]
+    Replay {
+        /// WARC files (.warc or .warc.gz) or directories of {id}.html files; a sighting
+        /// captured more than once keeps its last capture
+        #[arg(required = true)]
+        inputs: Vec<String>,
+
+        /// Output CSV filename, or - for stdout
+        #[arg(short, long, default_value = "sightings.csv")]
+        output: String,
+
+        /// Page layout to parse the captures as
+        #[arg(long, value_enum, default_value = "v1")]
+        parser_version: ParserVersion,
+
+        /// TOML file mapping record fields to CSS selectors, overriding the built-in ones
+        #[arg(long, value_name = "TOML")]
+        selectors: Option<String>,
+
+        /// Separator between the values of multi-valued fields such as checklist_regions
+        #[arg(long, default_value = DEFAULT_MULTI_VALUE_SEP, value_parser = parse_separator)]
+        multi_value_sep: String,
+
+        /// CSV of scientific_name,common_name pairs; mapped species get the canonical common name
+        #[arg(long, value_name = "FILE")]
+        name_map: Option<String>,
+
+        /// Keep every sighting row on a page as its own record, not only the first
+        #[arg(long)]
+        all_rows: bool,
+    },
+    /// Print the output schema as JSON, or check an existing CSV's header against it
+    Schema {
+        /// CSV file whose header is compared with the current schema
+        #[arg(long)]
+        validate_csv: Option<String>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let (name, value) = parse_header("Accept-Language: en-US, en;q=0.9").unwrap();
+        assert_eq!(name, "accept-language");
+        assert_eq!(value, "en-US, en;q=0.9");
+
+        assert!(parse_header("no-colon").is_err());
+        assert!(parse_header("bad name: value").is_err());
+        assert!(parse_header("X-Key: bad\nvalue").is_err());
+    }
+
+    #[test]
+    fn test_accept_language_replaces_header() {
+        let args = Args::parse_from([
+            "butterfly-scraper",
+            "--max",
+            "10",
+            "--header",
+            "Accept-Language: de",
+            "--header",
+            "X-Api-Key: abc",
+            "--accept-language",
+            "fr-CA, fr;q=0.9",
+        ]);
+        let headers = args.header_map();
+
+        let languages: Vec<_> = headers.get_all(ACCEPT_LANGUAGE).iter().collect();
+        assert_eq!(languages, vec!["fr-CA, fr;q=0.9"]);
+        assert_eq!(headers["x-api-key"], "abc");
+        assert!(parse_accept_language("en\nUS").is_err());
+        assert!(parse_accept_language(" ").is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d").unwrap(), Duration::from_secs(30 * 86400));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 3600));
+        assert_eq!(parse_age("2w").unwrap(), Duration::from_secs(14 * 86400));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+
+    #[test]
+    fn test_parse_separator() {
+        assert_eq!(parse_separator(" | ").unwrap(), " | ");
+        assert_eq!(parse_separator("\\t").unwrap(), "\t");
+        assert!(parse_separator("").is_err());
+    }
+
+    #[test]
+    fn test_merge_keeps_its_short_and_old_flags() {
+        let on_conflict = |argv: &[&str]| {
+            let args = Args::parse_from(
+                ["butterfly-scraper", "merge", "a.csv", "b.csv"]
+                    .iter()
+                    .chain(argv),
+            );
+            match args.command {
+                Some(Command::Merge { on_conflict, .. }) => on_conflict,
+                _ => panic!("not a merge"),
+            }
+        };
+        assert_eq!(on_conflict(&[]), ConflictPolicy::Replace);
+        assert_eq!(
+            on_conflict(&["-k", "keep-newer"]),
+            ConflictPolicy::KeepNewer
+        );
+        assert_eq!(on_conflict(&["-k", "first"]), ConflictPolicy::KeepExisting);
+        assert_eq!(on_conflict(&["--keep", "last"]), ConflictPolicy::Replace);
+    }
+}
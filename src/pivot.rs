@@ -0,0 +1,192 @@
+use crate::record::SightingRecord;
+use crate::util::parse_date;
+use clap::ValueEnum;
+use csv::Writer;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+/// Header of the column counting sightings in columns past the cap
+const OTHER_COLUMN: &str = "other";
+
+/// What a pivot's rows or columns group sightings by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PivotField {
+    /// Scientific name, or the common name when there is none
+    Species,
+    /// Checklist regions
+    Region,
+    /// Observation month as YYYY-MM
+    Month,
+}
+
+impl PivotField {
+    /// The group a record falls in, or `None` when it has no value to group by
+    fn key(self, record: &SightingRecord) -> Option<String> {
+        let key = match self {
+            PivotField::Species if !record.scientific_name.is_empty() => {
+                record.scientific_name.clone()
+            }
+            PivotField::Species => record.common_name.clone(),
+            PivotField::Region => record.checklist_regions.clone(),
+            PivotField::Month => parse_date(&record.observation_date)?
+                .format("%Y-%m")
+                .to_string(),
+        };
+        (!key.is_empty()).then_some(key)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PivotField::Species => "species",
+            PivotField::Region => "region",
+            PivotField::Month => "month",
+        }
+    }
+}
+
+/// Sighting counts by a row and a column field, built one record at a time. Records
+/// without a value for either field are left out.
+#[derive(Debug)]
+pub struct Pivot {
+    rows: PivotField,
+    cols: PivotField,
+    counts: BTreeMap<String, HashMap<String, u64>>,
+    col_totals: HashMap<String, u64>,
+}
+
+impl Pivot {
+    pub fn new(rows: PivotField, cols: PivotField) -> Self {
+        Self {
+            rows,
+            cols,
+            counts: BTreeMap::new(),
+            col_totals: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, record: &SightingRecord) {
+        let (Some(row), Some(col)) = (self.rows.key(record), self.cols.key(record)) else {
+            return;
+        };
+        *self
+            .counts
+            .entry(row)
+            .or_default()
+            .entry(col.clone())
+            .or_default() += 1;
+        *self.col_totals.entry(col).or_default() += 1;
+    }
+
+    /// The `max_cols` columns with the most sightings, ties broken by name, in name order
+    /// (chronological for months). Whether any columns were left out comes second.
+    fn columns(&self, max_cols: usize) -> (Vec<&String>, bool) {
+        let mut columns: Vec<(&String, &u64)> = self.col_totals.iter().collect();
+        columns.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        let truncated = columns.len() > max_cols;
+        let mut kept: Vec<&String> = columns
+            .into_iter()
+            .take(max_cols)
+            .map(|(column, _)| column)
+            .collect();
+        kept.sort();
+        (kept, truncated)
+    }
+
+    /// Write the matrix as CSV: a row per group in name order, a column per kept column
+    /// group, then `other` for the rest when there are more than `max_cols`, and `total`
+    pub fn write_csv<W: Write>(&self, writer: W, max_cols: usize) -> csv::Result<()> {
+        let (columns, truncated) = self.columns(max_cols);
+        let mut writer = Writer::from_writer(writer);
+
+        let mut header = vec![self.rows.name()];
+        header.extend(columns.iter().map(|column| column.as_str()));
+        if truncated {
+            header.push(OTHER_COLUMN);
+        }
+        header.push("total");
+        writer.write_record(&header)?;
+
+        for (row, counts) in &self.counts {
+            let mut line = vec![row.clone()];
+            let mut kept = 0;
+            for column in &columns {
+                let count = counts.get(*column).copied().unwrap_or(0);
+                kept += count;
+                line.push(count.to_string());
+            }
+            let total: u64 = counts.values().sum();
+            if truncated {
+                line.push((total - kept).to_string());
+            }
+            line.push(total.to_string());
+            writer.write_record(&line)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(species: &str, date: &str, region: &str) -> SightingRecord {
+        SightingRecord {
+            scientific_name: species.to_string(),
+            observation_date: date.to_string(),
+            checklist_regions: region.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn pivot_csv(pivot: &Pivot, max_cols: usize) -> String {
+        let mut bytes = Vec::new();
+        pivot.write_csv(&mut bytes, max_cols).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_pivot_caps_columns_into_other() {
+        let mut pivot = Pivot::new(PivotField::Species, PivotField::Region);
+        for record in [
+            record("Danaus plexippus", "2024-06-01", "Texas"),
+            record("Danaus plexippus", "2024-06-03", "Texas"),
+            record("Danaus plexippus", "2024-07-04", "Maine"),
+            record("Actias luna", "2024-06-12", "Ontario"),
+            record("Actias luna", "2024-06-15", "Texas"),
+            record("Vanessa cardui", "2024-05-09", ""),
+        ] {
+            pivot.add(&record);
+        }
+
+        assert_eq!(
+            pivot_csv(&pivot, 2),
+            "species,Maine,Texas,other,total\n\
+             Actias luna,0,1,1,2\n\
+             Danaus plexippus,1,2,0,3\n"
+        );
+        assert_eq!(
+            pivot_csv(&pivot, 10),
+            "species,Maine,Ontario,Texas,total\n\
+             Actias luna,0,1,1,2\n\
+             Danaus plexippus,1,0,2,3\n"
+        );
+    }
+
+    #[test]
+    fn test_pivot_by_month() {
+        let mut pivot = Pivot::new(PivotField::Region, PivotField::Month);
+        for record in [
+            record("Danaus plexippus", "07/04/2024", "Maine"),
+            record("Actias luna", "2024-06-12", "Maine"),
+            record("Actias luna", "June 15, 2024", "Maine"),
+            record("Actias luna", "unknown", "Maine"),
+        ] {
+            pivot.add(&record);
+        }
+        assert_eq!(
+            pivot_csv(&pivot, 10),
+            "region,2024-06,2024-07,total\nMaine,2,1,3\n"
+        );
+    }
+}
@@ -1,6 +1,10 @@
+use crate::schema::record_schema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SightingRecord {
     pub sighting_id: Option<u64>,
     pub url: Option<String>,
@@ -14,23 +18,359 @@ pub struct SightingRecord {
     pub verified_by: String,
     pub verified_date: String,
     pub checklist_regions: String,
+    /// Each checklist region on its own, as `checklist_regions` joins them. Only JSON
+    /// output carries it, so it is empty for records read back from a CSV.
+    #[serde(default, skip_serializing)]
+    pub checklist_regions_list: Vec<String>,
+    // Absent from CSVs written before the column was added
+    #[serde(default)]
+    pub locality: String,
+    /// Absolute URL of the submitter's profile, empty when the name is not linked
+    #[serde(default)]
+    pub submitted_by_url: String,
+    /// Absolute URL of the verifier's profile, empty when the name is not linked
+    #[serde(default)]
+    pub verified_by_url: String,
+    /// Date the sighting was submitted to the site, which can be well after it was observed
+    #[serde(default)]
+    pub submitted_date: String,
+    /// The page's note on how precise the sighting's coordinates are, if it has one
+    #[serde(default)]
+    pub coordinate_precision: Option<String>,
+    /// Decimal degrees north, when the page gives a coordinate that reads as one
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    /// Decimal degrees east, when the page gives a coordinate that reads as one
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    /// Whether the page marks the location as obscured, e.g. to protect a sensitive
+    /// species, so it should not be mapped as an exact point
+    #[serde(default)]
+    pub location_obscured: bool,
+    /// Photos shown with the sighting, counted from the page without downloading them
+    #[serde(default)]
+    pub photo_count: u32,
+    /// Whether `photo_count` is above zero, for filtering photographed sightings
+    #[serde(default)]
+    pub has_photo: bool,
+    /// SHA-256 of the record's content, set with `--with-hash`, so unchanged sightings
+    /// can be told apart from changed ones without comparing every field
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// RFC 3339 time the page was fetched; empty in CSVs written before the column was added
+    #[serde(default)]
+    pub scraped_at: String,
+    /// Tags carried over from the ID file, written as `meta_<name>` columns after the rest
+    #[serde(skip)]
+    pub extra: Tags,
 }
 
-impl Default for SightingRecord {
-    fn default() -> Self {
-        Self {
-            sighting_id: None,
-            url: None,
-            common_name: String::new(),
-            scientific_name: String::new(),
-            species_link: String::new(),
-            observation_date: String::new(),
-            submitted_by: String::new(),
-            specimen_type: String::new(),
-            status: String::new(),
-            verified_by: String::new(),
-            verified_date: String::new(),
-            checklist_regions: String::new(),
+/// Tag names and values attached to a sighting ID
+pub type Tags = BTreeMap<String, String>;
+
+/// Prefix of the output columns holding a record's `extra` tags
+pub const META_PREFIX: &str = "meta_";
+
+/// Columns left out of `content_hash`: where and when the page was fetched, and the
+/// hash itself. Tags are left out too, since they aren't schema columns.
+const UNHASHED_FIELDS: [&str; 3] = ["url", "content_hash", "scraped_at"];
+
+/// What a sighting is based on, from its free-text `specimen_type`. Spellings of the
+/// common types map to one canonical name; anything else is kept as written.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SpecimenType {
+    Photograph,
+    Specimen,
+    Sighting,
+    Other(String),
+}
+
+impl SpecimenType {
+    /// Recognize a specimen type, ignoring case and surrounding space, or `None` when empty
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let specimen_type = match text.to_lowercase().as_str() {
+            "" => return None,
+            "photograph" | "photo" | "photographed" => Self::Photograph,
+            "specimen" | "collected specimen" => Self::Specimen,
+            "sighting" | "sight record" | "observation" => Self::Sighting,
+            _ => Self::Other(text.to_string()),
+        };
+        Some(specimen_type)
+    }
+
+    /// The name written to the output
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Photograph => "Photograph",
+            Self::Specimen => "Specimen",
+            Self::Sighting => "Sighting",
+            Self::Other(text) => text,
+        }
+    }
+}
+
+impl fmt::Display for SpecimenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl SightingRecord {
+    /// Names and values of the text fields extracted from the page, in column order
+    pub fn text_fields(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("common_name", &self.common_name),
+            ("scientific_name", &self.scientific_name),
+            ("species_link", &self.species_link),
+            ("observation_date", &self.observation_date),
+            ("submitted_by", &self.submitted_by),
+            ("specimen_type", &self.specimen_type),
+            ("status", &self.status),
+            ("verified_by", &self.verified_by),
+            ("verified_date", &self.verified_date),
+            ("checklist_regions", &self.checklist_regions),
+            ("locality", &self.locality),
+            ("submitted_by_url", &self.submitted_by_url),
+            ("verified_by_url", &self.verified_by_url),
+            ("submitted_date", &self.submitted_date),
+        ]
+    }
+
+    /// `text_fields`, for changing them in place
+    fn text_fields_mut(&mut self) -> Vec<(&'static str, &mut String)> {
+        vec![
+            ("common_name", &mut self.common_name),
+            ("scientific_name", &mut self.scientific_name),
+            ("species_link", &mut self.species_link),
+            ("observation_date", &mut self.observation_date),
+            ("submitted_by", &mut self.submitted_by),
+            ("specimen_type", &mut self.specimen_type),
+            ("status", &mut self.status),
+            ("verified_by", &mut self.verified_by),
+            ("verified_date", &mut self.verified_date),
+            ("checklist_regions", &mut self.checklist_regions),
+            ("locality", &mut self.locality),
+            ("submitted_by_url", &mut self.submitted_by_url),
+            ("verified_by_url", &mut self.verified_by_url),
+            ("submitted_date", &mut self.submitted_date),
+        ]
+    }
+
+    /// Strip leading and trailing whitespace from every string value, tags included
+    pub fn trim_whitespace(&mut self) {
+        let trim = |value: &mut String| {
+            let trimmed = value.trim();
+            if trimmed.len() != value.len() {
+                *value = trimmed.to_string();
+            }
+        };
+        for (_, value) in self.text_fields_mut() {
+            trim(value);
+        }
+        trim(&mut self.scraped_at);
+        self.checklist_regions_list.iter_mut().for_each(trim);
+        if let Some(note) = &mut self.coordinate_precision {
+            trim(note);
+        }
+        self.extra.values_mut().for_each(trim);
+    }
+
+    /// Fill the text fields this record left empty with `other`'s values, so rows for the
+    /// same sighting spread over several pages add up to one record
+    pub fn fill_empty_from(&mut self, other: &SightingRecord) {
+        for ((_, value), (_, theirs)) in self.text_fields_mut().into_iter().zip(other.text_fields())
+        {
+            if value.is_empty() && !theirs.is_empty() {
+                *value = theirs.to_string();
+            }
+        }
+        if self.checklist_regions_list.is_empty() {
+            self.checklist_regions_list = other.checklist_regions_list.clone();
+        }
+        if self.coordinate_precision.is_none() {
+            self.coordinate_precision = other.coordinate_precision.clone();
+        }
+        // The coordinates are a pair, taken together from the row that has them
+        if self.latitude.is_none() && self.longitude.is_none() {
+            self.latitude = other.latitude;
+            self.longitude = other.longitude;
+        }
+        self.location_obscured |= other.location_obscured;
+        if self.photo_count == 0 {
+            self.photo_count = other.photo_count;
+            self.has_photo = other.has_photo;
+        }
+    }
+
+    /// Whether every text field from the page is empty or only whitespace, so the record
+    /// holds nothing but its ID
+    pub fn is_blank(&self) -> bool {
+        self.text_fields()
+            .iter()
+            .all(|(_, value)| value.trim().is_empty())
+    }
+
+    /// Whether `name` is one of the always-present string columns, which hold an empty string
+    /// rather than null when there is no value
+    pub fn is_string_field(name: &str) -> bool {
+        name == "scraped_at"
+            || Self::default()
+                .text_fields()
+                .iter()
+                .any(|(field, _)| *field == name)
+    }
+
+    /// The record's specimen type, or `None` when the page gave none
+    pub fn specimen_type(&self) -> Option<SpecimenType> {
+        SpecimenType::parse(&self.specimen_type)
+    }
+
+    /// Whether neither species name was extracted, which usually means the parse failed
+    pub fn is_sparse(&self) -> bool {
+        self.common_name.is_empty() && self.scientific_name.is_empty()
+    }
+
+    /// Number of text fields extracted from the page that are not empty
+    pub fn filled_fields(&self) -> usize {
+        self.text_fields()
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .count()
+    }
+
+    /// Lowercase hex SHA-256 over every schema column but `UNHASHED_FIELDS`, in schema
+    /// order, each as its name, a NUL, its CSV value and another NUL. Empty and null
+    /// columns are skipped. A rescrape of an unchanged page hashes the same, since the fetch
+    /// time is not part of it, and so does one after a nullable column is added.
+    pub fn compute_content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for field in record_schema() {
+            if UNHASHED_FIELDS.contains(&field.name) {
+                continue;
+            }
+            let value = self.field_value(field.name).unwrap_or_default();
+            if value.is_empty() {
+                continue;
+            }
+            hasher.update(field.name);
+            hasher.update([0]);
+            hasher.update(value);
+            hasher.update([0]);
         }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Value of a field by column name as it appears in the CSV, or `None` for an unknown name
+    pub fn field_value(&self, name: &str) -> Option<String> {
+        match name {
+            "sighting_id" => Some(
+                self.sighting_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+            ),
+            "url" => Some(self.url.clone().unwrap_or_default()),
+            "coordinate_precision" => Some(self.coordinate_precision.clone().unwrap_or_default()),
+            "latitude" => Some(self.latitude.map(|x| x.to_string()).unwrap_or_default()),
+            "longitude" => Some(self.longitude.map(|x| x.to_string()).unwrap_or_default()),
+            "location_obscured" => Some(self.location_obscured.to_string()),
+            "photo_count" => Some(self.photo_count.to_string()),
+            "has_photo" => Some(self.has_photo.to_string()),
+            "content_hash" => Some(self.content_hash.clone().unwrap_or_default()),
+            "scraped_at" => Some(self.scraped_at.clone()),
+            _ if name.starts_with(META_PREFIX) => Some(
+                self.extra
+                    .get(&name[META_PREFIX.len()..])
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            _ => self
+                .text_fields()
+                .into_iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, value)| value.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_specimen_type_spellings() {
+        assert_eq!(
+            SpecimenType::parse(" PHOTO "),
+            Some(SpecimenType::Photograph)
+        );
+        assert_eq!(
+            SpecimenType::parse("Sight record"),
+            Some(SpecimenType::Sighting)
+        );
+        assert_eq!(SpecimenType::parse("  "), None);
+        let other = SpecimenType::parse(" Live adult").unwrap();
+        assert_eq!(other, SpecimenType::Other("Live adult".to_string()));
+        assert_eq!(other.to_string(), "Live adult");
+        assert_eq!(SpecimenType::Specimen.to_string(), "Specimen");
+    }
+
+    #[test]
+    fn test_trim_whitespace_and_blank_records() {
+        let mut record = SightingRecord {
+            sighting_id: Some(7),
+            common_name: " Monarch\n".to_string(),
+            locality: "\t".to_string(),
+            extra: Tags::from([("batch".to_string(), " a ".to_string())]),
+            ..Default::default()
+        };
+        assert!(!record.is_blank());
+        record.trim_whitespace();
+        assert_eq!(record.common_name, "Monarch");
+        assert_eq!(record.locality, "");
+        assert_eq!(record.extra["batch"], "a");
+
+        record.common_name = "  ".to_string();
+        assert!(record.is_blank());
+
+        // Both lists name the same fields in the same order
+        let names: Vec<&str> = record.text_fields().iter().map(|(name, _)| *name).collect();
+        let mut_names: Vec<&str> = record
+            .text_fields_mut()
+            .iter()
+            .map(|(name, _)| *name)
+            .collect();
+        assert_eq!(names, mut_names);
+    }
+
+    #[test]
+    fn test_content_hash_ignores_fetch_details() {
+        let record = SightingRecord {
+            sighting_id: Some(7),
+            url: Some("https://www.butterfliesandmoths.org/sighting_details/7".to_string()),
+            common_name: "Monarch".to_string(),
+            scraped_at: "2025-06-01T12:00:00Z".to_string(),
+            ..Default::default()
+        };
+        let hash = record.compute_content_hash();
+        assert_eq!(hash.len(), 64);
+        // Only the columns with a value are hashed, so a new column left empty changes nothing
+        let filled = "sighting_id\x007\x00common_name\x00Monarch\x00location_obscured\x00false\x00\
+                      photo_count\x000\x00has_photo\x00false\x00";
+        assert_eq!(hash, format!("{:x}", Sha256::digest(filled)));
+
+        let mut rescraped = record.clone();
+        rescraped.url = Some("https://mirror.example.org/sighting_details/7".to_string());
+        rescraped.scraped_at = "2025-07-01T12:00:00Z".to_string();
+        rescraped.content_hash = Some(hash.clone());
+        rescraped.extra = Tags::from([("batch".to_string(), "b".to_string())]);
+        assert_eq!(rescraped.compute_content_hash(), hash);
+
+        rescraped.status = "Verified".to_string();
+        assert_ne!(rescraped.compute_content_hash(), hash);
+        // Moving a value to the next field changes the hash
+        let mut shifted = record.clone();
+        shifted.common_name = String::new();
+        shifted.scientific_name = "Monarch".to_string();
+        assert_ne!(shifted.compute_content_hash(), hash);
     }
 }
@@ -0,0 +1,295 @@
+use crate::output::RecordWriter;
+use crate::record::SightingRecord;
+use log::error;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// Records written between syncs of the output to disk
+pub const DEFAULT_FLUSH_EVERY: usize = 100;
+
+/// Results that finish out of order, released in order of position. A position that
+/// finishes without an item, such as a sighting that failed, still lets later ones go.
+#[derive(Debug)]
+pub struct ReorderBuffer<T> {
+    next: usize,
+    pending: BTreeMap<usize, Option<T>>,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new() -> Self {
+        Self {
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Hand in the result for `position`
+    pub fn insert(&mut self, position: usize, item: Option<T>) {
+        if position >= self.next {
+            self.pending.insert(position, item);
+        }
+    }
+
+    /// Take the items of every position now finished with none before it left unfinished
+    pub fn pop_ready(&mut self) -> Vec<T> {
+        let mut ready = Vec::new();
+        while let Some(item) = self.pending.remove(&self.next) {
+            ready.extend(item);
+            self.next += 1;
+        }
+        ready
+    }
+
+    /// Positions released so far, which is the first position still waiting
+    pub fn released(&self) -> usize {
+        self.next
+    }
+
+    /// Take every item still held, in order, skipping positions that never finished
+    pub fn drain(&mut self) -> Vec<T> {
+        if let Some(&last) = self.pending.keys().next_back() {
+            self.next = last + 1;
+        }
+        std::mem::take(&mut self.pending)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+}
+
+/// What the writer holds between results
+struct OrderedState {
+    /// The records of each sighting, one per row of its page
+    buffer: ReorderBuffer<Vec<SightingRecord>>,
+    writer: RecordWriter,
+    written: usize,
+    /// Records written between syncs of the file
+    flush_every: usize,
+    /// Positions written since progress last took them
+    unreported: usize,
+    /// The first write that failed; nothing more is written after it
+    error: Option<String>,
+}
+
+/// Records written to the output while the run goes, in dispatch order, so the file is
+/// ordered without the run being held in memory. A record that finishes early waits in
+/// a reorder buffer until every position before it has finished. A sighting is started
+/// only once it is within `capacity` positions of the first unwritten one, so the buffer
+/// never holds more than `capacity` records.
+pub struct OrderedOutput {
+    state: Mutex<OrderedState>,
+    released: watch::Sender<usize>,
+    capacity: usize,
+}
+
+impl OrderedOutput {
+    pub fn new(writer: RecordWriter, capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(OrderedState {
+                buffer: ReorderBuffer::new(),
+                writer,
+                written: 0,
+                flush_every: DEFAULT_FLUSH_EVERY,
+                unreported: 0,
+                error: None,
+            }),
+            released: watch::Sender::new(0),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Flush the file and sync it to disk after every `flush_every` records rather than
+    /// every `DEFAULT_FLUSH_EVERY`. Fewer records between syncs lose less in a crash but
+    /// write more slowly.
+    pub fn with_flush_every(self, flush_every: usize) -> Self {
+        self.state.lock().unwrap().flush_every = flush_every.max(1);
+        self
+    }
+
+    /// Wait until `position` is close enough to the first unwritten one to be started
+    pub async fn wait_for_room(&self, position: usize) {
+        let mut released = self.released.subscribe();
+        // The sender lives as long as `self`, so this can't fail
+        let _ = released
+            .wait_for(|&released| position < released + self.capacity)
+            .await;
+    }
+
+    /// Hand in the records for `position`, none for a sighting that failed, writing every
+    /// record it lets go. Returns false once a write has failed.
+    pub fn complete(&self, position: usize, records: Vec<SightingRecord>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let before = state.buffer.released();
+        state.buffer.insert(position, Some(records));
+        let ready = state.buffer.pop_ready().concat();
+        let released = state.buffer.released();
+        let ok = Self::write_all(&mut state, &ready);
+        if ok {
+            state.unreported += released - before;
+        }
+        drop(state);
+        self.released.send_replace(released);
+        ok
+    }
+
+    /// Take the number of positions written since the last call, for a progress bar that
+    /// counts sightings as their records reach the file
+    pub fn take_written_positions(&self) -> u64 {
+        std::mem::take(&mut self.state.lock().unwrap().unreported) as u64
+    }
+
+    /// Write whatever is still held, after sightings that were never started, and sync
+    /// the file. Returns the number of records written.
+    pub fn finish(&self) -> Result<usize, String> {
+        let mut state = self.state.lock().unwrap();
+        let rest = state.buffer.drain().concat();
+        Self::write_all(&mut state, &rest);
+        if state.error.is_none()
+            && let Err(e) = state.writer.sync()
+        {
+            state.error = Some(e.to_string());
+        }
+        match &state.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(state.written),
+        }
+    }
+
+    fn write_all(state: &mut OrderedState, records: &[SightingRecord]) -> bool {
+        for record in records {
+            if state.error.is_some() {
+                break;
+            }
+            match state.writer.write(record) {
+                Ok(()) => state.written += 1,
+                Err(e) => {
+                    error!("Failed to write sighting {:?}: {}", record.sighting_id, e);
+                    state.error = Some(e.to_string());
+                    break;
+                }
+            }
+            if state.written.is_multiple_of(state.flush_every)
+                && let Err(e) = state.writer.sync()
+            {
+                error!("Failed to flush the output: {}", e);
+                state.error = Some(e.to_string());
+            }
+        }
+        state.error.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{OutputFormat, read_csv};
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_reorder_buffer_releases_contiguous_prefix() {
+        let mut buffer = ReorderBuffer::new();
+        buffer.insert(2, Some('c'));
+        buffer.insert(1, None);
+        assert!(buffer.pop_ready().is_empty());
+
+        // A failed position lets the ones after it go
+        buffer.insert(0, Some('a'));
+        assert_eq!(buffer.pop_ready(), vec!['a', 'c']);
+        assert_eq!(buffer.released(), 3);
+
+        // Position 3 never finishes
+        buffer.insert(5, Some('f'));
+        buffer.insert(4, Some('e'));
+        assert!(buffer.pop_ready().is_empty());
+        assert_eq!(buffer.drain(), vec!['e', 'f']);
+        assert_eq!(buffer.released(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_ordered_output_writes_in_dispatch_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("out.csv").to_string_lossy().into_owned();
+        let writer = RecordWriter::create(
+            &filename,
+            OutputFormat::Csv,
+            &BTreeSet::new(),
+            false,
+            true,
+            "",
+        )
+        .unwrap();
+        let output = OrderedOutput::new(writer, 2);
+        let record = |id: u64| {
+            vec![SightingRecord {
+                sighting_id: Some(id),
+                common_name: "Monarch".to_string(),
+                ..Default::default()
+            }]
+        };
+
+        output.wait_for_room(1).await;
+        assert!(output.complete(1, record(11)));
+        assert_eq!(output.take_written_positions(), 0);
+        // Position 2 waits until position 0 is written
+        assert!(
+            tokio::time::timeout(
+                std::time::Duration::from_millis(20),
+                output.wait_for_room(2)
+            )
+            .await
+            .is_err()
+        );
+        assert!(output.complete(0, Vec::new()));
+        assert_eq!(output.take_written_positions(), 2);
+        assert_eq!(output.take_written_positions(), 0);
+        output.wait_for_room(2).await;
+        assert!(output.complete(3, record(13)));
+        assert_eq!(output.finish(), Ok(2));
+
+        let ids: Vec<Option<u64>> = read_csv(&filename)
+            .unwrap()
+            .iter()
+            .map(|record| record.sighting_id)
+            .collect();
+        assert_eq!(ids, vec![Some(11), Some(13)]);
+    }
+
+    #[test]
+    fn test_ordered_output_flushes_every_n_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("out.csv").to_string_lossy().into_owned();
+        let writer = RecordWriter::create(
+            &filename,
+            OutputFormat::Csv,
+            &BTreeSet::new(),
+            false,
+            true,
+            "",
+        )
+        .unwrap();
+        let output = OrderedOutput::new(writer, 4).with_flush_every(2);
+        let record = |id: u64| {
+            vec![SightingRecord {
+                sighting_id: Some(id),
+                ..Default::default()
+            }]
+        };
+        let saved = || -> Vec<Option<u64>> {
+            read_csv(&filename)
+                .unwrap()
+                .iter()
+                .map(|record| record.sighting_id)
+                .collect()
+        };
+
+        assert!(output.complete(0, record(10)));
+        assert!(saved().is_empty());
+        assert!(output.complete(1, record(11)));
+        assert!(output.complete(2, record(12)));
+        // The third record waits in the buffer until the next flush
+        assert_eq!(saved(), vec![Some(10), Some(11)]);
+        assert_eq!(output.finish(), Ok(3));
+        assert_eq!(saved(), vec![Some(10), Some(11), Some(12)]);
+    }
+}
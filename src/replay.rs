@@ -0,0 +1,310 @@
+use crate::names::NameMap;
+use crate::page_parser::{PageParser, parse_html_to_records};
+use crate::record::SightingRecord;
+use crate::scraper::absolute_url;
+use crate::sitemap::sighting_id_from_url;
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::read::MultiGzDecoder;
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// A sighting page saved by an earlier run, to be parsed again without fetching it
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub sighting_id: u64,
+    /// The page's URL, when the capture recorded it
+    pub url: Option<String>,
+    pub html: String,
+    /// RFC 3339 time the page was fetched
+    pub fetched_at: String,
+}
+
+/// Records parsed from captures, and the IDs whose captured page held no sighting
+#[derive(Debug, Default)]
+pub struct Replayed {
+    pub records: Vec<SightingRecord>,
+    pub unparsed: Vec<u64>,
+}
+
+/// Read the sighting pages in a WARC file written by `--warc`, gzipped or not, or in a
+/// directory of `{id}.html` files such as `--dump-failed-html` writes
+pub fn read_captures(input: &str) -> Result<Vec<Capture>, Box<dyn std::error::Error>> {
+    if Path::new(input).is_dir() {
+        read_html_dir(input)
+    } else {
+        read_warc(input)
+    }
+}
+
+/// The 2xx responses for sighting pages in a WARC file. Following pages of a paginated
+/// sighting, whose URLs carry a query, are skipped.
+fn read_warc(filename: &str) -> Result<Vec<Capture>, Box<dyn std::error::Error>> {
+    let file = File::open(filename)?;
+    let reader: Box<dyn Read> = if filename.ends_with(".gz") {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut reader = BufReader::new(reader);
+
+    let mut captures = Vec::new();
+    while let Some(record) = read_warc_record(&mut reader)? {
+        if record.header("WARC-Type") != Some("response") {
+            continue;
+        }
+        let Some(url) = record.header("WARC-Target-URI") else {
+            continue;
+        };
+        let Some(sighting_id) = sighting_id_from_url(url) else {
+            continue;
+        };
+        if url.contains('?') {
+            continue;
+        }
+        let Some((status, body)) = http_body(&record.block) else {
+            warn!("Skipping unreadable response for {} in {}", url, filename);
+            continue;
+        };
+        if !(200..300).contains(&status) {
+            continue;
+        }
+        captures.push(Capture {
+            sighting_id,
+            url: Some(url.to_string()),
+            html: String::from_utf8_lossy(body).into_owned(),
+            fetched_at: record.header("WARC-Date").unwrap_or_default().to_string(),
+        });
+    }
+    Ok(captures)
+}
+
+/// One WARC record: its named header fields and its content block
+struct WarcRecord {
+    headers: Vec<(String, String)>,
+    block: Vec<u8>,
+}
+
+impl WarcRecord {
+    /// Value of a header field, matching its name ignoring case
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read the next record, or `None` at the end of the file. The blank lines ending the
+/// previous record are skipped first.
+fn read_warc_record(
+    reader: &mut impl BufRead,
+) -> Result<Option<WarcRecord>, Box<dyn std::error::Error>> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+    if !line.starts_with("WARC/") {
+        return Err(format!("expected a WARC record, found {:?}", line.trim_end()).into());
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("WARC file ends inside a record header".into());
+        }
+        let field = line.trim_end();
+        if field.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = field.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let mut record = WarcRecord {
+        headers,
+        block: Vec::new(),
+    };
+    let length: usize = record
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+        .ok_or("WARC record without a valid Content-Length")?;
+    record.block = vec![0; length];
+    reader.read_exact(&mut record.block)?;
+    Ok(Some(record))
+}
+
+/// Status code and body of an HTTP response as archived
+fn http_body(response: &[u8]) -> Option<(u16, &[u8])> {
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")?;
+    let status_line = response[..end].split(|&byte| byte == b'\n').next()?;
+    let status = std::str::from_utf8(status_line)
+        .ok()?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+    Some((status, &response[end + 4..]))
+}
+
+/// Every `{id}.html` file in `dir`, dated by when it was last modified. Other files are
+/// left alone.
+fn read_html_dir(dir: &str) -> Result<Vec<Capture>, Box<dyn std::error::Error>> {
+    let mut captures = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "html") {
+            continue;
+        }
+        let Some(sighting_id) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        else {
+            continue;
+        };
+        let modified: DateTime<Utc> = std::fs::metadata(&path)?.modified()?.into();
+        captures.push(Capture {
+            sighting_id,
+            url: None,
+            html: String::from_utf8_lossy(&std::fs::read(&path)?).into_owned(),
+            fetched_at: modified.to_rfc3339_opts(SecondsFormat::Secs, true),
+        });
+    }
+    Ok(captures)
+}
+
+/// Parse captured pages into records with `parser`, as a scrape would have, sorted by ID:
+/// the first row of each page, or with `all_rows` every row that isn't blank. When a
+/// sighting was captured more than once, its last capture counts. A record's `scraped_at`
+/// is when its page was captured, not when it was replayed.
+pub fn replay(
+    captures: Vec<Capture>,
+    parser: &dyn PageParser,
+    name_map: Option<&NameMap>,
+    all_rows: bool,
+) -> Replayed {
+    let latest: BTreeMap<u64, Capture> = captures
+        .into_iter()
+        .map(|capture| (capture.sighting_id, capture))
+        .collect();
+
+    let mut replayed = Replayed::default();
+    for (sighting_id, capture) in latest {
+        let mut records = parse_html_to_records(&capture.html, parser);
+        if records.is_empty() {
+            warn!("No data found in the capture of sighting {}", sighting_id);
+            replayed.unparsed.push(sighting_id);
+            continue;
+        }
+        if !all_rows {
+            records.truncate(1);
+        } else if records.iter().any(|record| !record.is_blank()) {
+            // Blank rows beside filled ones are dropped, as when scraping
+            records.retain(|record| !record.is_blank());
+        }
+        for mut record in records {
+            record.sighting_id = Some(sighting_id);
+            if let Some(url) = &capture.url {
+                record.submitted_by_url = absolute_url(url, &record.submitted_by_url);
+                record.verified_by_url = absolute_url(url, &record.verified_by_url);
+            }
+            record.url = capture.url.clone();
+            record.scraped_at = capture.fetched_at.clone();
+            if let Some(name_map) = name_map {
+                name_map.apply(&mut record);
+            }
+            replayed.records.push(record);
+        }
+    }
+    replayed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_parser::ViewsRowParser;
+    use crate::warc::{WarcWriter, http_request, http_response};
+    use reqwest::header::HeaderMap;
+    use reqwest::{Method, StatusCode, Version};
+
+    const SIGHTING_HTML: &str = include_str!("../tests/fixtures/sighting.html");
+
+    #[test]
+    fn test_replay_warc() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("pages.warc.gz");
+        let filename = filename.to_str().unwrap();
+
+        let writer = WarcWriter::create(filename, u64::MAX).unwrap();
+        let headers = HeaderMap::new();
+        let archive = |url: &str, status: StatusCode, body: &str| {
+            let request = http_request(&Method::GET, url, &headers);
+            let response = http_response(Version::HTTP_11, status, &headers, body.as_bytes());
+            writer.write_exchange(url, &request, &response).unwrap();
+        };
+        let base = "https://example.org/sighting_details";
+        archive(
+            &format!("{}/7", base),
+            StatusCode::SERVICE_UNAVAILABLE,
+            "busy",
+        );
+        archive(&format!("{}/7", base), StatusCode::OK, SIGHTING_HTML);
+        archive(&format!("{}/7?page=1", base), StatusCode::OK, "<html/>");
+        archive(
+            &format!("{}/8", base),
+            StatusCode::OK,
+            "<html><body/></html>",
+        );
+        drop(writer);
+
+        let captures = read_captures(filename).unwrap();
+        let ids: Vec<u64> = captures.iter().map(|c| c.sighting_id).collect();
+        assert_eq!(ids, vec![7, 8]);
+
+        let replayed = replay(captures, &ViewsRowParser::new(", "), None, false);
+        assert_eq!(replayed.unparsed, vec![8]);
+        let [record] = replayed.records.as_slice() else {
+            panic!("expected one record");
+        };
+        assert_eq!(record.sighting_id, Some(7));
+        assert_eq!(
+            record.url.as_deref(),
+            Some("https://example.org/sighting_details/7")
+        );
+        assert!(!record.scientific_name.is_empty());
+        assert!(record.scraped_at.ends_with('Z'));
+    }
+
+    #[test]
+    fn test_replay_html_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("12.html"), SIGHTING_HTML).unwrap();
+        std::fs::write(dir.path().join("notes.html"), SIGHTING_HTML).unwrap();
+        std::fs::write(dir.path().join("13.txt"), SIGHTING_HTML).unwrap();
+        let multi_row = include_str!("../tests/fixtures/sighting_multi_row.html");
+        std::fs::write(dir.path().join("14.html"), multi_row).unwrap();
+
+        let captures = read_captures(dir.path().to_str().unwrap()).unwrap();
+        let replayed = replay(captures.clone(), &ViewsRowParser::new(", "), None, false);
+        let ids: Vec<Option<u64>> = replayed.records.iter().map(|r| r.sighting_id).collect();
+        assert_eq!(ids, vec![Some(12), Some(14)]);
+        assert_eq!(replayed.records[0].url, None);
+
+        // Every row of the page with several, each with its ID
+        let replayed = replay(captures, &ViewsRowParser::new(", "), None, true);
+        let ids: Vec<Option<u64>> = replayed.records.iter().map(|r| r.sighting_id).collect();
+        assert_eq!(ids, vec![Some(12), Some(14), Some(14), Some(14)]);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::parse::Args;
+use crate::scraper::write_missing_file;
+use crate::util::prefix_file_name;
+use clap::CommandFactory;
+use serde::Serialize;
+use std::io::Write;
+
+/// Label the plan puts in front of the output and missing file names, so the retry keeps
+/// its results and failures apart from the run that planned it
+const RETRY_PREFIX: &str = "retry-";
+
+/// Arguments dropped from the original command: the ID source, which the retry replaces
+/// with its own missing file, the files the retry writes under their own names, and
+/// streamed output, which a retry of the missing file can't use
+const REPLACED_ARGS: [&str; 14] = [
+    "min",
+    "max",
+    "descending",
+    "sitemap",
+    "ids",
+    "retry_missing",
+    "retry_missing_on_finish",
+    "rescrape_older_than",
+    "refresh_status",
+    "output",
+    "missing_prefix",
+    "emit_retry_plan",
+    "reorder_buffer",
+    "stream",
+];
+
+/// A follow-up run that retries exactly the sightings a run failed on, with its settings
+#[derive(Debug, Serialize)]
+pub struct RetryPlan {
+    pub failed_ids: Vec<u64>,
+    /// Missing file the retry reads its IDs from, written with the plan
+    pub missing_file: String,
+    /// Output the retry saves its records to
+    pub output: String,
+    /// The command line, program first
+    pub args: Vec<String>,
+    /// `args` quoted for a POSIX shell
+    pub command: String,
+}
+
+impl RetryPlan {
+    /// Plan the retry of `failed` sightings, with how many times each has failed, from the
+    /// original command line `argv`
+    pub fn new(argv: &[String], args: &Args, failed: &[(u64, u32)]) -> Self {
+        let missing_prefix = format!(
+            "{}{}",
+            RETRY_PREFIX,
+            args.missing_prefix.as_deref().unwrap_or_default()
+        );
+        let output = prefix_file_name(&args.output, RETRY_PREFIX);
+
+        let mut plan_args = strip_args(argv);
+        plan_args.extend([
+            "--retry-missing".to_string(),
+            "--missing-prefix".to_string(),
+            missing_prefix.clone(),
+            "--output".to_string(),
+            output.clone(),
+        ]);
+        let command = plan_args
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Self {
+            failed_ids: failed.iter().map(|(id, _)| *id).collect(),
+            missing_file: prefix_file_name(&args.missing, &missing_prefix),
+            output,
+            args: plan_args,
+            command,
+        }
+    }
+
+    /// Write the plan's missing file, then the plan itself to `filename`: a shell script
+    /// when it ends in `.sh`, otherwise JSON
+    pub fn write(
+        &self,
+        filename: &str,
+        failed: &[(u64, u32)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        write_missing_file(&self.missing_file, failed)?;
+
+        if filename.ends_with(".sh") {
+            std::fs::write(filename, format!("#!/bin/sh\nexec {}\n", self.command))?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(filename, std::fs::Permissions::from_mode(0o755))?;
+            }
+        } else {
+            let mut file = std::fs::File::create(filename)?;
+            serde_json::to_writer_pretty(&mut file, self)?;
+            writeln!(file)?;
+        }
+        Ok(())
+    }
+}
+
+/// `argv` without the `REPLACED_ARGS` and their values, in any of the forms clap accepts
+fn strip_args(argv: &[String]) -> Vec<String> {
+    let command = Args::command();
+    let replaced: Vec<&clap::Arg> = command
+        .get_arguments()
+        .filter(|arg| REPLACED_ARGS.contains(&arg.get_id().as_str()))
+        .collect();
+    let takes_value = |arg: &clap::Arg| arg.get_action().takes_values();
+
+    let mut kept = Vec::new();
+    let mut args = argv.iter();
+    if let Some(program) = args.next() {
+        kept.push(program.clone());
+    }
+    while let Some(arg) = args.next() {
+        let matched = replaced.iter().find_map(|replaced| {
+            if let Some(long) = arg.strip_prefix("--") {
+                let (name, inline) = match long.split_once('=') {
+                    Some((name, _)) => (name, true),
+                    None => (long, false),
+                };
+                (replaced.get_long() == Some(name)).then_some(!inline && takes_value(replaced))
+            } else if let Some(short) = arg.strip_prefix('-').and_then(|s| s.chars().next()) {
+                // `-M 10` takes the next argument; `-M10` carries its value
+                (replaced.get_short() == Some(short))
+                    .then_some(arg.len() == 2 && takes_value(replaced))
+            } else {
+                None
+            }
+        });
+        match matched {
+            Some(true) => {
+                args.next();
+            }
+            Some(false) => {}
+            None => kept.push(arg.clone()),
+        }
+    }
+    kept
+}
+
+/// Quote `arg` for a POSIX shell, leaving plain words as they are
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./=:,@%+".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_retry_plan_replaces_id_source_and_outputs() {
+        let argv = argv(&[
+            "sachem",
+            "-M10",
+            "--min=2",
+            "-c",
+            "4",
+            "--output",
+            "out/sightings.csv",
+            "--header",
+            "X-Note: it's me",
+            "--emit-retry-plan",
+            "plan.json",
+        ]);
+        let args = Args::try_parse_from(&argv).unwrap();
+        let plan = RetryPlan::new(&argv, &args, &[(7, 1), (9, 2)]);
+
+        assert_eq!(plan.failed_ids, vec![7, 9]);
+        assert_eq!(plan.missing_file, "retry-missing.txt");
+        assert_eq!(plan.output, "out/retry-sightings.csv");
+        assert_eq!(
+            plan.command,
+            "sachem -c 4 --header 'X-Note: it'\\''s me' --retry-missing \
+             --missing-prefix retry- --output out/retry-sightings.csv"
+        );
+        // The planned command is one the parser accepts
+        let retry = Args::try_parse_from(&plan.args).unwrap();
+        assert!(retry.retry_missing);
+        assert_eq!(retry.missing_file(), plan.missing_file);
+        assert_eq!(retry.concurrent, 4);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::record::SightingRecord;
+use csv::Reader;
+use serde::Serialize;
+use std::fs::File;
+
+/// Name and JSON type of one `SightingRecord` column
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldSchema {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub field_type: &'static str,
+    pub nullable: bool,
+}
+
+/// Columns of `SightingRecord` in serialization order
+pub fn record_schema() -> Vec<FieldSchema> {
+    let mut schema = vec![
+        FieldSchema {
+            name: "sighting_id",
+            field_type: "integer",
+            nullable: true,
+        },
+        FieldSchema {
+            name: "url",
+            field_type: "string",
+            nullable: true,
+        },
+    ];
+    schema.extend(
+        SightingRecord::default()
+            .text_fields()
+            .into_iter()
+            .map(|(name, _)| FieldSchema {
+                name,
+                field_type: "string",
+                nullable: false,
+            }),
+    );
+    schema.push(FieldSchema {
+        name: "coordinate_precision",
+        field_type: "string",
+        nullable: true,
+    });
+    schema.push(FieldSchema {
+        name: "latitude",
+        field_type: "number",
+        nullable: true,
+    });
+    schema.push(FieldSchema {
+        name: "longitude",
+        field_type: "number",
+        nullable: true,
+    });
+    schema.push(FieldSchema {
+        name: "location_obscured",
+        field_type: "boolean",
+        nullable: false,
+    });
+    schema.push(FieldSchema {
+        name: "photo_count",
+        field_type: "integer",
+        nullable: false,
+    });
+    schema.push(FieldSchema {
+        name: "has_photo",
+        field_type: "boolean",
+        nullable: false,
+    });
+    schema.push(FieldSchema {
+        name: "content_hash",
+        field_type: "string",
+        nullable: true,
+    });
+    schema.push(FieldSchema {
+        name: "scraped_at",
+        field_type: "string",
+        nullable: false,
+    });
+    schema
+}
+
+/// Columns an existing CSV has that the schema lacks, and the reverse
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HeaderDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl HeaderDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Compare a CSV header row with the current record schema
+pub fn diff_header(header: &[&str]) -> HeaderDiff {
+    let schema: Vec<&str> = record_schema().iter().map(|field| field.name).collect();
+
+    HeaderDiff {
+        added: header
+            .iter()
+            .filter(|column| !schema.contains(column))
+            .map(|column| column.to_string())
+            .collect(),
+        removed: schema
+            .iter()
+            .filter(|name| !header.contains(name))
+            .map(|name| name.to_string())
+            .collect(),
+    }
+}
+
+/// Compare the header row of a CSV file with the current record schema
+pub fn validate_csv_header(filename: &str) -> Result<HeaderDiff, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_reader(File::open(filename)?);
+    let header: Vec<&str> = reader.headers()?.iter().collect();
+    Ok(diff_header(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_matches_serialized_header() {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(SightingRecord::default()).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let header: Vec<&str> = csv.lines().next().unwrap().split(',').collect();
+
+        let schema: Vec<&str> = record_schema().iter().map(|field| field.name).collect();
+        assert_eq!(header, schema);
+    }
+
+    #[test]
+    fn test_diff_header() {
+        let mut header: Vec<&str> = record_schema().iter().map(|field| field.name).collect();
+        assert!(diff_header(&header).is_empty());
+
+        header.retain(|name| *name != "locality");
+        header.push("observation_notes");
+        let diff = diff_header(&header);
+        assert_eq!(diff.added, vec!["observation_notes"]);
+        assert_eq!(diff.removed, vec!["locality"]);
+    }
+}
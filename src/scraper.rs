@@ -1,436 +1,3926 @@
-use crate::record::SightingRecord;
-use csv::Writer;
+use crate::bloom::IdSet;
+use crate::clock::{Clock, TokioClock};
+#[cfg(feature = "parquet")]
+use crate::columnar::write_parquet;
+use crate::concurrency::{AutoConcurrency, SoftRateLimit};
+use crate::control::{ControlEvent, EventSender};
+use crate::cookies::CookieEntry;
+use crate::dwc::write_dwc;
+use crate::error::{FailureReason, ScraperError};
+use crate::id_index::IdIndex;
+use crate::lifecycle::{EventLog, LifecycleEvent};
+use crate::logging::{TaskTag, with_task_tag};
+use crate::metrics::{FieldFillStats, ParseWarning, RequestTimings, check_record};
+use crate::names::NameMap;
+use crate::output::{
+    OutputFormat, RecordWriter, STDOUT, stream_csv, write_compact_csv, write_csv, write_json,
+    write_ndjson,
+};
+use crate::page_parser::{PageParser, ViewsRowParser, is_maintenance_page};
+use crate::record::{SightingRecord, SpecimenType, Tags};
+use crate::reorder::{DEFAULT_FLUSH_EVERY, OrderedOutput};
+use crate::sitemap::{parse_sitemap, sighting_id_from_url};
+use crate::util::status;
+use crate::warc::{WarcWriter, http_request, http_response};
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
 use futures::future::join_all;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{error, info, warn};
 use rand::Rng;
-use reqwest::Client;
-use scraper::{Html, Selector};
-use std::collections::HashSet;
+use reqwest::cookie::Jar;
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderName, RETRY_AFTER};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tokio::time::sleep;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Default site the sighting pages are fetched from
+pub const DEFAULT_BASE_URL: &str = "https://www.butterfliesandmoths.org";
+
+/// Redirects followed per request before giving up, as in reqwest's default policy
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Factor the retry delay grows by with each attempt
+const BACKOFF_MULTIPLIER: f64 = 2.0;
+
+/// Largest page body read, unless configured otherwise; sighting pages are tens of KB
+pub const DEFAULT_MAX_BODY_BYTES: u64 = 10_000_000;
+
+/// Longest `Retry-After` obeyed, unless configured otherwise
+pub const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Maintenance pages a sighting waits out before it fails, unless configured otherwise
+pub const DEFAULT_MAX_MAINTENANCE_PAUSES: u32 = 12;
+
+/// Pause after the site serves its maintenance page, unless configured otherwise
+pub const DEFAULT_MAINTENANCE_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// HTTP statuses retried unless configured otherwise; any other error status is final
+pub const DEFAULT_RETRY_STATUSES: [u16; 5] = [429, 500, 502, 503, 504];
+
+/// Header carrying the per-request id sent with `--send-request-id`
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Most sitemap documents fetched when following a sitemap index
+const MAX_SITEMAPS: usize = 1000;
+
+/// Largest response body written by `dump_failed_html`, in bytes
+const MAX_DUMP_BYTES: usize = 1024 * 1024;
+
+/// The progress bar's layout unless `--progress-template` replaces it
+pub const DEFAULT_PROGRESS_TEMPLATE: &str = "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {percent:>3}% ETA: {eta_precise} {msg}";
+
+/// A check of how many IDs turn out to exist once the first `window` of a range scrape
+/// have finished, to catch a range that was never assigned before all of it is requested
+#[derive(Debug, Clone, Copy)]
+pub struct DensityCheck {
+    pub window: u64,
+    /// Share of the window, from 0 to 1, that must be found
+    pub min_density: f64,
+    /// Stop the run below `min_density`, rather than only warning
+    pub abort: bool,
+}
+
+/// Following a sighting page's next links, for pages that spread a sighting's rows over
+/// several pages
+#[derive(Debug, Clone, Copy)]
+pub struct Pagination {
+    /// Pages read per sighting at most, the first included, so a link loop can't run on
+    pub max_pages: usize,
+    /// Rows a full page shows; a page with fewer is taken as the last without following
+    /// its next link
+    pub records_per_page: Option<usize>,
+}
+
+/// Retries shared by every sighting in a run, on top of each sighting's own limit, so a
+/// few IDs that keep failing can't use up the run's time. Once it is spent, failures go
+/// straight to the missing list.
+#[derive(Debug)]
+pub struct RetryBudget {
+    total: u64,
+    used: AtomicU64,
+    /// Failures that would have been retried but for the budget
+    denied: AtomicU64,
+}
+
+impl RetryBudget {
+    pub fn new(total: u64) -> Self {
+        Self {
+            total,
+            used: AtomicU64::new(0),
+            denied: AtomicU64::new(0),
+        }
+    }
+
+    /// Take one retry, or `false` once the budget is spent
+    pub fn take(&self) -> bool {
+        let taken = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                (used < self.total).then_some(used + 1)
+            })
+            .is_ok();
+        if !taken && self.denied.fetch_add(1, Ordering::SeqCst) == 0 {
+            warn!(
+                "Retry budget of {} spent; failures now go straight to missing",
+                self.total
+            );
+        }
+        taken
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    pub fn denied(&self) -> u64 {
+        self.denied.load(Ordering::SeqCst)
+    }
+}
 
 pub struct ButterflyMothScraper {
     client: Client,
+    /// Cookies sent with every request; those set by responses are kept for the rest of the run
+    cookie_jar: Arc<Jar>,
+    pub(crate) max_redirects: usize,
+    /// Bodies longer than this are abandoned and the sighting marked missing
+    pub(crate) max_body_bytes: u64,
+    pub(crate) headers: HeaderMap,
+    /// Send a fresh X-Request-Id with each request and log it, so a mirror can match
+    /// its logs to ours
+    send_request_id: bool,
+    pub(crate) base_url: String,
     pub(crate) base_delay: Duration,
     pub(crate) max_retries: u32,
+    /// Retries shared across the run, drawn on before any sighting retries
+    retry_budget: Option<RetryBudget>,
+    /// Error statuses worth another attempt; the rest mark the sighting missing at once
+    pub(crate) retry_statuses: Vec<u16>,
+    pub(crate) per_id_timeout: Option<Duration>,
+    pub(crate) maintenance_cooldown: Duration,
+    /// Longest wait a `Retry-After` header is obeyed for
+    pub(crate) max_retry_after: Duration,
+    /// Maintenance pages one sighting waits out before it fails
+    pub(crate) max_maintenance_pauses: u32,
+    /// Sightings per burst and the pause between bursts; steady dispatch without
+    pub(crate) burst: Option<(usize, Duration)>,
+    /// Self-tuned limit on requests in flight, used in place of the fixed one when set
+    auto_concurrency: Option<Arc<AutoConcurrency>>,
+    /// Stretches the delay between requests while responses run well over their baseline
+    soft_rate_limit: Option<SoftRateLimit>,
+    /// End of the pause started when the site last served its maintenance page
+    paused_until: Arc<Mutex<Option<Instant>>>,
+    /// What every delay and pause waits on
+    clock: Arc<dyn Clock>,
+    parser: Box<dyn PageParser>,
+    name_map: Option<NameMap>,
     pub missing_sightings: Arc<Mutex<Vec<u64>>>,
     pub missing_sightings_file: Option<String>,
+    pub missing_attempts: Arc<Mutex<HashMap<u64, u32>>>,
+    /// Lines for a gzipped missing file that haven't made up a gzip member yet
+    pending_missing: Mutex<Vec<(u64, u32)>>,
+    pub dead_letter_file: Option<String>,
+    pub dead_letters: Arc<Mutex<IdSet>>,
+    /// Lines for a gzipped dead-letter file that haven't made up a gzip member yet
+    pending_dead_letters: Mutex<Vec<(u64, u32)>>,
+    /// IDs scraped by earlier runs, skipped like missing ones
+    id_index: Option<IdIndex>,
+    /// IDs already saved in the output an interrupted run left, skipped like missing ones
+    resumed_ids: IdSet,
+    pub dump_failed_html_dir: Option<String>,
+    /// Archive of the raw exchanges behind each page read
+    warc: Option<Arc<WarcWriter>>,
+    pub include_empty: bool,
+    /// Keep every row of a page as a record; `false` keeps the first, with pagination's
+    /// later rows merged into it
+    pub all_rows: bool,
+    /// Trim whitespace from every string field in the pass before saving
+    pub trim_whitespace_columns: bool,
+    /// Set each saved record's `content_hash`
+    pub with_hash: bool,
+    pub min_fields: usize,
+    /// Records dropped for having fewer than `min_fields` fields filled
+    pub below_min_fields: Arc<AtomicU64>,
+    /// Specimen types kept; records of any other type are dropped. Empty keeps them all.
+    pub specimen_types: Vec<SpecimenType>,
+    /// Records dropped for a specimen type not in `specimen_types`
+    pub other_specimen_types: Arc<AtomicU64>,
+    pub excel_csv: bool,
+    /// Records a streamed output writes between syncs to disk
+    pub flush_every: usize,
+    /// Write a header row at the top of CSV output
+    pub csv_headers: bool,
+    pub compact_csv: bool,
+    /// What empty string fields are written as in CSV and NDJSON output
+    pub null_value: String,
+    pub approx_dedup: bool,
+    /// indicatif template the progress bar is drawn with
+    pub progress_template: String,
+    pub density_check: Option<DensityCheck>,
+    pub pagination: Option<Pagination>,
+    /// Where records go as they finish, instead of being returned at the end
+    ordered_output: Option<OrderedOutput>,
+    pub field_fill_stats: Arc<Mutex<FieldFillStats>>,
+    pub parse_warnings: Arc<Mutex<Vec<(u64, ParseWarning)>>>,
+    pub cancel: CancellationToken,
+    /// Stop the run at the first sighting that fails for a reason other than not existing
+    pub fail_fast: bool,
+    /// The sighting that stopped a fail-fast run, and why it failed
+    pub first_failure: Arc<Mutex<Option<(u64, FailureReason)>>>,
+    pub request_timings: Arc<Mutex<RequestTimings>>,
+    pub records_scraped: Arc<AtomicU64>,
+    events: Option<EventSender>,
+    event_log: Option<Arc<EventLog>>,
+    /// Higher-priority IDs are dispatched first; IDs not listed have priority 0
+    priorities: HashMap<u64, i64>,
+    /// Tags from the ID file, copied into the `extra` of each ID's record
+    id_tags: HashMap<u64, Tags>,
 }
 
 impl ButterflyMothScraper {
     pub fn new() -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(10))
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
-            .build()
-            .expect("Failed to create HTTP client");
+        let cookie_jar = Arc::new(Jar::default());
+        let client = build_client(&cookie_jar, DEFAULT_MAX_REDIRECTS);
 
         Self {
             client,
+            cookie_jar,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            headers: HeaderMap::new(),
+            send_request_id: false,
+            base_url: DEFAULT_BASE_URL.to_string(),
             base_delay: Duration::from_millis(1000),
             max_retries: 3,
+            retry_budget: None,
+            retry_statuses: DEFAULT_RETRY_STATUSES.to_vec(),
+            per_id_timeout: None,
+            maintenance_cooldown: DEFAULT_MAINTENANCE_COOLDOWN,
+            max_maintenance_pauses: DEFAULT_MAX_MAINTENANCE_PAUSES,
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+            burst: None,
+            auto_concurrency: None,
+            soft_rate_limit: None,
+            paused_until: Arc::new(Mutex::new(None)),
+            clock: Arc::new(TokioClock),
+            parser: Box::new(ViewsRowParser::default()),
+            name_map: None,
             missing_sightings: Arc::new(Mutex::new(Vec::new())),
             missing_sightings_file: None,
+            missing_attempts: Arc::new(Mutex::new(HashMap::new())),
+            pending_missing: Mutex::new(Vec::new()),
+            dead_letter_file: None,
+            dead_letters: Arc::new(Mutex::new(IdSet::default())),
+            pending_dead_letters: Mutex::new(Vec::new()),
+            id_index: None,
+            resumed_ids: IdSet::default(),
+            dump_failed_html_dir: None,
+            warc: None,
+            include_empty: false,
+            all_rows: false,
+            trim_whitespace_columns: false,
+            with_hash: false,
+            min_fields: 0,
+            below_min_fields: Arc::new(AtomicU64::new(0)),
+            specimen_types: Vec::new(),
+            other_specimen_types: Arc::new(AtomicU64::new(0)),
+            excel_csv: false,
+            flush_every: DEFAULT_FLUSH_EVERY,
+            csv_headers: true,
+            compact_csv: false,
+            null_value: String::new(),
+            approx_dedup: false,
+            progress_template: DEFAULT_PROGRESS_TEMPLATE.to_string(),
+            density_check: None,
+            pagination: None,
+            ordered_output: None,
+            field_fill_stats: Arc::new(Mutex::new(FieldFillStats::default())),
+            parse_warnings: Arc::new(Mutex::new(Vec::new())),
+            cancel: CancellationToken::new(),
+            fail_fast: false,
+            first_failure: Arc::new(Mutex::new(None)),
+            request_timings: Arc::new(Mutex::new(RequestTimings::default())),
+            records_scraped: Arc::new(AtomicU64::new(0)),
+            events: None,
+            event_log: None,
+            priorities: HashMap::new(),
+            id_tags: HashMap::new(),
         }
     }
 
-    pub fn with_delay(mut self, delay_ms: u64) -> Self {
-        self.base_delay = Duration::from_millis(delay_ms);
+    /// Send these headers with every request, in addition to any already set
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        for (name, value) in headers.iter() {
+            self.headers.append(name, value.clone());
+        }
         self
     }
 
-    pub fn with_max_retries(mut self, retries: u32) -> Self {
-        self.max_retries = retries;
+    /// Give every request its own `X-Request-Id`, a random UUID logged with the sighting
+    pub fn with_request_id(mut self, send_request_id: bool) -> Self {
+        self.send_request_id = send_request_id;
         self
     }
 
-    pub fn with_missing_sightings_file(mut self, filename: &str) -> Self {
-        self.missing_sightings_file = Some(filename.to_string());
-        // Load existing missing sightings from file
-        if let Err(e) = self.load_missing_sightings() {
-            warn!("Could not load missing sightings from {}: {}", filename, e);
-        }
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
         self
     }
 
-    /// Load missing sightings from file
-    fn load_missing_sightings(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(filename) = &self.missing_sightings_file {
-            if let Ok(file) = File::open(filename) {
-                let reader = BufReader::new(file);
-                let mut missing_list = self.missing_sightings.lock().unwrap();
-
-                for line in reader.lines() {
-                    let line = line?;
-                    if let Ok(sighting_id) = line.trim().parse::<u64>() {
-                        missing_list.push(sighting_id);
-                    }
+    /// Follow at most `max_redirects` redirects per request; a longer chain or a loop
+    /// marks the sighting missing
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self.client = build_client(&self.cookie_jar, max_redirects);
+        self
+    }
+
+    /// Stop reading a page body once it passes `max_body_bytes`, marking the sighting
+    /// missing with reason `oversize`
+    pub fn with_max_body_bytes(mut self, max_body_bytes: u64) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    /// Send `name=value` cookies to the base URL. Call after `with_base_url`.
+    pub fn with_cookies(self, cookies: &[String]) -> Self {
+        match Url::parse(&self.base_url) {
+            Ok(url) => {
+                for cookie in cookies {
+                    self.cookie_jar.add_cookie_str(cookie, &url);
                 }
+            }
+            Err(e) => warn!("Could not set cookies for {}: {}", self.base_url, e),
+        }
+        self
+    }
 
-                info!(
-                    "Loaded {} missing sightings from {}",
-                    missing_list.len(),
-                    filename
-                );
+    /// Add cookies loaded from a cookie file, each for the site it was set by
+    pub fn with_cookie_entries(self, entries: &[CookieEntry]) -> Self {
+        for entry in entries {
+            match Url::parse(&entry.url) {
+                Ok(url) => self.cookie_jar.add_cookie_str(&entry.set_cookie, &url),
+                Err(e) => warn!("Skipping cookie for {}: {}", entry.url, e),
             }
         }
-        Ok(())
+        self
     }
 
-    /// Get a copy of the missing sightings list
-    pub fn get_missing_sightings(&self) -> Vec<u64> {
-        self.missing_sightings.lock().unwrap().clone()
+    pub fn with_delay(mut self, delay_ms: u64) -> Self {
+        self.base_delay = Duration::from_millis(delay_ms);
+        self
     }
 
-    /// Clear the missing sightings list
-    pub fn clear_missing_sightings(&self) {
-        self.missing_sightings.lock().unwrap().clear();
+    pub fn with_max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
     }
 
-    /// Add a sighting ID to the missing list
-    fn add_missing_sighting(&self, sighting_id: u64) {
-        let mut missing_list = self.missing_sightings.lock().unwrap();
-        if !missing_list.contains(&sighting_id) {
-            missing_list.push(sighting_id);
+    /// Cap the retries of the whole run at `total`, on top of the per-sighting limit
+    pub fn with_retry_budget(mut self, total: u64) -> Self {
+        self.retry_budget = Some(RetryBudget::new(total));
+        self
+    }
 
-            // Immediately append to file if configured
-            if let Some(filename) = &self.missing_sightings_file {
-                if let Err(e) = self.append_missing_sighting_to_file(sighting_id, filename) {
-                    error!(
-                        "Failed to append missing sighting {} to file: {}",
-                        sighting_id, e
-                    );
-                }
-            }
-        }
+    pub fn retry_budget(&self) -> Option<&RetryBudget> {
+        self.retry_budget.as_ref()
     }
 
-    /// Append a single missing sighting ID to the file
-    fn append_missing_sighting_to_file(
-        &self,
-        sighting_id: u64,
-        filename: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        use std::io::Write;
+    /// Whether a sighting that failed on `attempt` may try again: it has retries of its
+    /// own left, and the run's budget, if any, has one to give
+    fn may_retry(&self, attempt: u32) -> bool {
+        attempt < self.max_retries && self.retry_budget.as_ref().is_none_or(RetryBudget::take)
+    }
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(filename)?;
+    /// Retry only responses with these error statuses; others fail on the first attempt
+    pub fn with_retry_statuses(mut self, statuses: &[u16]) -> Self {
+        self.retry_statuses = statuses.to_vec();
+        self
+    }
 
-        writeln!(file, "{}", sighting_id)?;
-        file.flush()?;
+    /// Scrape in bursts of `size` sightings, each followed by a randomized pause of at least
+    /// `pause`. Within a burst requests are spaced by the usual delay and concurrency limit.
+    pub fn with_bursts(mut self, size: usize, pause: Duration) -> Self {
+        self.burst = Some((size, pause));
+        self
+    }
 
-        Ok(())
+    /// Tune the number of requests in flight from their latency and 429s instead of holding
+    /// it fixed, starting at one and never exceeding `max`
+    pub fn with_auto_concurrency(mut self, max: usize) -> Self {
+        self.auto_concurrency = Some(Arc::new(AutoConcurrency::new(max)));
+        self
     }
 
-    /// Filter out missing sightings from a list of sighting IDs
-    fn filter_missing_sightings(&self, sighting_ids: &[u64]) -> Vec<u64> {
-        let missing_set: HashSet<u64> = self
-            .missing_sightings
-            .lock()
-            .unwrap()
-            .iter()
-            .cloned()
-            .collect();
-        let filtered: Vec<u64> = sighting_ids
-            .iter()
-            .filter(|&id| !missing_set.contains(id))
-            .cloned()
-            .collect();
+    /// The self-tuned concurrency limit, when it is in use
+    pub fn auto_concurrency(&self) -> Option<&AutoConcurrency> {
+        self.auto_concurrency.as_deref()
+    }
 
-        let filtered_count = sighting_ids.len() - filtered.len();
-        if filtered_count > 0 {
-            info!("Filtered out {} already missing sightings", filtered_count);
+    /// Back off the delay between requests when the average latency of the last `window`
+    /// responses passes `multiplier` times that of the first `window`, as a site that
+    /// throttles by slowing down rather than answering 429 would
+    pub fn with_soft_rate_limit(mut self, multiplier: f64, window: usize) -> Self {
+        self.soft_rate_limit = Some(SoftRateLimit::new(multiplier, window));
+        self
+    }
+
+    /// The latency-based backoff, when it is in use
+    pub fn soft_rate_limit(&self) -> Option<&SoftRateLimit> {
+        self.soft_rate_limit.as_ref()
+    }
+
+    /// The delay between requests, stretched while the site is slowing responses down
+    fn paced_delay(&self) -> Duration {
+        match &self.soft_rate_limit {
+            Some(limit) => self.base_delay * limit.factor(),
+            None => self.base_delay,
         }
+    }
 
-        filtered
+    /// Pause the run for `cooldown` whenever the site serves its maintenance page
+    pub fn with_maintenance_cooldown(mut self, cooldown: Duration) -> Self {
+        self.maintenance_cooldown = cooldown;
+        self
     }
 
-    /// Parse HTML content into a SightingRecord
-    fn parse_html_to_record(&self, html_content: &str) -> Option<SightingRecord> {
-        let document = Html::parse_document(html_content);
+    /// Wait at most `max` when a 429's `Retry-After` asks for longer
+    pub fn with_max_retry_after(mut self, max: Duration) -> Self {
+        self.max_retry_after = max;
+        self
+    }
 
-        // Find rows with views-row class
-        let row_selector = Selector::parse("div[class*='views-row']").ok()?;
-        let row = document.select(&row_selector).next()?;
+    /// Fail a sighting with reason `maintenance` once it has been served the maintenance
+    /// page more than `max` times, rather than pausing for it indefinitely
+    pub fn with_max_maintenance_pauses(mut self, max: u32) -> Self {
+        self.max_maintenance_pauses = max;
+        self
+    }
 
-        let mut record = SightingRecord::default();
+    /// Give up on a sighting, retries and backoff included, once it has taken `timeout`
+    pub fn with_per_id_timeout(mut self, timeout: Duration) -> Self {
+        self.per_id_timeout = Some(timeout);
+        self
+    }
 
-        // Helper function to extract field content
-        let get_field = |class_name: &str, sub_selector: &str| -> Option<String> {
-            let field_selector = Selector::parse(&format!("div.{}", class_name)).ok()?;
-            let field = row.select(&field_selector).next()?;
+    /// Parse fetched pages with `parser` instead of the built-in views-row parser
+    pub fn with_parser(mut self, parser: Box<dyn PageParser>) -> Self {
+        self.parser = parser;
+        self
+    }
 
-            let content_selector = Selector::parse(sub_selector).ok()?;
-            let content = field.select(&content_selector).next()?;
+    /// Attach `id_tags` to the records scraped for those IDs, as their `extra` tags
+    pub fn with_id_tags(mut self, id_tags: HashMap<u64, Tags>) -> Self {
+        self.id_tags = id_tags;
+        self
+    }
 
-            Some(
-                content
-                    .text()
-                    .collect::<Vec<_>>()
-                    .join("")
-                    .trim()
-                    .to_string(),
-            )
-        };
+    /// Every tag name given in `id_tags`, which records scraped for those IDs may carry
+    pub fn tag_names(&self) -> BTreeSet<String> {
+        self.id_tags
+            .values()
+            .flat_map(|tags| tags.keys().cloned())
+            .collect()
+    }
 
-        // Extract species information
-        if let Ok(species_selector) = Selector::parse("div.views-field-field-sciname") {
-            if let Some(species_field) = row.select(&species_selector).next() {
-                if let Ok(h4_selector) = Selector::parse("h4") {
-                    if let Some(h4) = species_field.select(&h4_selector).next() {
-                        // Get common name (text before any child elements)
-                        let text_nodes: Vec<_> = h4.text().collect();
-                        if !text_nodes.is_empty() {
-                            record.common_name = text_nodes[0].trim().to_string();
-                        }
+    /// Replace scraped common names with the canonical ones in `name_map`
+    pub fn with_name_map(mut self, name_map: NameMap) -> Self {
+        self.name_map = Some(name_map);
+        self
+    }
 
-                        // Get scientific name from <em> tag
-                        if let Ok(em_selector) = Selector::parse("em") {
-                            if let Some(em) = h4.select(&em_selector).next() {
-                                record.scientific_name =
-                                    em.text().collect::<Vec<_>>().join("").trim().to_string();
-                            }
-                        }
+    /// Dispatch sightings in descending `priorities` order, unlisted IDs at priority 0
+    pub fn with_priorities(mut self, priorities: HashMap<u64, i64>) -> Self {
+        self.priorities = priorities;
+        self
+    }
 
-                        // Get species link from <a> tag
-                        if let Ok(a_selector) = Selector::parse("a") {
-                            if let Some(a) = h4.select(&a_selector).next() {
-                                record.species_link =
-                                    a.value().attr("href").unwrap_or("").to_string();
-                            }
-                        }
-                    }
-                }
-            }
+    pub fn with_missing_sightings_file(mut self, filename: &str) -> Self {
+        self.missing_sightings_file = Some(filename.to_string());
+        // Load existing missing sightings from file
+        if let Err(e) = self.load_missing_sightings() {
+            warn!("Could not load missing sightings from {}: {}", filename, e);
         }
+        self
+    }
 
-        // Extract other fields
-        record.observation_date =
-            get_field("views-field-field-sightingdate", ".field-content").unwrap_or_default();
-        record.submitted_by = get_field("views-field-name", ".username").unwrap_or_default();
-        record.specimen_type =
-            get_field("views-field-field-specimen-type", ".field-content").unwrap_or_default();
-        record.status =
-            get_field("views-field-field-sighting-status", ".field-content").unwrap_or_default();
-        record.verified_by = get_field("views-field-name-1", ".username").unwrap_or_default();
-        record.verified_date =
-            get_field("views-field-field-recorddate", ".field-content").unwrap_or_default();
-
-        // Extract regions (join multiple links)
-        if let Ok(region_selector) = Selector::parse("div.views-field-field-region") {
-            if let Some(region_field) = row.select(&region_selector).next() {
-                if let Ok(a_selector) = Selector::parse("a") {
-                    let regions: Vec<String> = region_field
-                        .select(&a_selector)
-                        .map(|link| link.text().collect::<Vec<_>>().join("").trim().to_string())
-                        .collect();
-                    record.checklist_regions = regions.join(", ");
-                }
-            }
-        }
+    /// Wait on `clock` instead of real time
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-        Some(record)
+    /// Skip sightings already in `index`, as scraped by earlier runs
+    pub fn with_id_index(mut self, index: IdIndex) -> Self {
+        self.id_index = Some(index);
+        self
     }
 
-    /// Scrape a single sighting page by ID with exponential backoff retry
-    pub async fn scrape_sighting_page(&self, sighting_id: u64) -> Option<SightingRecord> {
-        let url = format!(
-            "https://www.butterfliesandmoths.org/sighting_details/{}",
-            sighting_id
-        );
+    /// Hand back the ID index, for adding this run's records to it
+    pub fn take_id_index(&mut self) -> Option<IdIndex> {
+        self.id_index.take()
+    }
 
-        for attempt in 0..=self.max_retries {
-            // Add delay with jitter
-            if attempt > 0 {
-                let backoff_delay = Duration::from_millis(
-                    (2_u64.pow(attempt) * self.base_delay.as_millis() as u64)
-                        + rand::rng().random_range(0..self.base_delay.as_millis() as u64),
-                );
+    /// Skip sightings already saved in the CSV at `filename`, so a rerun of an interrupted
+    /// scrape picks up where it stopped. Rows that don't parse, such as a partial last row,
+    /// are passed over. A file that doesn't exist yet skips nothing. The file is read a row
+    /// at a time, and with `with_approx_dedup` only a bloom filter of its IDs is kept.
+    pub fn with_resume_from_output(mut self, filename: &str) -> Self {
+        if !Path::new(filename).exists() {
+            return self;
+        }
+        match self.load_resumed_ids(filename) {
+            Ok(skipped) => {
+                if skipped > 0 {
+                    warn!("Skipped {} unreadable rows in {}", skipped, filename);
+                }
                 info!(
-                    "Retrying sighting {} (attempt {}) after {}ms delay",
-                    sighting_id,
-                    attempt + 1,
-                    backoff_delay.as_millis()
-                );
-                sleep(backoff_delay).await;
-            } else {
-                let initial_delay = Duration::from_millis(
-                    self.base_delay.as_millis() as u64
-                        + rand::rng().random_range(0..self.base_delay.as_millis() as u64 / 2),
+                    "Resuming with {} sightings already in {}",
+                    self.resumed_ids.len(),
+                    filename
                 );
-                sleep(initial_delay).await;
             }
+            Err(e) => warn!("Could not read saved sightings from {}: {}", filename, e),
+        }
+        self
+    }
 
-            match self.client.get(&url).send().await {
-                Ok(response) => match response.status().as_u16() {
-                    429 => {
-                        if attempt < self.max_retries {
-                            warn!("Rate limited for sighting {}, retrying...", sighting_id);
-                            continue;
-                        } else {
-                            error!(
-                                "Rate limited for sighting {}, max retries reached",
-                                sighting_id
-                            );
-                            self.add_missing_sighting(sighting_id);
-                            return None;
-                        }
-                    }
-                    200..=299 => match response.text().await {
-                        Ok(html) => match self.parse_html_to_record(&html) {
-                            Some(mut record) => {
-                                record.sighting_id = Some(sighting_id);
-                                record.url = Some(url);
-                                if attempt > 0 {
-                                    info!(
-                                        "Successfully scraped sighting {} on attempt {}",
-                                        sighting_id,
-                                        attempt + 1
-                                    );
-                                } else {
-                                    info!("Successfully scraped sighting {}", sighting_id);
-                                }
-                                return Some(record);
-                            }
-                            None => {
-                                warn!("No data found for sighting {}", sighting_id);
-                                self.add_missing_sighting(sighting_id);
-                                return None;
-                            }
-                        },
-                        Err(_) => {
-                            self.add_missing_sighting(sighting_id);
-                            return None;
-                        }
-                    },
-                    _ => {
-                        if attempt < self.max_retries {
-                            warn!(
-                                "HTTP error {} for sighting {}, retrying...",
-                                response.status(),
-                                sighting_id
-                            );
-                            continue;
-                        } else {
-                            self.add_missing_sighting(sighting_id);
-                            return None;
-                        }
-                    }
-                },
-                Err(e) => {
-                    if attempt < self.max_retries {
-                        warn!(
-                            "Request failed for sighting {}, retrying...: {}",
-                            sighting_id, e
-                        );
-                        continue;
-                    } else {
-                        error!(
-                            "Request failed for sighting {}, max retries reached: {}",
-                            sighting_id, e
-                        );
-                        self.add_missing_sighting(sighting_id);
-                        return None;
+    /// Collect the sighting IDs saved in the CSV at `filename`, returning how many rows
+    /// could not be read
+    fn load_resumed_ids(&mut self, filename: &str) -> csv::Result<usize> {
+        // Counted first, so a bloom filter can be sized without holding the IDs
+        let expected = match self.approx_dedup {
+            true => stream_csv(filename)?.count(),
+            false => 0,
+        };
+        let mut resumed_ids = IdSet::new(self.approx_dedup, expected);
+        let mut skipped = 0;
+        for record in stream_csv(filename)? {
+            match record {
+                Ok(record) => {
+                    if let Some(sighting_id) = record.sighting_id {
+                        resumed_ids.insert(sighting_id);
                     }
                 }
+                Err(e) if e.is_io_error() => return Err(e),
+                Err(_) => skipped += 1,
             }
         }
+        self.resumed_ids = resumed_ids;
+        Ok(skipped)
+    }
 
-        error!(
-            "Failed to scrape sighting {} after {} attempts",
-            sighting_id,
-            self.max_retries + 1
-        );
-        self.add_missing_sighting(sighting_id);
-        None
+    /// IDs skipped because `with_resume_from_output` found them already saved
+    pub fn resumed_count(&self) -> usize {
+        self.resumed_ids.len()
     }
 
-    /// Scrape multiple sighting pages concurrently
-    pub async fn scrape_multiple_sightings(
-        &self,
-        sighting_ids: &[u64],
-        max_concurrent: usize,
-    ) -> Vec<SightingRecord> {
-        let filtered_sightings_ids = self.filter_missing_sightings(sighting_ids);
+    /// Record sightings that have used up their lifetime attempts in `filename`.
+    /// IDs already listed there are never scraped again.
+    pub fn with_dead_letter_file(mut self, filename: &str) -> Self {
+        self.dead_letter_file = Some(filename.to_string());
+        match self.load_dead_letters(filename) {
+            Ok(()) => info!(
+                "Loaded {} dead-lettered sightings from {}",
+                self.dead_letters.lock().unwrap().len(),
+                filename
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Could not load dead letters from {}: {}", filename, e),
+        }
+        self
+    }
 
-        // Create progress bar
-        let progress_bar = ProgressBar::new(filtered_sightings_ids.len() as u64);
-        progress_bar.set_style(
-            ProgressStyle::default_bar()
-                .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {percent:>3}% ETA: {eta_precise} {msg}")
-                .unwrap()
-                .progress_chars("##-")
-        );
-        progress_bar.set_message("Scraping sightings");
+    /// Add the IDs in the dead-letter file at `filename`, read a line at a time, to the
+    /// dead letters
+    fn load_dead_letters(&self, filename: &str) -> std::io::Result<()> {
+        let mut dead_letters = self.dead_letters.lock().unwrap();
+        if self.approx_dedup {
+            // Counted first, so the filter can be sized without holding the IDs, with room
+            // for every missing ID this run could dead-letter
+            let expected = missing_file_entries(filename)?.count()
+                + dead_letters.len()
+                + self.missing_sightings.lock().unwrap().len();
+            let mut approx = IdSet::new(true, expected);
+            if let IdSet::Exact(ids) = std::mem::take(&mut *dead_letters) {
+                for id in ids {
+                    approx.insert(id);
+                }
+            }
+            *dead_letters = approx;
+        }
+        for entry in missing_file_entries(filename)? {
+            dead_letters.insert(entry?.0);
+        }
+        Ok(())
+    }
 
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent));
-        let mut tasks = Vec::new();
-        let pb = Arc::new(progress_bar);
+    pub fn with_dump_failed_html(mut self, dir: &str) -> Self {
+        self.dump_failed_html_dir = Some(dir.to_string());
+        self
+    }
 
-        for sighting_id in filtered_sightings_ids.iter() {
-            let permit = Arc::clone(&semaphore);
-            let sighting_id = *sighting_id;
-            let scraper = self;
-            let progress = Arc::clone(&pb);
+    /// Archive the request and raw response behind every page body read, so records can be
+    /// parsed again later without refetching
+    pub fn with_warc(mut self, warc: WarcWriter) -> Self {
+        self.warc = Some(Arc::new(warc));
+        self
+    }
 
-            let task = async move {
-                let _permit = permit.acquire().await.unwrap();
-                let result = scraper.scrape_sighting_page(sighting_id).await;
-                progress.inc(1);
-                result
-            };
+    /// Keep records with neither a common nor a scientific name instead of discarding them
+    pub fn with_include_empty(mut self, include_empty: bool) -> Self {
+        self.include_empty = include_empty;
+        self
+    }
 
-            tasks.push(task);
-        }
+    /// Whether every sighting row found on a page, or on the pages after it with
+    /// pagination, is kept as a record of its own, each with the page's sighting ID and
+    /// URL, rather than only the first, as by default
+    pub fn with_all_rows(mut self, all_rows: bool) -> Self {
+        self.all_rows = all_rows;
+        self
+    }
 
-        let results = join_all(tasks).await;
-        let successful_records: Vec<SightingRecord> =
-            results.into_iter().filter_map(|r| r).collect();
+    /// Trim leading and trailing whitespace from every string field before saving
+    pub fn with_trim_whitespace_columns(mut self, trim: bool) -> Self {
+        self.trim_whitespace_columns = trim;
+        self
+    }
 
-        let missing_count = self.get_missing_sightings().len();
-        info!(
-            "Successfully scraped {} out of {} sightings ({} unfound)",
-            successful_records.len(),
-            sighting_ids.len(),
-            missing_count
-        );
+    /// Give every saved record a `content_hash`, worked out after trimming
+    pub fn with_content_hash(mut self, with_hash: bool) -> Self {
+        self.with_hash = with_hash;
+        self
+    }
 
-        successful_records
+    /// The last pass over records before they are saved: trim them if asked to, then drop
+    /// any whose page fields are all blank, marking their IDs missing with reason `empty`.
+    /// Those kept are hashed when `with_hash` is set.
+    /// Such records slip past the parser's checks only with `include_empty` or on rows read
+    /// back from an earlier run. Returns how many were dropped.
+    pub fn clean_records(&self, records: &mut Vec<SightingRecord>) -> usize {
+        let before = records.len();
+        records.retain_mut(|record| self.keep_record(record));
+        before - records.len()
     }
 
-    /// Scrape a range of sighting IDs
-    pub async fn scrape_sighting_range(
+    /// `clean_records` for one record: trim and hash it if asked to, and whether it is
+    /// worth saving
+    fn keep_record(&self, record: &mut SightingRecord) -> bool {
+        if self.trim_whitespace_columns {
+            record.trim_whitespace();
+        }
+        if !record.is_blank() {
+            if self.with_hash {
+                record.content_hash = Some(record.compute_content_hash());
+            }
+            return true;
+        }
+        match record.sighting_id {
+            Some(sighting_id) => {
+                warn!("Dropping blank record for sighting {}", sighting_id);
+                self.add_missing_sighting(sighting_id, FailureReason::Blank);
+            }
+            None => warn!("Dropping blank record without a sighting ID"),
+        }
+        false
+    }
+
+    /// Drop records with fewer than `min_fields` of their page fields filled, without
+    /// marking them missing
+    pub fn with_min_fields(mut self, min_fields: usize) -> Self {
+        self.min_fields = min_fields;
+        self
+    }
+
+    /// Keep only records of these specimen types, without marking the others missing
+    pub fn with_specimen_types(mut self, specimen_types: &[SpecimenType]) -> Self {
+        self.specimen_types = specimen_types.to_vec();
+        self
+    }
+
+    /// Write CSV output with a UTF-8 BOM and CRLF line endings so Excel opens it cleanly
+    pub fn with_excel_csv(mut self, excel_csv: bool) -> Self {
+        self.excel_csv = excel_csv;
+        self
+    }
+
+    /// Sync a streamed output to disk after every `flush_every` records
+    pub fn with_flush_every(mut self, flush_every: usize) -> Self {
+        self.flush_every = flush_every;
+        self
+    }
+
+    /// Write CSV output without its header row, for loaders that expect data rows only
+    pub fn with_csv_headers(mut self, csv_headers: bool) -> Self {
+        self.csv_headers = csv_headers;
+        self
+    }
+
+    /// Leave columns that are empty in every record out of the CSV output
+    pub fn with_compact_csv(mut self, compact_csv: bool) -> Self {
+        self.compact_csv = compact_csv;
+        self
+    }
+
+    /// Write empty string fields as `null_value` in CSV and NDJSON output, for tools that
+    /// tell an empty string from a missing value poorly
+    pub fn with_null_value(mut self, null_value: &str) -> Self {
+        self.null_value = null_value.to_string();
+        self
+    }
+
+    /// Skip missing, dead-lettered and already saved IDs using bloom filters instead of
+    /// hash sets. This needs far less memory for millions of IDs, but now and then skips
+    /// an ID that was never missing or saved.
+    pub fn with_approx_dedup(mut self, approx_dedup: bool) -> Self {
+        self.approx_dedup = approx_dedup;
+        if approx_dedup {
+            let mut dead_letters = self.dead_letters.lock().unwrap();
+            *dead_letters = std::mem::take(&mut *dead_letters).into_approx();
+            self.resumed_ids = std::mem::take(&mut self.resumed_ids).into_approx();
+        }
+        self
+    }
+
+    /// Draw the progress bar with this indicatif template. One that doesn't parse is
+    /// reported and the default kept.
+    pub fn with_progress_template(mut self, template: &str) -> Self {
+        match ProgressStyle::default_bar().template(template) {
+            Ok(_) => self.progress_template = template.to_string(),
+            Err(e) => status!("Invalid --progress-template, using the default: {}", e),
+        }
+        self
+    }
+
+    /// Check the found density of range scrapes once the first `check.window` IDs finish
+    pub fn with_density_check(mut self, check: DensityCheck) -> Self {
+        self.density_check = Some(check);
+        self
+    }
+
+    /// Follow each sighting page's next links and merge the rows found into its record
+    pub fn with_pagination(mut self, pagination: Pagination) -> Self {
+        self.pagination = Some(pagination);
+        self
+    }
+
+    /// Write scraped records to `output` in dispatch order as they finish, rather than
+    /// returning them, so a long run's records aren't all held in memory
+    pub fn with_ordered_output(mut self, output: OrderedOutput) -> Self {
+        self.ordered_output = Some(output);
+        self
+    }
+
+    /// Write the records still held by the ordered output and close it, returning how
+    /// many were written in all, or `None` without one
+    pub fn finish_ordered_output(&self) -> Option<Result<usize, String>> {
+        self.ordered_output.as_ref().map(OrderedOutput::finish)
+    }
+
+    /// Share a cancellation token with the caller. Once it is cancelled, no further
+    /// sightings are started and any backoff or maintenance pause is cut short; requests
+    /// already in flight finish and the scrape returns partial results.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Cancel the run once a sighting fails for any reason but a 404 or 410, after its
+    /// retries. The failure is kept for `first_failure`.
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Report progress and every scraped record through `events`, e.g. to a control socket
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Write each sighting's success or failure to `event_log`
+    pub fn with_event_log(mut self, event_log: Arc<EventLog>) -> Self {
+        self.event_log = Some(event_log);
+        self
+    }
+
+    fn emit(&self, event: &LifecycleEvent) {
+        if let Some(event_log) = &self.event_log {
+            event_log.emit(event);
+        }
+    }
+
+    fn send_event(&self, event: &ControlEvent) {
+        if let Some(events) = &self.events {
+            events.send(event);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Sleep for `duration` on the clock, returning `false` at once if the run is cancelled
+    /// first
+    async fn sleep_unless_cancelled(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = self.clock.sleep(duration) => !self.is_cancelled(),
+            _ = self.cancel.cancelled() => false,
+        }
+    }
+
+    /// Load missing sightings and their attempt counts from file
+    fn load_missing_sightings(&self) -> Result<(), ScraperError> {
+        if let Some(filename) = &self.missing_sightings_file
+            && Path::new(filename).exists()
+        {
+            let entries = read_missing_file(filename)?;
+            let mut missing_list = self.missing_sightings.lock().unwrap();
+            let mut attempts = self.missing_attempts.lock().unwrap();
+
+            // A failed retry appends a new line, so the last count for an ID is current
+            for (sighting_id, count) in entries {
+                if attempts.insert(sighting_id, count).is_none() {
+                    missing_list.push(sighting_id);
+                }
+            }
+
+            info!(
+                "Loaded {} missing sightings from {}",
+                missing_list.len(),
+                filename
+            );
+        }
+        Ok(())
+    }
+
+    /// Get a copy of the missing sightings list
+    pub fn get_missing_sightings(&self) -> Vec<u64> {
+        self.missing_sightings.lock().unwrap().clone()
+    }
+
+    /// Get a copy of the per-field fill statistics for this run
+    pub fn get_field_fill_stats(&self) -> FieldFillStats {
+        self.field_fill_stats.lock().unwrap().clone()
+    }
+
+    /// Get a copy of the parse warnings raised so far, with the sighting each came from
+    pub fn get_parse_warnings(&self) -> Vec<(u64, ParseWarning)> {
+        self.parse_warnings.lock().unwrap().clone()
+    }
+
+    /// The sighting that stopped a `--fail-fast` run and its failure reason, if one did
+    pub fn get_first_failure(&self) -> Option<(u64, FailureReason)> {
+        self.first_failure.lock().unwrap().clone()
+    }
+
+    /// Get a copy of the request latencies and retry count so far
+    pub fn get_request_timings(&self) -> RequestTimings {
+        self.request_timings.lock().unwrap().clone()
+    }
+
+    /// Add a sighting ID to the missing list, or count another failed attempt if it is
+    /// already there (i.e. it failed again while retrying missing sightings)
+    fn add_missing_sighting(&self, sighting_id: u64, reason: FailureReason) {
+        self.emit(&LifecycleEvent::IdFailure {
+            sighting_id,
+            reason: &reason.to_string(),
+        });
+        if self.fail_fast && !reason.is_not_found() {
+            let mut first_failure = self.first_failure.lock().unwrap();
+            if first_failure.is_none() {
+                error!("Stopping at sighting {}: {}", sighting_id, reason);
+                *first_failure = Some((sighting_id, reason));
+                self.cancel.cancel();
+            }
+        }
+        let mut missing_list = self.missing_sightings.lock().unwrap();
+        let mut attempts = self.missing_attempts.lock().unwrap();
+        let count = attempts.entry(sighting_id).or_insert(0);
+        *count += 1;
+        if !missing_list.contains(&sighting_id) {
+            missing_list.push(sighting_id);
+        }
+
+        // Immediately append to file if configured
+        if let Some(filename) = &self.missing_sightings_file
+            && let Err(e) = append_missing_line(
+                filename,
+                &mut self.pending_missing.lock().unwrap(),
+                sighting_id,
+                *count,
+            )
+        {
+            error!(
+                "Failed to append missing sighting {} to file: {}",
+                sighting_id, e
+            );
+        }
+    }
+
+    /// Rewrite the missing file with one line per sighting still missing
+    fn save_missing_sightings(&self) -> Result<(), ScraperError> {
+        let Some(filename) = &self.missing_sightings_file else {
+            return Ok(());
+        };
+        let missing_list = self.missing_sightings.lock().unwrap();
+        let attempts = self.missing_attempts.lock().unwrap();
+
+        let entries: Vec<(u64, u32)> = missing_list
+            .iter()
+            .map(|&sighting_id| {
+                (
+                    sighting_id,
+                    attempts.get(&sighting_id).copied().unwrap_or(1),
+                )
+            })
+            .collect();
+        // The rewrite holds every line still waiting to be appended
+        self.pending_missing.lock().unwrap().clear();
+        write_missing_file(filename, &entries)?;
+
+        Ok(())
+    }
+
+    /// Write out the missing and dead-letter lines still waiting, and pack a gzipped
+    /// file's members back into one. Call once the run has no more failures to record.
+    pub fn finish_failure_files(&self) -> Result<(), ScraperError> {
+        if let Some(filename) = &self.missing_sightings_file {
+            repack_missing_file(filename, &mut self.pending_missing.lock().unwrap())?;
+        }
+        if let Some(filename) = &self.dead_letter_file {
+            repack_missing_file(filename, &mut self.pending_dead_letters.lock().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Move missing sightings that have failed in `max_lifetime_attempts` runs to the
+    /// dead-letter file so they are no longer retried. Returns the IDs moved.
+    fn dead_letter_exhausted(&self, max_lifetime_attempts: u32) -> Vec<u64> {
+        let mut missing_list = self.missing_sightings.lock().unwrap();
+        let mut attempts = self.missing_attempts.lock().unwrap();
+        let mut dead_letters = self.dead_letters.lock().unwrap();
+
+        let mut exhausted = Vec::new();
+        missing_list.retain(|&sighting_id| {
+            let count = attempts.get(&sighting_id).copied().unwrap_or(1);
+            if count < max_lifetime_attempts {
+                return true;
+            }
+            if let Some(filename) = &self.dead_letter_file
+                && let Err(e) = append_missing_line(
+                    filename,
+                    &mut self.pending_dead_letters.lock().unwrap(),
+                    sighting_id,
+                    count,
+                )
+            {
+                error!(
+                    "Failed to append sighting {} to dead-letter file: {}",
+                    sighting_id, e
+                );
+            }
+            exhausted.push(sighting_id);
+            false
+        });
+        for sighting_id in &exhausted {
+            attempts.remove(sighting_id);
+            dead_letters.insert(*sighting_id);
+        }
+
+        exhausted
+    }
+
+    /// Write the body of a page that failed to parse to `{dir}/{id}.html`
+    fn dump_failed_html(&self, sighting_id: u64, html: &str) {
+        let Some(dir) = &self.dump_failed_html_dir else {
+            return;
+        };
+        if html.trim().is_empty() {
+            return;
+        }
+
+        let mut end = html.len().min(MAX_DUMP_BYTES);
+        while !html.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let path = std::path::Path::new(dir).join(format!("{}.html", sighting_id));
+        let result = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, &html[..end]));
+        match result {
+            Ok(()) => info!(
+                "Dumped HTML for sighting {} to {}",
+                sighting_id,
+                path.display()
+            ),
+            Err(e) => error!("Failed to dump HTML for sighting {}: {}", sighting_id, e),
+        }
+    }
+
+    /// Filter out missing, dead-lettered and already indexed sightings from a list of
+    /// sighting IDs
+    fn filter_missing_sightings(&self, sighting_ids: &[u64]) -> Vec<u64> {
+        let missing_list = self.missing_sightings.lock().unwrap();
+        let dead_letters = self.dead_letters.lock().unwrap();
+        let missing = IdSet::from_ids(self.approx_dedup, missing_list.iter().copied());
+        if let (Some(missing_bytes), Some(dead_letter_bytes)) =
+            (missing.filter_bytes(), dead_letters.filter_bytes())
+        {
+            info!(
+                "Checking sightings against {} bytes of bloom filters",
+                missing_bytes + dead_letter_bytes
+            );
+        }
+        let filtered: Vec<u64> = sighting_ids
+            .iter()
+            .filter(|&&id| !missing.contains(id) && !dead_letters.contains(id))
+            .copied()
+            .collect();
+
+        let filtered_count = sighting_ids.len() - filtered.len();
+        if filtered_count > 0 {
+            info!("Filtered out {} already missing sightings", filtered_count);
+        }
+
+        let filtered = if self.resumed_ids.is_empty() {
+            filtered
+        } else {
+            let unsaved: Vec<u64> = filtered
+                .iter()
+                .filter(|&&id| !self.resumed_ids.contains(id))
+                .copied()
+                .collect();
+            let saved_count = filtered.len() - unsaved.len();
+            if saved_count > 0 {
+                info!("Skipped {} sightings already in the output", saved_count);
+            }
+            unsaved
+        };
+
+        match &self.id_index {
+            Some(index) => {
+                let unscraped: Vec<u64> = filtered
+                    .iter()
+                    .filter(|&&id| !index.contains(id))
+                    .copied()
+                    .collect();
+                let indexed_count = filtered.len() - unscraped.len();
+                if indexed_count > 0 {
+                    info!(
+                        "Skipped {} sightings already in the ID index",
+                        indexed_count
+                    );
+                }
+                unscraped
+            }
+            None => filtered,
+        }
+    }
+
+    /// URL of the details page for a sighting
+    fn sighting_url(&self, sighting_id: u64) -> String {
+        format!("{}/sighting_details/{}", self.base_url, sighting_id)
+    }
+
+    /// Start a request carrying the configured headers. These are set per request rather
+    /// than as client defaults, which would keep only one value per header name.
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .headers(self.headers.clone())
+    }
+
+    /// Send a request, recording how long it took to get a response. `sighting_id` is the
+    /// sighting the request is for, logged with its request id.
+    async fn send(
         &self,
-        start_id: u64,
-        end_id: u64,
-        max_concurrent: usize,
-    ) -> Vec<SightingRecord> {
-        let sighting_ids: Vec<u64> = (start_id..=end_id).collect();
-        self.scrape_multiple_sightings(&sighting_ids, max_concurrent)
-            .await
+        method: Method,
+        url: &str,
+        sighting_id: Option<u64>,
+    ) -> reqwest::Result<Response> {
+        let mut request = self.request(method, url);
+        if self.send_request_id {
+            let request_id = Uuid::new_v4().to_string();
+            match sighting_id {
+                Some(id) => info!("Request {} for sighting {}", request_id, id),
+                None => info!("Request {} for {}", request_id, url),
+            }
+            request = request.header(REQUEST_ID, request_id);
+        }
+        let start = Instant::now();
+        let result = request.send().await;
+        let latency = start.elapsed();
+        self.request_timings.lock().unwrap().latencies.push(latency);
+        if let Some(tuner) = &self.auto_concurrency {
+            let pushed_back = match &result {
+                Ok(response) => response.status() == StatusCode::TOO_MANY_REQUESTS,
+                Err(_) => true,
+            };
+            tuner.observe(latency, pushed_back);
+        }
+        if let (Some(limit), Ok(response)) = (&self.soft_rate_limit, &result)
+            && response.status() != StatusCode::TOO_MANY_REQUESTS
+        {
+            limit.observe(latency);
+        }
+        result
     }
 
-    /// Save records to CSV file
-    pub fn save_to_csv(
+    /// Read a response body as text, archiving the exchange first when writing a WARC.
+    /// The body is read a chunk at a time and given up on as soon as it would pass
+    /// `max_body_bytes`, or at once when its declared length already does.
+    async fn read_body(&self, mut response: Response) -> Result<String, BodyError> {
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.max_body_bytes)
+        {
+            return Err(BodyError::Oversize);
+        }
+        let url = response.url().to_string();
+        let (version, status) = (response.version(), response.status());
+        let headers = response.headers().clone();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(BodyError::Read)? {
+            if (body.len() + chunk.len()) as u64 > self.max_body_bytes {
+                return Err(BodyError::Oversize);
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        if let Some(warc) = &self.warc {
+            let request = http_request(&Method::GET, &url, &self.headers);
+            let raw = http_response(version, status, &headers, &body);
+            if let Err(e) = warc.write_exchange(&url, &request, &raw) {
+                error!("Failed to archive {}: {}", url, e);
+            }
+        }
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Note a sighting page that redirected, warning when it landed on a different sighting
+    fn log_redirect(&self, sighting_id: u64, final_url: &str) {
+        match sighting_id_from_url(final_url) {
+            Some(id) if id != sighting_id => warn!(
+                "Sighting {} redirected to sighting {} at {}",
+                sighting_id, id, final_url
+            ),
+            _ => info!("Sighting {} redirected to {}", sighting_id, final_url),
+        }
+    }
+
+    /// Pause every request until `maintenance_cooldown` from now, after the site served its
+    /// maintenance page. Extends a pause already under way rather than stacking on it.
+    fn start_maintenance_cooldown(&self) {
+        let now = self.clock.now();
+        let until = now + self.maintenance_cooldown;
+        let mut paused_until = self.paused_until.lock().unwrap();
+        match *paused_until {
+            Some(current) if current >= until => {}
+            Some(current) if current > now => *paused_until = Some(until),
+            _ => {
+                warn!(
+                    "Site is down for maintenance, pausing for {}s",
+                    self.maintenance_cooldown.as_secs()
+                );
+                self.request_timings.lock().unwrap().maintenance_pauses += 1;
+                *paused_until = Some(until);
+            }
+        }
+    }
+
+    /// Sleep until any maintenance pause is over, returning `false` if the run is
+    /// cancelled first
+    async fn wait_out_maintenance(&self) -> bool {
+        loop {
+            let Some(until) = *self.paused_until.lock().unwrap() else {
+                return true;
+            };
+            let now = self.clock.now();
+            if until <= now {
+                return true;
+            }
+            if !self.sleep_unless_cancelled(until - now).await {
+                return false;
+            }
+        }
+    }
+
+    /// How long a 429's `Retry-After` asks us to wait, capped at `max_retry_after`
+    fn retry_after(&self, sighting_id: u64, headers: &HeaderMap) -> Option<Duration> {
+        let wait = parse_retry_after(headers, self.clock.utc_now())?;
+        if wait > self.max_retry_after {
+            warn!(
+                "Sighting {} asked to wait {}s, waiting {}s",
+                sighting_id,
+                wait.as_secs(),
+                self.max_retry_after.as_secs()
+            );
+            return Some(self.max_retry_after);
+        }
+        Some(wait)
+    }
+
+    /// Sleep before a request: the base delay with jitter on the first attempt, and on
+    /// retries the wait the server asked for with `Retry-After`, or else exponential
+    /// backoff with jitter. Returns `false` if the run is cancelled while waiting.
+    async fn wait_before_attempt(
         &self,
-        records: &[SightingRecord],
-        filename: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let file = File::create(filename)?;
-        let mut writer = Writer::from_writer(file);
+        sighting_id: u64,
+        attempt: u32,
+        retry_after: Option<Duration>,
+    ) -> bool {
+        if !self.wait_out_maintenance().await {
+            return false;
+        }
+        let base_delay = self.paced_delay();
+        if attempt > 0 {
+            self.request_timings.lock().unwrap().retries += 1;
+            let backoff_delay = retry_after.unwrap_or_else(|| {
+                backoff_delay(
+                    attempt,
+                    base_delay,
+                    BACKOFF_MULTIPLIER,
+                    Duration::MAX,
+                    base_delay,
+                    &mut rand::rng(),
+                )
+            });
+            info!(
+                "Retrying sighting {} (attempt {}) after {}ms delay{}",
+                sighting_id,
+                attempt + 1,
+                backoff_delay.as_millis(),
+                if retry_after.is_some() {
+                    " (Retry-After)"
+                } else {
+                    ""
+                }
+            );
+            self.sleep_unless_cancelled(backoff_delay).await
+        } else {
+            // The first attempt waits the base delay with up to half of it again as jitter
+            let initial_delay = backoff_delay(
+                0,
+                base_delay,
+                BACKOFF_MULTIPLIER,
+                Duration::MAX,
+                base_delay / 2,
+                &mut rand::rng(),
+            );
+            self.sleep_unless_cancelled(initial_delay).await
+        }
+    }
+
+    /// Rows of the page at `page_url`. With pagination, the rows of the pages its next
+    /// links lead to follow, until a page has none, `max_pages` are read, a link leads
+    /// back to a page already read, or a page can't be fetched; rows already read are kept.
+    async fn parse_pages(
+        &self,
+        sighting_id: u64,
+        page_url: &str,
+        html: &str,
+    ) -> Vec<SightingRecord> {
+        let mut rows = self.parser.parse(html);
+        let Some(pagination) = self.pagination else {
+            return rows;
+        };
+        let mut page_url = page_url.to_string();
+        let mut page_rows = rows.len();
+        let mut next = self.parser.next_page(html);
+        let mut visited = HashSet::from([page_url.clone()]);
+        let mut pages = 1;
+        while let Some(href) = next.take() {
+            if pagination
+                .records_per_page
+                .is_some_and(|full| page_rows < full)
+            {
+                break;
+            }
+            if pages >= pagination.max_pages {
+                warn!(
+                    "Sighting {} has more than {} pages; the rest are not read",
+                    sighting_id, pagination.max_pages
+                );
+                break;
+            }
+            let url = absolute_url(&page_url, &href);
+            if !visited.insert(url.clone()) {
+                warn!("Sighting {} pages link back to {}", sighting_id, url);
+                break;
+            }
+            self.clock.sleep(self.paced_delay()).await;
+            let html = match self.send(Method::GET, &url, Some(sighting_id)).await {
+                Ok(response) if response.status().is_success() => {
+                    match self.read_body(response).await {
+                        Ok(html) => html,
+                        Err(_) => {
+                            warn!("Failed to read page {} of sighting {}", url, sighting_id);
+                            break;
+                        }
+                    }
+                }
+                Ok(response) => {
+                    warn!(
+                        "HTTP error {} for page {} of sighting {}",
+                        response.status(),
+                        url,
+                        sighting_id
+                    );
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        "Request failed for page {} of sighting {}: {}",
+                        url, sighting_id, e
+                    );
+                    break;
+                }
+            };
+            let more = self.parser.parse(&html);
+            page_rows = more.len();
+            rows.extend(more);
+            next = self.parser.next_page(&html);
+            page_url = url;
+            pages += 1;
+        }
+        rows
+    }
 
-        for record in records {
-            writer.serialize(record)?;
+    /// Turn a page's rows into the sighting's records, noting any parse warnings against
+    /// the sighting ID
+    fn parse_and_check(&self, sighting_id: u64, rows: Vec<SightingRecord>) -> Vec<SightingRecord> {
+        // Each row on the page, and on any pages after it, is a record of its own with
+        // --all-rows. Otherwise a details page shows a single sighting, so later rows are
+        // ignored unless they come from following pages, where they fill in what the first
+        // left out.
+        let records: Vec<SightingRecord> = if self.all_rows {
+            rows
+        } else {
+            let mut rows = rows.into_iter();
+            rows.next()
+                .map(|mut record| {
+                    if self.pagination.is_some() {
+                        rows.for_each(|row| record.fill_empty_from(&row));
+                    }
+                    record
+                })
+                .into_iter()
+                .collect()
+        };
+        let warnings = if records.is_empty() {
+            vec![ParseWarning::NoRow]
+        } else {
+            let mut field_fill_stats = self.field_fill_stats.lock().unwrap();
+            records
+                .iter()
+                .flat_map(|record| {
+                    field_fill_stats.record(record);
+                    check_record(record)
+                })
+                .collect()
+        };
+        if !warnings.is_empty() {
+            let mut parse_warnings = self.parse_warnings.lock().unwrap();
+            parse_warnings.extend(warnings.into_iter().map(|warning| (sighting_id, warning)));
         }
+        records
+    }
 
-        writer.flush()?;
-        info!("Data saved to {}", filename);
-        Ok(())
+    /// Scrape a single sighting page by ID with exponential backoff retry, returning a
+    /// record per row kept from it with `with_all_rows(true)`, otherwise one at most. Rows
+    /// can all be filtered out, leaving none. A sighting that fails, or is still unresolved when the
+    /// per-ID timeout runs out, is marked missing and its failure returned. One cut short by
+    /// cancellation is not marked missing.
+    pub async fn scrape_sighting_page(
+        &self,
+        sighting_id: u64,
+    ) -> Result<Vec<SightingRecord>, ScraperError> {
+        let result = match self.per_id_timeout {
+            None => self.fetch_sighting_page(sighting_id).await,
+            Some(limit) => tokio::time::timeout(limit, self.fetch_sighting_page(sighting_id))
+                .await
+                .unwrap_or_else(|_| {
+                    error!(
+                        "Sighting {} missing (reason: timeout after {}ms)",
+                        sighting_id,
+                        limit.as_millis()
+                    );
+                    self.request_timings.lock().unwrap().timeouts += 1;
+                    Err(FailureReason::Timeout(limit))
+                }),
+        };
+        result.map_err(|reason| {
+            // A sighting cut short by cancellation hasn't failed, and is scraped on resume
+            if reason != FailureReason::Cancelled {
+                self.add_missing_sighting(sighting_id, reason.clone());
+            }
+            ScraperError::from_failure(sighting_id, reason)
+        })
+    }
+
+    /// Fetch and parse a sighting page, retrying with exponential backoff
+    async fn fetch_sighting_page(
+        &self,
+        sighting_id: u64,
+    ) -> Result<Vec<SightingRecord>, FailureReason> {
+        let url = self.sighting_url(sighting_id);
+
+        let mut attempts = 0..=self.max_retries;
+        // How long the last 429 asked us to wait, in place of the next backoff
+        let mut retry_after = None;
+        let mut maintenance_pages = 0;
+        while let Some(attempt) = attempts.next() {
+            if !self
+                .wait_before_attempt(sighting_id, attempt, retry_after.take())
+                .await
+            {
+                return Err(FailureReason::Cancelled);
+            }
+
+            match self.send(Method::GET, &url, Some(sighting_id)).await {
+                Ok(response) => match response.status().as_u16() {
+                    200..=299 => {
+                        // Where any redirects ended up, which is the page the record came from
+                        let final_url = response.url().to_string();
+                        if final_url != url {
+                            self.log_redirect(sighting_id, &final_url);
+                        }
+                        // Wrong content rather than markup the parser couldn't read
+                        if let Some(content_type) = non_html_content_type(response.headers()) {
+                            warn!("Sighting {} served {}, not HTML", sighting_id, content_type);
+                            *self
+                                .request_timings
+                                .lock()
+                                .unwrap()
+                                .non_html
+                                .entry(content_type)
+                                .or_default() += 1;
+                            return Err(FailureReason::NonHtml);
+                        }
+                        match self.read_body(response).await {
+                            Ok(html) if is_maintenance_page(&html) => {
+                                maintenance_pages += 1;
+                                if maintenance_pages > self.max_maintenance_pauses {
+                                    error!(
+                                        "Sighting {} missing (reason: site still down for maintenance after {} pauses)",
+                                        sighting_id, self.max_maintenance_pauses
+                                    );
+                                    return Err(FailureReason::Maintenance);
+                                }
+                                if self.is_cancelled() {
+                                    return Err(FailureReason::Cancelled);
+                                }
+                                // Not this sighting's fault, so the attempt is not used up
+                                self.start_maintenance_cooldown();
+                                attempts = attempt..=self.max_retries;
+                                continue;
+                            }
+                            Ok(html) => {
+                                let rows = self.parse_and_check(
+                                    sighting_id,
+                                    self.parse_pages(sighting_id, &final_url, &html).await,
+                                );
+                                return self.accept_rows(
+                                    sighting_id,
+                                    attempt,
+                                    &final_url,
+                                    &html,
+                                    rows,
+                                );
+                            }
+                            Err(BodyError::Oversize) => {
+                                // The same page would be just as large on a retry
+                                error!(
+                                    "Sighting {} missing (reason: body over {} bytes)",
+                                    sighting_id, self.max_body_bytes
+                                );
+                                return Err(FailureReason::Oversize);
+                            }
+                            Err(BodyError::Read(e)) => {
+                                // The connection can drop partway through the body
+                                if self.may_retry(attempt) {
+                                    warn!(
+                                        "Failed to read body for sighting {}, retrying...: {}",
+                                        sighting_id, e
+                                    );
+                                    continue;
+                                } else {
+                                    error!(
+                                        "Failed to read body for sighting {}, max retries reached: {}",
+                                        sighting_id, e
+                                    );
+                                    return Err(FailureReason::BodyRead);
+                                }
+                            }
+                        }
+                    }
+                    status if self.retry_statuses.contains(&status) && self.may_retry(attempt) => {
+                        if status == 429 {
+                            warn!("Rate limited for sighting {}, retrying...", sighting_id);
+                            retry_after = self.retry_after(sighting_id, response.headers());
+                        } else {
+                            warn!(
+                                "HTTP error {} for sighting {}, retrying...",
+                                response.status(),
+                                sighting_id
+                            );
+                        }
+                        continue;
+                    }
+                    429 if self.retry_statuses.contains(&429) => {
+                        error!(
+                            "Rate limited for sighting {}, max retries reached",
+                            sighting_id
+                        );
+                        return Err(FailureReason::RateLimited);
+                    }
+                    429 => {
+                        warn!(
+                            "Rate limited for sighting {}, not retried as 429 is not in --retry-statuses",
+                            sighting_id
+                        );
+                        return Err(FailureReason::Http(429));
+                    }
+                    status if self.retry_statuses.contains(&status) => {
+                        error!(
+                            "HTTP error {} for sighting {}, max retries reached",
+                            status, sighting_id
+                        );
+                        return Err(FailureReason::Http(status));
+                    }
+                    // Not worth retrying, as a 404 is final
+                    status => return Err(FailureReason::Http(status)),
+                },
+                Err(e) if e.is_redirect() => {
+                    // A loop or over-long chain redirects the same way on every attempt
+                    error!(
+                        "Sighting {} missing (reason: too many redirects, limit {})",
+                        sighting_id, self.max_redirects
+                    );
+                    return Err(FailureReason::TooManyRedirects);
+                }
+                Err(e) => {
+                    if self.may_retry(attempt) {
+                        warn!(
+                            "Request failed for sighting {}, retrying...: {}",
+                            sighting_id, e
+                        );
+                        continue;
+                    } else {
+                        error!(
+                            "Request failed for sighting {}, max retries reached: {}",
+                            sighting_id, e
+                        );
+                        return Err(FailureReason::RequestFailed);
+                    }
+                }
+            }
+        }
+
+        error!(
+            "Failed to scrape sighting {} after {} attempts",
+            sighting_id,
+            self.max_retries + 1
+        );
+        Err(FailureReason::RetriesExhausted)
+    }
+
+    /// Keep the rows parsed from a sighting's page as its records, stamped with the
+    /// sighting's ID, URL and scrape time. A page without rows, or whose rows are all
+    /// empty, fails the sighting. Rows below `min_fields` or of an unselected
+    /// specimen type are dropped without marking it missing.
+    fn accept_rows(
+        &self,
+        sighting_id: u64,
+        attempt: u32,
+        final_url: &str,
+        html: &str,
+        rows: Vec<SightingRecord>,
+    ) -> Result<Vec<SightingRecord>, FailureReason> {
+        if rows.is_empty() {
+            warn!("No data found for sighting {}", sighting_id);
+            self.dump_failed_html(sighting_id, html);
+            return Err(FailureReason::NoData);
+        }
+        let row_count = rows.len();
+        let rows: Vec<SightingRecord> = rows
+            .into_iter()
+            .filter(|record| self.include_empty || !record.is_sparse())
+            .collect();
+        if rows.is_empty() {
+            warn!("Discarding empty record for sighting {}", sighting_id);
+            self.dump_failed_html(sighting_id, html);
+            return Err(FailureReason::EmptyRecord);
+        }
+        if rows.len() < row_count {
+            warn!(
+                "Discarding {} empty rows for sighting {}",
+                row_count - rows.len(),
+                sighting_id
+            );
+        }
+
+        let records: Vec<SightingRecord> = rows
+            .into_iter()
+            .filter(|record| self.is_selected(sighting_id, record))
+            .map(|mut record| {
+                record.sighting_id = Some(sighting_id);
+                record.submitted_by_url = absolute_url(final_url, &record.submitted_by_url);
+                record.verified_by_url = absolute_url(final_url, &record.verified_by_url);
+                record.url = Some(final_url.to_string());
+                record.scraped_at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+                if let Some(name_map) = &self.name_map {
+                    name_map.apply(&mut record);
+                }
+                record
+            })
+            .collect();
+        if records.is_empty() {
+            return Ok(records);
+        }
+
+        if attempt > 0 {
+            info!(
+                "Successfully scraped sighting {} on attempt {}",
+                sighting_id,
+                attempt + 1
+            );
+        } else {
+            info!("Successfully scraped sighting {}", sighting_id);
+        }
+        self.records_scraped.fetch_add(1, Ordering::SeqCst);
+        self.emit(&LifecycleEvent::IdSuccess {
+            sighting_id,
+            attempts: attempt + 1,
+        });
+        for record in &records {
+            self.send_event(&ControlEvent::Record { record });
+        }
+        Ok(records)
+    }
+
+    /// Whether a record passes `min_fields` and the specimen type filter, counting the
+    /// ones that don't
+    fn is_selected(&self, sighting_id: u64, record: &SightingRecord) -> bool {
+        if record.filled_fields() < self.min_fields {
+            // The page was there, so this is not a missing sighting
+            info!(
+                "Dropping sighting {}: {} of {} required fields filled",
+                sighting_id,
+                record.filled_fields(),
+                self.min_fields
+            );
+            self.below_min_fields.fetch_add(1, Ordering::SeqCst);
+            return false;
+        }
+        if !self.specimen_types.is_empty()
+            && !record
+                .specimen_type()
+                .is_some_and(|t| self.specimen_types.contains(&t))
+        {
+            info!(
+                "Dropping sighting {}: specimen type {:?} not selected",
+                sighting_id, record.specimen_type
+            );
+            self.other_specimen_types.fetch_add(1, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+
+    /// Run `task` for every sighting ID, with its position in `sighting_ids`, with at most
+    /// `max_concurrent` in flight, advancing a progress bar as each finishes, or as its
+    /// records are written to `output` when there is one. Results are in input order, with
+    /// `None` for IDs that were never started because the run was cancelled.
+    async fn run_concurrently<'a, T, F, Fut>(
+        &'a self,
+        sighting_ids: &[u64],
+        max_concurrent: usize,
+        message: &'static str,
+        output: Option<&'a OrderedOutput>,
+        task: F,
+    ) -> Vec<Option<T>>
+    where
+        F: Fn(usize, u64) -> Fut,
+        Fut: Future<Output = T> + 'a,
+    {
+        // Create progress bar
+        let progress_bar = ProgressBar::new(sighting_ids.len() as u64);
+        progress_bar.set_style(
+            ProgressStyle::default_bar()
+                .template(&self.progress_template)
+                // Parsed when it was set
+                .unwrap()
+                .progress_chars("##-"),
+        );
+        progress_bar.set_message(message);
+
+        // With auto concurrency every run shares the tuned limit, carrying it across passes
+        let (semaphore, max_concurrent) = match &self.auto_concurrency {
+            Some(tuner) => (tuner.semaphore(), tuner.limit()),
+            None => (
+                Arc::new(tokio::sync::Semaphore::new(max_concurrent)),
+                max_concurrent,
+            ),
+        };
+        let mut tasks = Vec::new();
+        let pb = Arc::new(progress_bar);
+
+        for (index, &sighting_id) in sighting_ids.iter().enumerate() {
+            let permit = Arc::clone(&semaphore);
+            let progress = Arc::clone(&pb);
+            // Tagged so every line logged for this ID, retries included, can be grepped out
+            let future = with_task_tag(TaskTag { index, sighting_id }, task(index, sighting_id));
+            let offset = start_offset(index, max_concurrent, self.base_delay, &mut rand::rng());
+
+            let task = async move {
+                let _permit = permit.acquire().await.unwrap();
+                // Smear the first wave so it doesn't fire as one burst
+                if !offset.is_zero() {
+                    self.clock.sleep(offset).await;
+                }
+                if self.is_cancelled() {
+                    return None;
+                }
+                let result = future.await;
+                match output {
+                    // Written once every earlier position is, which may be this one's doing
+                    Some(output) => progress.inc(output.take_written_positions()),
+                    None => progress.inc(1),
+                }
+                self.send_progress(&progress, sighting_id);
+                Some(result)
+            };
+
+            tasks.push(task);
+        }
+
+        let results = match self.burst {
+            Some((size, pause)) => self.run_in_bursts(tasks, size, pause).await,
+            None => join_all(tasks).await,
+        };
+        if self.is_cancelled() {
+            let skipped = results.iter().filter(|result| result.is_none()).count();
+            warn!("Run cancelled, {} sightings were not started", skipped);
+        }
+        results
+    }
+
+    /// Run `tasks` `size` at a time, pausing between bursts for `pause` plus up to half as
+    /// much again. Tasks left after a cancellation are still run, and return at once.
+    async fn run_in_bursts<Fut: Future>(
+        &self,
+        tasks: Vec<Fut>,
+        size: usize,
+        pause: Duration,
+    ) -> Vec<Fut::Output> {
+        let mut results = Vec::with_capacity(tasks.len());
+        let mut tasks = tasks.into_iter().peekable();
+
+        while tasks.peek().is_some() {
+            if !results.is_empty() && !self.is_cancelled() {
+                let jitter = rand::rng().random_range(0..=pause.as_millis() as u64 / 2);
+                let pause = pause + Duration::from_millis(jitter);
+                info!("Burst done, pausing for {}ms", pause.as_millis());
+                self.sleep_unless_cancelled(pause).await;
+            }
+            results.extend(join_all(tasks.by_ref().take(size)).await);
+        }
+        results
+    }
+
+    /// Send a progress event after `sighting_id` finished, if anyone is listening
+    fn send_progress(&self, progress: &ProgressBar, sighting_id: u64) {
+        if self.events.is_none() {
+            return;
+        }
+        let completed = progress.position();
+        self.send_event(&ControlEvent::Progress {
+            completed,
+            total: progress.length().unwrap_or(0),
+            records: self.records_scraped.load(Ordering::SeqCst),
+            missing: self.missing_sightings.lock().unwrap().len(),
+            rate: completed as f64 / progress.elapsed().as_secs_f64().max(f64::EPSILON),
+            current_id: sighting_id,
+        });
+    }
+
+    /// Check whether a sighting page exists with a HEAD request, without downloading it.
+    /// Returns `None` if neither a success nor a 404/410 came back within the retries.
+    pub async fn sighting_exists(&self, sighting_id: u64) -> Option<bool> {
+        let url = self.sighting_url(sighting_id);
+
+        let mut retry_after = None;
+        for attempt in 0..=self.max_retries {
+            if !self
+                .wait_before_attempt(sighting_id, attempt, retry_after.take())
+                .await
+            {
+                return None;
+            }
+
+            match self.send(Method::HEAD, &url, Some(sighting_id)).await {
+                Ok(response) => match response.status().as_u16() {
+                    200..=299 => return Some(true),
+                    404 | 410 => return Some(false),
+                    status if self.retry_statuses.contains(&status) && self.may_retry(attempt) => {
+                        if status == 429 {
+                            retry_after = self.retry_after(sighting_id, response.headers());
+                        }
+                        warn!(
+                            "HTTP error {} probing sighting {}, retrying...",
+                            response.status(),
+                            sighting_id
+                        );
+                    }
+                    status if self.retry_statuses.contains(&status) => {
+                        error!(
+                            "HTTP error {} probing sighting {}, max retries reached",
+                            status, sighting_id
+                        );
+                        return None;
+                    }
+                    status => {
+                        error!(
+                            "HTTP error {} probing sighting {}, not retried as it is not in --retry-statuses",
+                            status, sighting_id
+                        );
+                        return None;
+                    }
+                },
+                Err(e) if self.may_retry(attempt) => warn!(
+                    "Probe failed for sighting {}, retrying...: {}",
+                    sighting_id, e
+                ),
+                Err(e) => {
+                    error!(
+                        "Probe failed for sighting {}, max retries reached: {}",
+                        sighting_id, e
+                    );
+                    return None;
+                }
+            }
+        }
+
+        error!(
+            "Failed to probe sighting {} after {} attempts",
+            sighting_id,
+            self.max_retries + 1
+        );
+        None
+    }
+
+    /// Find the highest existing sighting ID at or above `low`, probing one at a time with
+    /// `sighting_exists`. Starting from the guess `high`, the upper bound doubles until
+    /// nothing exists above it, and the boundary is then binary-searched. IDs are not
+    /// contiguous, so each probe checks up to `gap` consecutive IDs and only a run of `gap`
+    /// missing IDs counts as the end.
+    pub async fn find_max_sighting(
+        &self,
+        low: u64,
+        high: u64,
+        gap: u64,
+    ) -> Result<u64, ScraperError> {
+        let Some(mut lo) = self.first_existing(low, gap).await? else {
+            return Err(ScraperError::NoSightings {
+                first: low,
+                last: low + gap - 1,
+            });
+        };
+        let mut hi = high.max(lo + 1);
+        while let Some(found) = self.first_existing(hi, gap).await? {
+            lo = found;
+            hi = hi.saturating_mul(2).max(found + 1);
+            info!(
+                "Sighting {} exists, raising the upper bound to {}",
+                found, hi
+            );
+        }
+
+        // Something exists at lo, and nothing in the `gap` IDs from hi
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            match self.first_existing(mid, gap).await? {
+                Some(found) => lo = found,
+                None => hi = mid,
+            }
+        }
+        Ok(lo)
+    }
+
+    /// The first of the `gap` IDs from `start` that exists, if any
+    async fn first_existing(&self, start: u64, gap: u64) -> Result<Option<u64>, ScraperError> {
+        for sighting_id in start..start.saturating_add(gap) {
+            match self.sighting_exists(sighting_id).await {
+                Some(true) => return Ok(Some(sighting_id)),
+                Some(false) => {}
+                None => {
+                    return Err(ScraperError::from_failure(
+                        sighting_id,
+                        FailureReason::RetriesExhausted,
+                    ));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Probe sighting IDs with HEAD requests, returning those that exist in input order
+    pub async fn find_existing_sightings(
+        &self,
+        sighting_ids: &[u64],
+        max_concurrent: usize,
+    ) -> Vec<u64> {
+        let results = self
+            .run_concurrently(
+                sighting_ids,
+                max_concurrent,
+                "Probing sightings",
+                None,
+                |_, sighting_id| self.sighting_exists(sighting_id),
+            )
+            .await;
+        let results: Vec<Option<bool>> = results.into_iter().map(Option::flatten).collect();
+
+        let unknown = results.iter().filter(|exists| exists.is_none()).count();
+        let existing: Vec<u64> = sighting_ids
+            .iter()
+            .zip(results)
+            .filter(|(_, exists)| *exists == Some(true))
+            .map(|(sighting_id, _)| *sighting_id)
+            .collect();
+        info!(
+            "Found {} existing sightings out of {} probed ({} unknown)",
+            existing.len(),
+            sighting_ids.len(),
+            unknown
+        );
+
+        existing
+    }
+
+    /// Collect sighting IDs from a sitemap, following sitemap index files to their children.
+    /// IDs are returned in the order listed, without duplicates.
+    pub async fn fetch_sitemap_ids(&self, sitemap_url: &str) -> Result<Vec<u64>, ScraperError> {
+        let mut queue = VecDeque::from([sitemap_url.to_string()]);
+        let mut visited = HashSet::new();
+        let mut seen_ids = HashSet::new();
+        let mut sighting_ids = Vec::new();
+
+        while let Some(url) = queue.pop_front() {
+            if !visited.insert(url.clone()) {
+                continue;
+            }
+            if visited.len() > MAX_SITEMAPS {
+                warn!("Stopped after fetching {} sitemaps", MAX_SITEMAPS);
+                break;
+            }
+
+            let xml = self
+                .send(Method::GET, &url, None)
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            let sitemap = parse_sitemap(&xml)?;
+            info!(
+                "Sitemap {} lists {} pages and {} sitemaps",
+                url,
+                sitemap.urls.len(),
+                sitemap.sitemaps.len()
+            );
+
+            queue.extend(sitemap.sitemaps);
+            for sighting_id in sitemap.urls.iter().filter_map(|u| sighting_id_from_url(u)) {
+                if seen_ids.insert(sighting_id) {
+                    sighting_ids.push(sighting_id);
+                }
+            }
+        }
+
+        info!("Found {} sighting IDs in sitemaps", sighting_ids.len());
+        Ok(sighting_ids)
+    }
+
+    /// Scrape multiple sighting pages concurrently. Sightings that fail go to the missing
+    /// list; with `with_fail_fast`, the failure that stopped the run is returned instead.
+    pub async fn scrape_multiple_sightings(
+        &self,
+        sighting_ids: &[u64],
+        max_concurrent: usize,
+    ) -> Result<Vec<SightingRecord>, ScraperError> {
+        self.scrape_ids(sighting_ids, max_concurrent, self.ordered_output.as_ref())
+            .await
+    }
+
+    /// Scrape `sighting_ids`, returning the records kept, or writing them to `output` as
+    /// they finish and returning nothing when there is one. Fails with the failure that
+    /// stopped a `--fail-fast` run.
+    async fn scrape_ids(
+        &self,
+        sighting_ids: &[u64],
+        max_concurrent: usize,
+        output: Option<&OrderedOutput>,
+    ) -> Result<Vec<SightingRecord>, ScraperError> {
+        let mut filtered_sightings_ids = self.filter_missing_sightings(sighting_ids);
+        // Permits are handed out in dispatch order, so this order is the order IDs start in
+        prioritize(&mut filtered_sightings_ids, &self.priorities);
+
+        // Only IDs missing from this pass count against the density
+        let missing_before = self.get_missing_sightings().len();
+        let completed = Arc::new(AtomicU64::new(0));
+        let results = self
+            .run_concurrently(
+                &filtered_sightings_ids,
+                max_concurrent,
+                "Scraping sightings",
+                output,
+                |position, sighting_id| {
+                    let completed = Arc::clone(&completed);
+                    async move {
+                        if let Some(output) = output {
+                            output.wait_for_room(position).await;
+                        }
+                        let records = self
+                            .scrape_sighting_page(sighting_id)
+                            .await
+                            .unwrap_or_default();
+                        if let Some(check) = &self.density_check
+                            && completed.fetch_add(1, Ordering::SeqCst) + 1 == check.window
+                        {
+                            self.check_density(check, missing_before);
+                        }
+                        match output {
+                            Some(output) => {
+                                // Written once every earlier position is, and not kept
+                                let records = records
+                                    .into_iter()
+                                    .filter_map(|mut record| {
+                                        self.keep_record(&mut record).then_some(record)
+                                    })
+                                    .collect();
+                                if !output.complete(position, records) {
+                                    self.cancel.cancel();
+                                }
+                                Vec::new()
+                            }
+                            None => records,
+                        }
+                    }
+                },
+            )
+            .await;
+        let successful_records: Vec<SightingRecord> =
+            results.into_iter().flatten().flatten().collect();
+
+        let missing_count = self.get_missing_sightings().len();
+        info!(
+            "Successfully scraped {} out of {} sightings ({} unfound)",
+            successful_records.len(),
+            sighting_ids.len(),
+            missing_count
+        );
+
+        match self.get_first_failure() {
+            Some((sighting_id, reason)) => Err(ScraperError::from_failure(sighting_id, reason)),
+            None => Ok(successful_records),
+        }
+    }
+
+    /// Compare the share of the first `check.window` IDs that were found, rather than
+    /// marked missing, with the minimum, stopping the run below it if asked to
+    fn check_density(&self, check: &DensityCheck, missing_before: usize) {
+        let missing = self
+            .get_missing_sightings()
+            .len()
+            .saturating_sub(missing_before) as u64;
+        let density = check.window.saturating_sub(missing) as f64 / check.window as f64;
+        if density >= check.min_density {
+            info!(
+                "Found {:.1}% of the first {} sightings",
+                density * 100.0,
+                check.window
+            );
+            return;
+        }
+        status!(
+            "Only {:.1}% of the first {} sightings were found, below --min-density {}{}",
+            density * 100.0,
+            check.window,
+            check.min_density,
+            if check.abort { "; stopping" } else { "" }
+        );
+        if check.abort {
+            self.cancel.cancel();
+        }
+    }
+
+    /// Scrape the sightings in the missing list again. IDs that succeed leave the list;
+    /// IDs that fail count another attempt and, once they have failed in
+    /// `max_lifetime_attempts` runs, move to the dead-letter file.
+    pub async fn retry_missing_sightings(
+        &self,
+        max_concurrent: usize,
+        max_lifetime_attempts: u32,
+    ) -> Result<Vec<SightingRecord>, ScraperError> {
+        let sighting_ids = self.get_missing_sightings();
+        info!("Retrying {} missing sightings", sighting_ids.len());
+
+        let results = self
+            .run_concurrently(
+                &sighting_ids,
+                max_concurrent,
+                "Retrying missing sightings",
+                None,
+                |_, sighting_id| self.scrape_sighting_page(sighting_id),
+            )
+            .await;
+        let records: Vec<SightingRecord> = results
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .flatten()
+            .collect();
+        let recovered = self.remove_recovered(&records);
+
+        let exhausted = self.dead_letter_exhausted(max_lifetime_attempts);
+        self.save_missing_sightings()?;
+        info!(
+            "Recovered {} of {} missing sightings; {} moved to dead letters",
+            recovered.len(),
+            sighting_ids.len(),
+            exhausted.len()
+        );
+
+        Ok(records)
+    }
+
+    /// Give sightings that failed earlier in this run one more try after a fresh base delay.
+    /// IDs that succeed leave the missing list. IDs that fail again stay in it without
+    /// counting another lifetime attempt, since it is still the same run.
+    pub async fn retry_failed_sightings(
+        &self,
+        sighting_ids: &[u64],
+        max_concurrent: usize,
+    ) -> Result<Vec<SightingRecord>, ScraperError> {
+        let attempts_before: HashMap<u64, u32> = {
+            let attempts = self.missing_attempts.lock().unwrap();
+            sighting_ids
+                .iter()
+                .filter_map(|id| attempts.get(id).map(|count| (*id, *count)))
+                .collect()
+        };
+        self.clock.sleep(self.base_delay).await;
+
+        let results = self
+            .run_concurrently(
+                sighting_ids,
+                max_concurrent,
+                "Retrying failed sightings",
+                None,
+                |_, sighting_id| self.scrape_sighting_page(sighting_id),
+            )
+            .await;
+        let records: Vec<SightingRecord> = results
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .flatten()
+            .collect();
+        let recovered = self.remove_recovered(&records);
+
+        self.missing_attempts.lock().unwrap().extend(
+            attempts_before
+                .into_iter()
+                .filter(|(id, _)| !recovered.contains(id)),
+        );
+        self.save_missing_sightings()?;
+
+        Ok(records)
+    }
+
+    /// Take the sightings of freshly scraped records off the missing list, returning their IDs
+    fn remove_recovered(&self, records: &[SightingRecord]) -> HashSet<u64> {
+        let recovered: HashSet<u64> = records.iter().filter_map(|r| r.sighting_id).collect();
+        self.missing_sightings
+            .lock()
+            .unwrap()
+            .retain(|id| !recovered.contains(id));
+        let mut attempts = self.missing_attempts.lock().unwrap();
+        for sighting_id in &recovered {
+            attempts.remove(sighting_id);
+        }
+        recovered
+    }
+
+    /// Scrape a range of sighting IDs, requested in order from `start_id` to `end_id`.
+    /// The range is scraped high to low when `start_id` is greater than `end_id`.
+    pub async fn scrape_sighting_range(
+        &self,
+        start_id: u64,
+        end_id: u64,
+        max_concurrent: usize,
+    ) -> Result<Vec<SightingRecord>, ScraperError> {
+        let sighting_ids = sighting_id_range(start_id, end_id);
+        self.scrape_multiple_sightings(&sighting_ids, max_concurrent)
+            .await
+    }
+
+    /// Scrape a range of sighting IDs as `scrape_sighting_range` does, writing each record
+    /// to the CSV at `output_path` once every earlier ID has finished rather than returning
+    /// them. At most `max_concurrent` records are held waiting, and the file holds every
+    /// record scraped up to the first ID still in flight if the run stops early. Returns
+    /// the number of records written.
+    pub async fn scrape_range_streaming(
+        &self,
+        start_id: u64,
+        end_id: u64,
+        max_concurrent: usize,
+        output_path: &str,
+    ) -> Result<usize, ScraperError> {
+        let writer = RecordWriter::create(
+            output_path,
+            OutputFormat::Csv,
+            &self.tag_names(),
+            self.excel_csv,
+            self.csv_headers,
+            &self.null_value,
+        )?;
+        let output = OrderedOutput::new(writer, max_concurrent).with_flush_every(self.flush_every);
+        let sighting_ids = sighting_id_range(start_id, end_id);
+        let scraped = self
+            .scrape_ids(&sighting_ids, max_concurrent, Some(&output))
+            .await;
+        // Records written before a --fail-fast stop are kept in the file
+        let written = output
+            .finish()
+            .map_err(|e| ScraperError::Output(e.into()))?;
+        scraped.map(|_| written)
+    }
+
+    /// Save records to CSV file
+    pub fn save_to_csv(
+        &self,
+        records: &[SightingRecord],
+        filename: &str,
+    ) -> Result<(), ScraperError> {
+        if self.compact_csv {
+            let dropped = write_compact_csv(records, filename, self.excel_csv, &self.null_value)?;
+            if !dropped.is_empty() {
+                status!(
+                    "Dropped empty columns from {}: {}",
+                    filename,
+                    dropped.join(", ")
+                );
+            }
+        } else {
+            write_csv(
+                records,
+                filename,
+                self.excel_csv,
+                self.csv_headers,
+                &self.null_value,
+            )?;
+        }
+        log_saved(filename);
+        Ok(())
+    }
+
+    /// Save records to newline-delimited JSON file
+    pub fn save_to_ndjson(
+        &self,
+        records: &[SightingRecord],
+        filename: &str,
+    ) -> Result<(), ScraperError> {
+        write_ndjson(records, filename, &self.null_value)?;
+        log_saved(filename);
+        Ok(())
+    }
+
+    /// Save records to a JSON file holding one array
+    pub fn save_to_json(
+        &self,
+        records: &[SightingRecord],
+        filename: &str,
+    ) -> Result<(), ScraperError> {
+        write_json(records, filename, &self.null_value)?;
+        log_saved(filename);
+        Ok(())
+    }
+
+    /// Save records to a file in the given format
+    pub fn save_to_format(
+        &self,
+        records: &[SightingRecord],
+        filename: &str,
+        format: OutputFormat,
+    ) -> Result<(), ScraperError> {
+        match format {
+            OutputFormat::Csv => self.save_to_csv(records, filename),
+            OutputFormat::Ndjson => self.save_to_ndjson(records, filename),
+            OutputFormat::Json => self.save_to_json(records, filename),
+            OutputFormat::Dwc => {
+                write_dwc(records, filename)?;
+                log_saved(filename);
+                Ok(())
+            }
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => {
+                write_parquet(records, filename)?;
+                log_saved(filename);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The media type of a response that declares itself something other than HTML, such as
+/// `application/pdf`. Responses without a `Content-Type` are taken to be HTML.
+fn non_html_content_type(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().unwrap_or_default();
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match media_type.as_str() {
+        "text/html" | "application/xhtml+xml" => None,
+        "" => Some(content_type.trim().to_string()).filter(|raw| !raw.is_empty()),
+        _ => Some(media_type),
+    }
+}
+
+/// The wait a `Retry-After` header asks for, given either as seconds or as an HTTP date
+/// (a date already past asks for none). `None` when the header is absent or unparsable.
+fn parse_retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Why a page body was not read
+#[derive(Debug)]
+enum BodyError {
+    /// It was longer than the scraper's `max_body_bytes`
+    Oversize,
+    /// The connection failed partway through
+    Read(reqwest::Error),
+}
+
+/// Note where records were saved, unless they went to stdout
+fn log_saved(filename: &str) {
+    if filename != STDOUT {
+        info!("Data saved to {}", filename);
+    }
+}
+
+/// Whether a missing or dead-letter file is gzipped, which its `.gz` extension says
+fn is_gzip(filename: &str) -> bool {
+    filename.ends_with(".gz")
+}
+
+/// Read `id,attempts` lines from a missing or dead-letter file
+fn read_missing_file(filename: &str) -> std::io::Result<Vec<(u64, u32)>> {
+    missing_file_entries(filename)?.collect()
+}
+
+/// The `id,attempts` lines of a missing or dead-letter file, read one at a time.
+/// Lines holding only an ID, as written by earlier versions, count as one attempt.
+/// A gzipped file may be a run of gzip members, one per appended line; a member cut
+/// short by a crash ends the file rather than failing it.
+fn missing_file_entries(
+    filename: &str,
+) -> std::io::Result<impl Iterator<Item = std::io::Result<(u64, u32)>>> {
+    let file = File::open(filename)?;
+    let gzip = is_gzip(filename);
+    let reader: Box<dyn BufRead> = if gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let filename = filename.to_string();
+
+    let lines = reader.lines().map_while(move |line| match line {
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && gzip => {
+            warn!("Ignoring truncated end of {}", filename);
+            None
+        }
+        line => Some(line),
+    });
+    Ok(lines.filter_map(|line| {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        let (id, attempts) = match line.trim().split_once(',') {
+            Some((id, attempts)) => (id, attempts.trim().parse().unwrap_or(1)),
+            None => (line.trim(), 1),
+        };
+        id.trim()
+            .parse::<u64>()
+            .ok()
+            .map(|sighting_id| Ok((sighting_id, attempts)))
+    }))
+}
+
+/// Lines a gzipped missing or dead-letter file collects before they are written out
+/// together as one gzip member
+const GZIP_MEMBER_LINES: usize = 100;
+
+/// Append one `id,attempts` line to a missing or dead-letter file. A plain file gets it
+/// at once. A finished gzip stream can't be extended, so a gzipped file's lines wait in
+/// `pending` and go out as one gzip member every `GZIP_MEMBER_LINES` lines, or when
+/// flushed; a crash loses the lines still waiting.
+fn append_missing_line(
+    filename: &str,
+    pending: &mut Vec<(u64, u32)>,
+    sighting_id: u64,
+    attempts: u32,
+) -> std::io::Result<()> {
+    if !is_gzip(filename) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(filename)?;
+        writeln!(file, "{},{}", sighting_id, attempts)?;
+        return file.flush();
+    }
+    pending.push((sighting_id, attempts));
+    if pending.len() >= GZIP_MEMBER_LINES {
+        flush_missing_lines(filename, pending)?;
+    }
+    Ok(())
+}
+
+/// Append the lines waiting in `pending` to a gzipped missing or dead-letter file as one
+/// gzip member
+fn flush_missing_lines(filename: &str, pending: &mut Vec<(u64, u32)>) -> std::io::Result<()> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    write_missing_lines(&mut encoder, pending)?;
+    let member = encoder.finish()?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(filename)?;
+    // One write, so a crash leaves at most the last member incomplete
+    file.write_all(&member)?;
+    file.flush()?;
+    pending.clear();
+    Ok(())
+}
+
+/// Flush a gzipped missing or dead-letter file's waiting lines, then rewrite it as a
+/// single gzip member. A plain file is left as it is.
+fn repack_missing_file(filename: &str, pending: &mut Vec<(u64, u32)>) -> std::io::Result<()> {
+    if !is_gzip(filename) {
+        return Ok(());
+    }
+    flush_missing_lines(filename, pending)?;
+    if !Path::new(filename).exists() {
+        return Ok(());
+    }
+    let entries = read_missing_file(filename)?;
+    write_missing_file(filename, &entries)
+}
+
+/// Write a missing file from scratch with one `id,attempts` line per entry, gzipped when
+/// its name ends in `.gz`
+pub fn write_missing_file(filename: &str, entries: &[(u64, u32)]) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(filename)?);
+    if is_gzip(filename) {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        write_missing_lines(&mut encoder, entries)?;
+        encoder.finish()?.flush()
+    } else {
+        let mut file = file;
+        write_missing_lines(&mut file, entries)?;
+        file.flush()
+    }
+}
+
+fn write_missing_lines<W: Write>(writer: &mut W, entries: &[(u64, u32)]) -> std::io::Result<()> {
+    for (sighting_id, attempts) in entries {
+        writeln!(writer, "{},{}", sighting_id, attempts)?;
+    }
+    Ok(())
+}
+
+/// Inclusive list of IDs from `start_id` to `end_id`, descending if `start_id > end_id`
+pub fn sighting_id_range(start_id: u64, end_id: u64) -> Vec<u64> {
+    if start_id <= end_id {
+        (start_id..=end_id).collect()
+    } else {
+        (end_id..=start_id).rev().collect()
+    }
+}
+
+/// Resolve a link found on the page at `page_url` to an absolute URL. Empty links stay
+/// empty, and links that cannot be resolved are kept as found.
+pub(crate) fn absolute_url(page_url: &str, href: &str) -> String {
+    if href.is_empty() {
+        return String::new();
+    }
+    Url::parse(page_url)
+        .and_then(|base| base.join(href))
+        .map(String::from)
+        .unwrap_or_else(|_| href.to_string())
+}
+
+/// HTTP client sharing `cookie_jar` and following up to `max_redirects` redirects
+fn build_client(cookie_jar: &Arc<Jar>, max_redirects: usize) -> Client {
+    Client::builder()
+        .cookie_provider(Arc::clone(cookie_jar))
+        .redirect(Policy::limited(max_redirects))
+        .timeout(Duration::from_secs(10))
+        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
+        .build()
+        .expect("Failed to create HTTP client")
+}
+
+/// Stable-sort IDs by descending priority; IDs with equal priorities keep their order
+fn prioritize(sighting_ids: &mut [u64], priorities: &HashMap<u64, i64>) {
+    if priorities.is_empty() {
+        return;
+    }
+    sighting_ids.sort_by_key(|id| std::cmp::Reverse(priorities.get(id).copied().unwrap_or(0)));
+}
+
+/// Delay before attempt number `attempt` (0 for the first request): `base` times
+/// `multiplier` to the power of `attempt`, capped at `max`, plus a random jitter below
+/// `jitter`. The cap applies before the jitter is added.
+fn backoff_delay(
+    attempt: u32,
+    base: Duration,
+    multiplier: f64,
+    max: Duration,
+    jitter: Duration,
+    rng: &mut impl Rng,
+) -> Duration {
+    let scaled = base.as_secs_f64() * multiplier.powf(f64::from(attempt));
+    let delay = Duration::try_from_secs_f64(scaled).unwrap_or(max).min(max);
+    let jitter_ms = jitter.as_millis() as u64;
+    if jitter_ms == 0 {
+        return delay;
+    }
+    delay.saturating_add(Duration::from_millis(rng.random_range(0..jitter_ms)))
+}
+
+/// Extra delay before the task at `index` starts. Tasks in the first wave of `max_concurrent`
+/// each get a random offset within their own slice of `base_delay`, so the wave is spread
+/// over one base delay; later tasks start as soon as a permit frees up.
+fn start_offset(
+    index: usize,
+    max_concurrent: usize,
+    base_delay: Duration,
+    rng: &mut impl Rng,
+) -> Duration {
+    if index >= max_concurrent {
+        return Duration::ZERO;
+    }
+
+    let slot = base_delay.as_millis() as u64 / max_concurrent as u64;
+    if slot == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(index as u64 * slot + rng.random_range(0..slot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::output::{read_csv, read_records};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::time::sleep;
+
+    const SIGHTING_HTML: &str = include_str!("../tests/fixtures/sighting.html");
+
+    /// Serve one canned HTTP response per connection, in order, returning the base URL
+    async fn serve_responses(responses: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        base_url
+    }
+
+    fn ok_response(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    #[tokio::test]
+    async fn test_cookies_sent_and_kept_across_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let set_cookie = format!(
+            "HTTP/1.1 200 OK\r\nSet-Cookie: session=abc; Path=/\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            SIGHTING_HTML.len(),
+            SIGHTING_HTML
+        );
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in [set_cookie, ok_response(SIGHTING_HTML)] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 4096];
+                let read = socket.read(&mut request).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request[..read]).to_lowercase());
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_cookies(&["theme=dark".to_string()])
+            .with_delay(10);
+        assert!(scraper.scrape_sighting_page(1).await.is_ok());
+        assert!(scraper.scrape_sighting_page(2).await.is_ok());
+
+        let requests = server.await.unwrap();
+        assert!(
+            requests[0].contains("cookie: theme=dark"),
+            "{}",
+            requests[0]
+        );
+        assert!(requests[1].contains("session=abc"), "{}", requests[1]);
+        assert!(requests[1].contains("theme=dark"), "{}", requests[1]);
+    }
+
+    fn redirect_response(location: &str) -> String {
+        format!(
+            "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            location
+        )
+    }
+
+    #[tokio::test]
+    async fn test_redirect_final_url_recorded() {
+        let base_url = serve_responses(vec![
+            redirect_response("/sighting_details/9"),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10);
+        let record = scraper.scrape_sighting_page(1).await.unwrap().remove(0);
+        assert_eq!(record.url, Some(format!("{}/sighting_details/9", base_url)));
+        assert_eq!(record.sighting_id, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_redirect_loop_marks_missing() {
+        let base_url = serve_responses(vec![redirect_response("/sighting_details/1"); 3]).await;
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(3)
+            .with_max_redirects(2);
+        assert!(matches!(
+            scraper.scrape_sighting_page(1).await,
+            Err(ScraperError::Failed {
+                reason: FailureReason::TooManyRedirects,
+                ..
+            })
+        ));
+        assert_eq!(scraper.get_missing_sightings(), vec![1]);
+        // Not retried: every attempt would loop the same way
+        assert_eq!(scraper.get_request_timings().retries, 0);
+    }
+
+    fn status_response(status: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        )
+    }
+
+    #[tokio::test]
+    async fn test_retries_back_off_exponentially() {
+        let base_url = serve_responses(vec![
+            status_response("503 Service Unavailable"),
+            status_response("503 Service Unavailable"),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+
+        let clock = Arc::new(ManualClock::default());
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(1000)
+            .with_max_retries(2)
+            .with_clock(clock.clone());
+        assert!(scraper.scrape_sighting_page(1).await.is_ok());
+
+        // The base delay plus half as much jitter, then the base doubled per retry plus
+        // up to the base as jitter
+        let sleeps: Vec<u128> = clock.sleeps().iter().map(|d| d.as_millis()).collect();
+        assert_eq!(sleeps.len(), 3, "{:?}", sleeps);
+        assert!((1000..1500).contains(&sleeps[0]), "{:?}", sleeps);
+        assert!((2000..3000).contains(&sleeps[1]), "{:?}", sleeps);
+        assert!((4000..5000).contains(&sleeps[2]), "{:?}", sleeps);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_replaces_backoff() {
+        let base_url = serve_responses(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 7\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: soon\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+
+        let clock = Arc::new(ManualClock::default());
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(1000)
+            .with_max_retries(2)
+            .with_clock(clock.clone());
+        assert!(scraper.scrape_sighting_page(1).await.is_ok());
+
+        // The header's wait exactly, then the usual backoff once it can't be read
+        let sleeps: Vec<u128> = clock.sleeps().iter().map(|d| d.as_millis()).collect();
+        assert_eq!(sleeps.len(), 3, "{:?}", sleeps);
+        assert_eq!(sleeps[1], 7000, "{:?}", sleeps);
+        assert!((4000..5000).contains(&sleeps[2]), "{:?}", sleeps);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_date_and_cap() {
+        let base_url = serve_responses(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: Wed, 21 Oct 2015 07:28:30 GMT\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+
+        let start = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = Arc::new(ManualClock::at(start));
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(0)
+            .with_max_retries(2)
+            .with_max_retry_after(Duration::from_secs(60))
+            .with_clock(clock.clone());
+        assert!(scraper.scrape_sighting_page(1).await.is_ok());
+
+        // Until the date on the clock, then the cap in place of the hour asked for
+        let sleeps: Vec<u64> = clock.sleeps().iter().map(|d| d.as_secs()).collect();
+        assert_eq!(sleeps, vec![0, 30, 60]);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let retry_after = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, value.parse().unwrap());
+            parse_retry_after(&headers, now)
+        };
+        assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:29:30 GMT"),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:00:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after("-5"), None);
+        assert_eq!(retry_after("tomorrow"), None);
+        assert_eq!(parse_retry_after(&HeaderMap::new(), now), None);
+    }
+
+    #[tokio::test]
+    async fn test_only_listed_statuses_are_retried() {
+        let base_url = serve_responses(vec![
+            status_response("404 Not Found"),
+            status_response("503 Service Unavailable"),
+            status_response("403 Forbidden"),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+
+        // A 404 is final by default, so the sighting fails without a retry
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(3);
+        assert!(matches!(
+            scraper.scrape_sighting_page(1).await,
+            Err(ScraperError::NotFound { sighting_id: 1 })
+        ));
+        assert_eq!(scraper.get_missing_sightings(), vec![1]);
+        assert_eq!(scraper.get_request_timings().retries, 0);
+
+        // Listing 403 retries it along with the 503
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(3)
+            .with_retry_statuses(&[403, 503]);
+        let record = scraper.scrape_sighting_page(2).await.unwrap().remove(0);
+        assert_eq!(record.common_name, "Monarch");
+        assert_eq!(scraper.get_request_timings().retries, 2);
+    }
+
+    #[tokio::test]
+    async fn test_unretried_429_is_not_exhausted_retries() {
+        let rate_limited = || status_response("429 Too Many Requests");
+        let base_url = serve_responses(vec![rate_limited(), rate_limited(), rate_limited()]).await;
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(1)
+            .with_retry_statuses(&[503]);
+        assert!(matches!(
+            scraper.scrape_sighting_page(1).await,
+            Err(ScraperError::Failed {
+                reason: FailureReason::Http(429),
+                ..
+            })
+        ));
+        assert_eq!(scraper.get_request_timings().retries, 0);
+
+        let scraper = scraper.with_retry_statuses(&[429]);
+        assert!(matches!(
+            scraper.scrape_sighting_page(2).await,
+            Err(ScraperError::RateLimited { sighting_id: 2 })
+        ));
+        assert_eq!(scraper.get_request_timings().retries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_page_pauses_without_using_attempts() {
+        let maintenance = r#"<html><head><title>Site under maintenance</title></head><body class="maintenance-page"></body></html>"#;
+        let base_url = serve_responses(vec![
+            ok_response(maintenance),
+            ok_response(maintenance),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+
+        // The default five-minute cooldown, passed on a clock that doesn't really wait
+        let clock = Arc::new(ManualClock::default());
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(0)
+            .with_clock(clock.clone());
+        let record = scraper.scrape_sighting_page(1).await.unwrap().remove(0);
+
+        assert_eq!(record.common_name, "Monarch");
+        assert!(scraper.get_missing_sightings().is_empty());
+        let cooldowns = clock
+            .sleeps()
+            .into_iter()
+            .filter(|&pause| pause == DEFAULT_MAINTENANCE_COOLDOWN)
+            .count();
+        assert_eq!(cooldowns, 2);
+        assert_eq!(scraper.get_request_timings().maintenance_pauses, 2);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_pauses_run_out() {
+        let maintenance = r#"<html><body class="maintenance-page"></body></html>"#;
+        let base_url = serve_responses(vec![ok_response(maintenance); 3]).await;
+
+        let clock = Arc::new(ManualClock::default());
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_maintenance_pauses(2)
+            .with_clock(clock.clone());
+
+        assert!(matches!(
+            scraper.scrape_sighting_page(1).await,
+            Err(ScraperError::Failed {
+                sighting_id: 1,
+                reason: FailureReason::Maintenance,
+            })
+        ));
+        assert_eq!(scraper.get_missing_sightings(), vec![1]);
+        assert_eq!(scraper.get_request_timings().maintenance_pauses, 2);
+    }
+
+    #[tokio::test]
+    async fn test_bursts_pause_between_groups() {
+        let base_url = serve_responses(vec![ok_response(SIGHTING_HTML); 3]).await;
+
+        let clock = Arc::new(ManualClock::default());
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_bursts(2, Duration::from_secs(60))
+            .with_clock(clock.clone());
+        let records = scraper
+            .scrape_multiple_sightings(&[1, 2, 3], 2)
+            .await
+            .unwrap();
+
+        assert_eq!(records.len(), 3);
+        // Two bursts, so exactly one pause, of 60 to 90 seconds
+        let pauses: Vec<Duration> = clock
+            .sleeps()
+            .into_iter()
+            .filter(|&pause| pause >= Duration::from_secs(1))
+            .collect();
+        assert_eq!(pauses.len(), 1);
+        assert!(pauses[0] <= Duration::from_secs(90), "{:?}", pauses);
+    }
+
+    #[tokio::test]
+    async fn test_profile_links_made_absolute() {
+        let html = include_str!("../tests/fixtures/sighting_profile_links.html");
+        let base_url = serve_responses(vec![ok_response(html)]).await;
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10);
+        let record = scraper.scrape_sighting_page(1).await.unwrap().remove(0);
+
+        assert_eq!(
+            record.submitted_by_url,
+            format!("{}/users/observer123", base_url)
+        );
+        assert_eq!(
+            record.verified_by_url,
+            "https://www.butterfliesandmoths.org/users/coordinator456"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sparse_records_dropped_unless_included() {
+        let sparse = r#"<div class="views-row"><div class="views-field-field-sightingdate"><span class="field-content">2024-01-15</span></div></div>"#;
+        let base_url = serve_responses(vec![ok_response(sparse), ok_response(sparse)]).await;
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10);
+        assert!(matches!(
+            scraper.scrape_sighting_page(1).await,
+            Err(ScraperError::Parse { sighting_id: 1 })
+        ));
+        assert_eq!(scraper.get_missing_sightings(), vec![1]);
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_include_empty(true);
+        let record = scraper.scrape_sighting_page(2).await.unwrap().remove(0);
+        assert_eq!(record.observation_date, "2024-01-15");
+    }
+
+    #[tokio::test]
+    async fn test_min_fields_drops_without_marking_missing() {
+        let partial = r#"<div class="views-row"><div class="views-field-field-sciname"><h4>Monarch <em>Danaus plexippus</em></h4></div></div>"#;
+        let base_url =
+            serve_responses(vec![ok_response(partial), ok_response(SIGHTING_HTML)]).await;
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_min_fields(3);
+        assert!(scraper.scrape_sighting_page(1).await.unwrap().is_empty());
+        assert!(scraper.scrape_sighting_page(2).await.is_ok());
+        assert!(scraper.get_missing_sightings().is_empty());
+        assert_eq!(scraper.below_min_fields.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_resume_from_output_skips_saved_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("out.csv").to_string_lossy().into_owned();
+        let saved: Vec<SightingRecord> = [2, 3]
+            .into_iter()
+            .map(|id| SightingRecord {
+                sighting_id: Some(id),
+                ..Default::default()
+            })
+            .collect();
+        write_csv(&saved, &filename, false, true, "").unwrap();
+
+        let scraper = ButterflyMothScraper::new().with_resume_from_output(&filename);
+        assert_eq!(scraper.resumed_count(), 2);
+        assert_eq!(scraper.filter_missing_sightings(&[1, 2, 3, 4]), vec![1, 4]);
+
+        // Nothing saved yet skips nothing
+        let absent = dir.path().join("absent.csv").to_string_lossy().into_owned();
+        let scraper = ButterflyMothScraper::new().with_resume_from_output(&absent);
+        assert_eq!(scraper.filter_missing_sightings(&[1, 2]), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_approx_dedup_resume_uses_a_bloom_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("out.csv").to_string_lossy().into_owned();
+        let saved: Vec<SightingRecord> = [2, 3]
+            .into_iter()
+            .map(|id| SightingRecord {
+                sighting_id: Some(id),
+                ..Default::default()
+            })
+            .collect();
+        write_csv(&saved, &filename, false, true, "").unwrap();
+
+        let scraper = ButterflyMothScraper::new()
+            .with_approx_dedup(true)
+            .with_resume_from_output(&filename);
+        assert!(scraper.resumed_ids.filter_bytes().is_some());
+        assert_eq!(scraper.resumed_count(), 2);
+        assert_eq!(scraper.filter_missing_sightings(&[1, 2, 3, 4]), vec![1, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_specimen_type_filter_drops_without_marking_missing() {
+        let base_url =
+            serve_responses(vec![ok_response(SIGHTING_HTML), ok_response(SIGHTING_HTML)]).await;
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_specimen_types(&[SpecimenType::Specimen]);
+        assert!(scraper.scrape_sighting_page(1).await.unwrap().is_empty());
+        assert!(scraper.get_missing_sightings().is_empty());
+        assert_eq!(scraper.other_specimen_types.load(Ordering::SeqCst), 1);
+
+        let scraper = scraper.with_specimen_types(&[SpecimenType::Photograph]);
+        let record = scraper.scrape_sighting_page(2).await.unwrap().remove(0);
+        assert_eq!(record.specimen_type, "Photograph");
+    }
+
+    #[tokio::test]
+    async fn test_retry_missing_dead_letters_exhausted_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.txt");
+        let dead_letter = dir.path().join("dead_letter.txt");
+        std::fs::write(&missing, "5,2\n6\n").unwrap();
+
+        let not_found = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        let base_url =
+            serve_responses(vec![not_found.to_string(), ok_response(SIGHTING_HTML)]).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(0)
+            .with_missing_sightings_file(missing.to_str().unwrap())
+            .with_dead_letter_file(dead_letter.to_str().unwrap());
+
+        let records = scraper.retry_missing_sightings(1, 3).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].sighting_id, Some(6));
+        assert!(scraper.get_missing_sightings().is_empty());
+        assert_eq!(std::fs::read_to_string(&missing).unwrap(), "");
+        assert_eq!(std::fs::read_to_string(&dead_letter).unwrap(), "5,3\n");
+        assert_eq!(scraper.filter_missing_sightings(&[5, 7]), vec![7]);
+        let scraper = scraper.with_approx_dedup(true);
+        assert_eq!(scraper.filter_missing_sightings(&[5, 7]), vec![7]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_failed_sightings_second_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("second-pass.txt");
+        let not_found = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        let base_url = serve_responses(vec![
+            not_found.to_string(),
+            not_found.to_string(),
+            ok_response(SIGHTING_HTML),
+            not_found.to_string(),
+        ])
+        .await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(0)
+            .with_missing_sightings_file(missing.to_str().unwrap());
+
+        assert!(
+            scraper
+                .scrape_multiple_sightings(&[1, 2], 1)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let records = scraper.retry_failed_sightings(&[1, 2], 1).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].sighting_id, Some(1));
+        assert_eq!(std::fs::read_to_string(&missing).unwrap(), "2,1\n");
+    }
+
+    #[test]
+    fn test_read_missing_file_accepts_bare_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("missing.txt");
+        std::fs::write(&filename, "12\n13,4\n\nnot-an-id\n12,2\n").unwrap();
+
+        let entries = read_missing_file(filename.to_str().unwrap()).unwrap();
+        assert_eq!(entries, vec![(12, 1), (13, 4), (12, 2)]);
+    }
+
+    #[test]
+    fn test_gzipped_missing_file_appends_members() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("missing.txt.gz");
+        let filename = filename.to_str().unwrap();
+        let mut pending = Vec::new();
+
+        write_missing_file(filename, &[(12, 1), (13, 4)]).unwrap();
+        let packed = std::fs::metadata(filename).unwrap().len();
+        // Lines wait until a member's worth has built up, then go out together
+        append_missing_line(filename, &mut pending, 12, 2).unwrap();
+        assert_eq!(std::fs::metadata(filename).unwrap().len(), packed);
+        for sighting_id in 1..GZIP_MEMBER_LINES as u64 {
+            append_missing_line(filename, &mut pending, 100 + sighting_id, 1).unwrap();
+        }
+        assert!(pending.is_empty());
+        let before_last = std::fs::metadata(filename).unwrap().len() as usize;
+        append_missing_line(filename, &mut pending, 20, 1).unwrap();
+        flush_missing_lines(filename, &mut pending).unwrap();
+
+        let entries = read_missing_file(filename).unwrap();
+        assert_eq!(entries.len(), 2 + GZIP_MEMBER_LINES + 1);
+        assert_eq!(entries[..3], [(12, 1), (13, 4), (12, 2)]);
+        assert_eq!(entries.last(), Some(&(20, 1)));
+
+        // Repacking leaves the same lines in a single gzip member
+        let repacked = dir.path().join("repacked.txt.gz");
+        let repacked = repacked.to_str().unwrap();
+        std::fs::copy(filename, repacked).unwrap();
+        append_missing_line(repacked, &mut pending, 21, 1).unwrap();
+        repack_missing_file(repacked, &mut pending).unwrap();
+        let one_member =
+            std::io::read_to_string(flate2::read::GzDecoder::new(File::open(repacked).unwrap()))
+                .unwrap();
+        assert_eq!(one_member.lines().count(), entries.len() + 1);
+        assert_eq!(one_member.lines().last(), Some("21,1"));
+
+        // A member cut short by a crash, past its 10-byte header, drops only its lines
+        let mut bytes = std::fs::read(filename).unwrap();
+        bytes.truncate(before_last + 12);
+        std::fs::write(filename, &bytes).unwrap();
+        assert_eq!(
+            read_missing_file(filename).unwrap(),
+            entries[..entries.len() - 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_per_id_timeout_marks_missing() {
+        // Accept the connection but never answer
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            sleep(Duration::from_secs(30)).await;
+        });
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_per_id_timeout(Duration::from_millis(200));
+
+        assert!(matches!(
+            scraper.scrape_sighting_page(3).await,
+            Err(ScraperError::Failed {
+                sighting_id: 3,
+                reason: FailureReason::Timeout(_),
+            })
+        ));
+        assert_eq!(scraper.get_missing_sightings(), vec![3]);
+        assert_eq!(scraper.get_request_timings().timeouts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sighting_exists() {
+        let not_found = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string();
+        let base_url = serve_responses(vec![not_found, ok_response("")]).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10);
+
+        assert_eq!(scraper.sighting_exists(1).await, Some(false));
+        assert_eq!(scraper.sighting_exists(2).await, Some(true));
+    }
+
+    /// Answer every HEAD request for a sighting with 200 if its ID is in `existing`, or 404
+    async fn serve_existing(existing: HashSet<u64>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 4096];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]);
+                let id = request
+                    .split_whitespace()
+                    .nth(1)
+                    .and_then(|path| path.rsplit('/').next())
+                    .and_then(|id| id.parse().ok());
+                let response = match id {
+                    Some(id) if existing.contains(&id) => ok_response(""),
+                    _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        base_url
+    }
+
+    #[tokio::test]
+    async fn test_find_max_sighting_bridges_gaps() {
+        // IDs 40-44 were deleted; the last sighting is 57
+        let existing: HashSet<u64> = (3..=57).filter(|id| !(40..=44).contains(id)).collect();
+        let base_url = serve_existing(existing).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_clock(Arc::new(ManualClock::default()));
+
+        // The guess is raised when sightings exist above it, and lowered when not
+        assert_eq!(scraper.find_max_sighting(1, 10, 8).await.unwrap(), 57);
+        assert_eq!(scraper.find_max_sighting(1, 1000, 8).await.unwrap(), 57);
+        assert!(scraper.find_max_sighting(100, 1000, 8).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_custom_headers_are_sent() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let request = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 4096];
+            let read = socket.read(&mut request).await.unwrap();
+            let _ = socket.write_all(ok_response("").as_bytes()).await;
+            String::from_utf8_lossy(&request[..read]).to_lowercase()
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.append("x-api-key", "secret".parse().unwrap());
+        headers.append("x-tag", "a".parse().unwrap());
+        headers.append("x-tag", "b".parse().unwrap());
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_headers(headers);
+        scraper.sighting_exists(1).await;
+
+        let request = request.await.unwrap();
+        assert!(request.contains("x-api-key: secret"));
+        assert!(request.contains("x-tag: a"));
+        assert!(request.contains("x-tag: b"));
+    }
+
+    #[tokio::test]
+    async fn test_request_ids_are_unique() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = tokio::spawn(async move {
+            let mut ids = Vec::new();
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![0u8; 4096];
+                let read = socket.read(&mut request).await.unwrap();
+                let _ = socket.write_all(ok_response("").as_bytes()).await;
+                let request = String::from_utf8_lossy(&request[..read]).to_lowercase();
+                ids.extend(
+                    request
+                        .lines()
+                        .find_map(|line| line.strip_prefix("x-request-id: "))
+                        .map(str::to_string),
+                );
+            }
+            ids
+        });
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_request_id(true);
+        scraper.sighting_exists(1).await;
+        scraper.sighting_exists(2).await;
+
+        let ids = requests.await.unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+        assert!(ids.iter().all(|id| Uuid::parse_str(id).is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_sitemap_ids() {
+        let xml = r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://www.butterfliesandmoths.org/sighting_details/12</loc></url>
+  <url><loc>https://www.butterfliesandmoths.org/species/Danaus-plexippus</loc></url>
+  <url><loc>https://www.butterfliesandmoths.org/sighting_details/7</loc></url>
+  <url><loc>https://www.butterfliesandmoths.org/sighting_details/12</loc></url>
+</urlset>"#;
+        let base_url = serve_responses(vec![ok_response(xml)]).await;
+        let scraper = ButterflyMothScraper::new();
+
+        let ids = scraper
+            .fetch_sitemap_ids(&format!("{}/sitemap.xml", base_url))
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![12, 7]);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_scrape_starts_nothing() {
+        let cancel = CancellationToken::new();
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url("http://127.0.0.1:9")
+            .with_delay(10)
+            .with_cancellation(cancel.clone());
+        cancel.cancel();
+
+        let records = scraper.scrape_sighting_range(1, 20, 2).await.unwrap();
+        assert!(records.is_empty());
+        assert!(scraper.get_missing_sightings().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_mid_backoff_saves_partial_results() {
+        let base_url = serve_responses(vec![
+            ok_response(SIGHTING_HTML),
+            status_response("404 Not Found"),
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 60\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+        ])
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let missing_path = dir.path().join("missing.txt");
+        let output_path = dir.path().join("sightings.csv");
+        let cancel = CancellationToken::new();
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_missing_sightings_file(missing_path.to_str().unwrap())
+            .with_cancellation(cancel.clone());
+
+        // Sighting 3 is waiting out its minute-long Retry-After when the signal arrives
+        let shutdown = async {
+            sleep(Duration::from_millis(500)).await;
+            cancel.cancel();
+        };
+        let (scraped, _) = tokio::join!(
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                scraper.scrape_multiple_sightings(&[1, 2, 3], 1)
+            ),
+            shutdown
+        );
+        let records = scraped.expect("the backoff was not cut short").unwrap();
+        scraper
+            .save_to_csv(&records, output_path.to_str().unwrap())
+            .unwrap();
+
+        let saved = read_csv(output_path.to_str().unwrap()).unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].sighting_id, Some(1));
+        // The cancelled sighting is left to be scraped on resume, not marked missing
+        assert_eq!(scraper.get_missing_sightings(), vec![2]);
+        assert_eq!(std::fs::read_to_string(&missing_path).unwrap(), "2,1\n");
+    }
+
+    #[tokio::test]
+    async fn test_sparse_range_stops_after_density_window() {
+        let not_found = || status_response("404 Not Found");
+        let base_url = serve_responses(vec![
+            not_found(),
+            ok_response(SIGHTING_HTML),
+            not_found(),
+            not_found(),
+            not_found(),
+        ])
+        .await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_density_check(DensityCheck {
+                window: 5,
+                min_density: 0.5,
+                abort: true,
+            });
+
+        scraper.scrape_sighting_range(1, 20, 1).await.unwrap();
+        assert!(scraper.is_cancelled());
+        // One at a time, nothing starts after the fifth ID finishes
+        assert_eq!(scraper.get_missing_sightings(), vec![1, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_pagination_merges_rows_until_a_link_loops() {
+        let first = r#"<div class="views-row"><div class="views-field-field-sciname"><h4>Monarch <em>Danaus plexippus</em></h4></div></div>
+            <ul class="pager"><li class="pager-next"><a href="?page=1">next</a></li></ul>"#;
+        let second = r#"<div class="views-row"><div class="views-field-field-locality"><span class="field-content">Travis County</span></div></div>
+            <a rel="next" href="/sighting_details/1">next</a>"#;
+        let base_url = serve_responses(vec![ok_response(first), ok_response(second)]).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_pagination(Pagination {
+                max_pages: 5,
+                records_per_page: None,
+            });
+
+        let mut records = scraper.scrape_sighting_page(1).await.unwrap();
+        assert_eq!(records.len(), 1);
+        let record = records.remove(0);
+        assert_eq!(record.scientific_name, "Danaus plexippus");
+        assert_eq!(record.locality, "Travis County");
+        assert_eq!(record.url, Some(format!("{}/sighting_details/1", base_url)));
+
+        // A page short of a full page is the last, so its next link is never requested
+        let base_url = serve_responses(vec![ok_response(first)]).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_pagination(Pagination {
+                max_pages: 5,
+                records_per_page: Some(2),
+            });
+        let record = scraper.scrape_sighting_page(1).await.unwrap().remove(0);
+        assert_eq!(record.locality, "");
+    }
+
+    #[tokio::test]
+    async fn test_all_rows_with_pagination_adds_rows_until_a_link_loops() {
+        let first = include_str!("../tests/fixtures/sighting_page_1.html");
+        let second = include_str!("../tests/fixtures/sighting_page_2.html");
+        let base_url = serve_responses(vec![ok_response(first), ok_response(second)]).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_all_rows(true)
+            .with_pagination(Pagination {
+                max_pages: 5,
+                records_per_page: None,
+            });
+
+        // Two rows on the first page and one on the second, whose next link leads back
+        let records = scraper.scrape_sighting_page(1).await.unwrap();
+        let names: Vec<&str> = records.iter().map(|r| r.scientific_name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["Danaus plexippus", "Danaus gilippus", "Limenitis archippus"]
+        );
+        let url = Some(format!("{}/sighting_details/1", base_url));
+        assert!(
+            records
+                .iter()
+                .all(|r| r.sighting_id == Some(1) && r.url == url)
+        );
+
+        // A page short of a full page is the last, so its next link is never requested
+        let base_url = serve_responses(vec![ok_response(first)]).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_all_rows(true)
+            .with_pagination(Pagination {
+                max_pages: 5,
+                records_per_page: Some(3),
+            });
+        assert_eq!(scraper.scrape_sighting_page(1).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_all_rows_keeps_every_row_of_a_page() {
+        let page = r#"<div class="views-row"><div class="views-field-field-sciname"><h4>Monarch <em>Danaus plexippus</em></h4></div></div>
+            <div class="views-row"><div class="views-field-field-sciname"><h4>Queen <em>Danaus gilippus</em></h4></div></div>
+            <div class="views-row"></div>"#;
+        let base_url = serve_responses(vec![ok_response(page), ok_response(page)]).await;
+
+        // Only the first row by default
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10);
+        let records = scraper.scrape_sighting_page(1).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].scientific_name, "Danaus plexippus");
+
+        // When asked, every row that isn't empty, all with the page's ID and URL
+        let scraper = scraper.with_all_rows(true);
+        let records = scraper.scrape_sighting_page(2).await.unwrap();
+        let names: Vec<&str> = records.iter().map(|r| r.scientific_name.as_str()).collect();
+        assert_eq!(names, vec!["Danaus plexippus", "Danaus gilippus"]);
+        let url = Some(format!("{}/sighting_details/2", base_url));
+        assert!(
+            records
+                .iter()
+                .all(|r| r.sighting_id == Some(2) && r.url == url)
+        );
+        assert!(scraper.get_missing_sightings().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_every_row_of_a_page_reaches_the_output() {
+        let html = include_str!("../tests/fixtures/sighting_multi_row.html");
+        let base_url = serve_responses(vec![
+            ok_response(html),
+            status_response("404 Not Found"),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_all_rows(true);
+        let records = scraper
+            .scrape_multiple_sightings(&[1, 2, 3], 1)
+            .await
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("out.csv");
+        let filename = filename.to_str().unwrap();
+        scraper.save_to_csv(&records, filename).unwrap();
+        let saved = read_csv(filename).unwrap();
+        let rows: Vec<(Option<u64>, &str)> = saved
+            .iter()
+            .map(|r| (r.sighting_id, r.scientific_name.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (Some(1), "Danaus plexippus"),
+                (Some(1), "Danaus gilippus"),
+                (Some(1), "Limenitis archippus"),
+                (Some(3), "Danaus plexippus"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ordered_output_streams_records_past_missing_ids() {
+        let base_url = serve_responses(vec![
+            ok_response(SIGHTING_HTML),
+            status_response("404 Not Found"),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("out.ndjson").to_string_lossy().into_owned();
+        let writer = RecordWriter::create(
+            &filename,
+            OutputFormat::Ndjson,
+            &BTreeSet::new(),
+            false,
+            true,
+            "",
+        )
+        .unwrap();
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_ordered_output(OrderedOutput::new(writer, 1));
+
+        // Nothing is returned; it is all in the file, in order, with the 404 skipped
+        assert!(
+            scraper
+                .scrape_multiple_sightings(&[1, 2, 3], 1)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(scraper.finish_ordered_output(), Some(Ok(2)));
+        let ids: Vec<Option<u64>> = read_records(&filename)
+            .unwrap()
+            .iter()
+            .map(|record| record.sighting_id)
+            .collect();
+        assert_eq!(ids, vec![Some(1), Some(3)]);
+        assert_eq!(scraper.get_missing_sightings(), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_scrape_range_streaming_writes_csv() {
+        let base_url = serve_responses(vec![
+            ok_response(SIGHTING_HTML),
+            status_response("404 Not Found"),
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("out.csv").to_string_lossy().into_owned();
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10);
+
+        let written = scraper
+            .scrape_range_streaming(1, 3, 1, &filename)
+            .await
+            .unwrap();
+        assert_eq!(written, 2);
+        let ids: Vec<Option<u64>> = read_records(&filename)
+            .unwrap()
+            .iter()
+            .map(|record| record.sighting_id)
+            .collect();
+        assert_eq!(ids, vec![Some(1), Some(3)]);
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_stops_at_first_unexpected_failure() {
+        let base_url = serve_responses(vec![
+            status_response("404 Not Found"),
+            ok_response(SIGHTING_HTML),
+            status_response("500 Internal Server Error"),
+        ])
+        .await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(0)
+            .with_fail_fast(true);
+
+        // The 404 is tolerated; the 500 stops the run before any other ID starts
+        assert!(matches!(
+            scraper.scrape_sighting_range(1, 10, 1).await,
+            Err(ScraperError::Failed {
+                sighting_id: 3,
+                reason: FailureReason::Http(500),
+            })
+        ));
+        assert_eq!(
+            scraper.get_first_failure(),
+            Some((3, FailureReason::Http(500)))
+        );
+        assert_eq!(scraper.get_missing_sightings(), vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_non_html_response_is_not_parsed() {
+        let pdf = "HTTP/1.1 200 OK\r\nContent-Type: application/pdf\r\nContent-Length: 4\r\nConnection: close\r\n\r\n%PDF";
+        let base_url = serve_responses(vec![
+            pdf.to_string(),
+            ok_response(SIGHTING_HTML).replace("text/html", "text/html; charset=utf-8"),
+        ])
+        .await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10);
+
+        let records = scraper.scrape_multiple_sightings(&[1, 2], 1).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(scraper.get_missing_sightings(), vec![1]);
+        let timings = scraper.get_request_timings();
+        assert_eq!(timings.non_html.get("application/pdf"), Some(&1));
+        // Counted apart from pages that were HTML but didn't parse
+        assert!(scraper.get_parse_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_progress_template_keeps_default() {
+        let scraper = ButterflyMothScraper::new().with_progress_template("{pos}/{len} {per_sec}");
+        assert_eq!(scraper.progress_template, "{pos}/{len} {per_sec}");
+
+        let scraper = ButterflyMothScraper::new().with_progress_template("{pos:>x}");
+        assert_eq!(scraper.progress_template, DEFAULT_PROGRESS_TEMPLATE);
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget_is_shared_across_sightings() {
+        let unavailable = || status_response("503 Service Unavailable");
+        let base_url = serve_responses(vec![
+            unavailable(),
+            unavailable(),
+            ok_response(SIGHTING_HTML),
+            unavailable(),
+        ])
+        .await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(3)
+            .with_retry_budget(2);
+
+        let records = scraper.scrape_multiple_sightings(&[1, 2], 1).await.unwrap();
+        // The first sighting spends the budget, so the second fails without a retry
+        assert_eq!(records.len(), 1);
+        assert_eq!(scraper.get_missing_sightings(), vec![2]);
+        let budget = scraper.retry_budget().unwrap();
+        assert_eq!((budget.used(), budget.denied()), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_clean_records_drops_blank_rows() {
+        let blank = include_str!("../tests/fixtures/sighting_blank.html");
+        let base_url = serve_responses(vec![ok_response(blank), ok_response(SIGHTING_HTML)]).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_include_empty(true);
+
+        // Kept by --include-empty, then caught by the final pass
+        let mut records = scraper.scrape_multiple_sightings(&[1, 2], 1).await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(scraper.clean_records(&mut records), 1);
+        assert_eq!(records[0].sighting_id, Some(2));
+        assert_eq!(scraper.get_missing_sightings(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_oversize_body_is_abandoned() {
+        // One body declares its length, the other is chunked and only found out by reading
+        let chunked = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            SIGHTING_HTML.len(),
+            SIGHTING_HTML
+        );
+        let base_url = serve_responses(vec![
+            ok_response(SIGHTING_HTML),
+            chunked,
+            ok_response(SIGHTING_HTML),
+        ])
+        .await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_body_bytes(SIGHTING_HTML.len() as u64 - 1);
+
+        assert!(scraper.scrape_sighting_page(1).await.is_err());
+        assert!(scraper.scrape_sighting_page(2).await.is_err());
+        // Neither was retried
+        assert_eq!(scraper.get_request_timings().retries, 0);
+        assert_eq!(scraper.get_missing_sightings(), vec![1, 2]);
+
+        let scraper = scraper.with_max_body_bytes(SIGHTING_HTML.len() as u64);
+        assert!(scraper.scrape_sighting_page(3).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_truncated_body_is_retried() {
+        // Promise more bytes than are sent, then close the connection
+        let truncated = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            SIGHTING_HTML.len(),
+            &SIGHTING_HTML[..100]
+        );
+        let base_url = serve_responses(vec![truncated, ok_response(SIGHTING_HTML)]).await;
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_max_retries(1);
+
+        let record = scraper.scrape_sighting_page(42).await.unwrap().remove(0);
+        assert_eq!(record.sighting_id, Some(42));
+        assert_eq!(record.scientific_name, "Danaus plexippus");
+        assert!(scraper.get_missing_sightings().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_higher_priority_sightings_start_first() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut paths = Vec::new();
+            for _ in 0..4 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 4096];
+                let read = socket.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..read]).to_string();
+                paths.push(request.split_whitespace().nth(1).unwrap().to_string());
+                socket
+                    .write_all(ok_response(SIGHTING_HTML).as_bytes())
+                    .await
+                    .unwrap();
+            }
+            paths
+        });
+
+        let scraper = ButterflyMothScraper::new()
+            .with_base_url(&base_url)
+            .with_delay(10)
+            .with_priorities(HashMap::from([(2, 5), (3, 5), (4, -1)]));
+        let records = scraper
+            .scrape_multiple_sightings(&[1, 4, 2, 3], 1)
+            .await
+            .unwrap();
+
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            server.await.unwrap(),
+            vec![
+                "/sighting_details/2",
+                "/sighting_details/3",
+                "/sighting_details/1",
+                "/sighting_details/4",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backoff_delay_bounds() {
+        let base = Duration::from_millis(100);
+        let mut rng = rand::rng();
+        for attempt in 0..6 {
+            let floor = base * 2u32.pow(attempt);
+            for _ in 0..50 {
+                let delay = backoff_delay(attempt, base, 2.0, Duration::MAX, base, &mut rng);
+                assert!(delay >= floor && delay < floor + base, "{:?}", delay);
+            }
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay_is_monotonic_and_capped() {
+        let base = Duration::from_millis(250);
+        let max = Duration::from_secs(5);
+        let mut rng = rand::rng();
+        let delays: Vec<Duration> = (0..40)
+            .map(|attempt| backoff_delay(attempt, base, 2.0, max, Duration::ZERO, &mut rng))
+            .collect();
+
+        assert!(delays.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(delays[0], base);
+        assert_eq!(delays[39], max);
+        // No overflow or panic at extreme attempts or with a zero base delay
+        let extreme = backoff_delay(u32::MAX, base, 2.0, max, base, &mut rng);
+        assert!(extreme >= max && extreme < max + base);
+        assert_eq!(
+            backoff_delay(3, Duration::ZERO, 2.0, max, Duration::ZERO, &mut rng),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_start_offset_spreads_first_wave() {
+        let base_delay = Duration::from_millis(1000);
+        let mut rng = rand::rng();
+
+        for index in 0..4 {
+            let offset = start_offset(index, 4, base_delay, &mut rng).as_millis() as usize;
+            assert!((index * 250..(index + 1) * 250).contains(&offset));
+        }
+        assert_eq!(start_offset(4, 4, base_delay, &mut rng), Duration::ZERO);
+        assert_eq!(start_offset(0, 4, Duration::ZERO, &mut rng), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_sighting_id_range_direction() {
+        assert_eq!(sighting_id_range(3, 5), vec![3, 4, 5]);
+        assert_eq!(sighting_id_range(5, 3), vec![5, 4, 3]);
+        assert_eq!(sighting_id_range(4, 4), vec![4]);
+    }
+
+    #[test]
+    fn test_dump_failed_html_caps_size_and_skips_empty() {
+        // The scraper creates the dump directory itself
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("dump");
+        let scraper = ButterflyMothScraper::new().with_dump_failed_html(dir.to_str().unwrap());
+
+        scraper.dump_failed_html(1, "   ");
+        assert!(!dir.join("1.html").exists());
+
+        scraper.dump_failed_html(2, &"x".repeat(MAX_DUMP_BYTES + 10));
+        let written = std::fs::read_to_string(dir.join("2.html")).unwrap();
+        assert_eq!(written.len(), MAX_DUMP_BYTES);
     }
 }
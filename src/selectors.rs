@@ -0,0 +1,189 @@
+use scraper::Selector;
+use std::collections::HashMap;
+
+/// CSS selectors the views-row parser reads each field with, compiled once up front.
+/// Field selectors are matched inside a row; `checklist_regions` joins the text of every match.
+#[derive(Debug, Clone)]
+pub struct FieldSelectors {
+    pub row: Selector,
+    /// Link to the next page of a paginated page, matched anywhere in the document
+    pub next_page: Selector,
+    /// The first text node of the match is the common name
+    pub common_name: Selector,
+    pub scientific_name: Selector,
+    /// The `href` of the match is the species link
+    pub species_link: Selector,
+    pub observation_date: Selector,
+    pub submitted_by: Selector,
+    /// The `href` of the match is the submitter's profile link
+    pub submitted_by_url: Selector,
+    pub specimen_type: Selector,
+    pub status: Selector,
+    pub verified_by: Selector,
+    /// The `href` of the match is the verifier's profile link
+    pub verified_by_url: Selector,
+    pub verified_date: Selector,
+    pub submitted_date: Selector,
+    pub locality: Selector,
+    pub checklist_regions: Selector,
+    pub coordinate_precision: Selector,
+    /// The text of the match is read as decimal degrees
+    pub latitude: Selector,
+    pub longitude: Selector,
+    /// Any match marks the location as obscured
+    pub location_obscured: Selector,
+    /// Each match is a photo, except one inside another match, such as a thumbnail's image
+    pub photos: Selector,
+}
+
+/// Selectors for the Drupal views markup the site serves today, by config key
+const DEFAULT_SELECTORS: [(&str, &str); 21] = [
+    ("row", "div[class*='views-row']"),
+    (
+        "next_page",
+        "a[rel='next'], li.pager-next a, li.pager__item--next a",
+    ),
+    ("common_name", "div.views-field-field-sciname h4"),
+    ("scientific_name", "div.views-field-field-sciname h4 em"),
+    ("species_link", "div.views-field-field-sciname h4 a"),
+    (
+        "observation_date",
+        "div.views-field-field-sightingdate .field-content",
+    ),
+    ("submitted_by", "div.views-field-name .username"),
+    ("submitted_by_url", "div.views-field-name a.username[href]"),
+    (
+        "specimen_type",
+        "div.views-field-field-specimen-type .field-content",
+    ),
+    (
+        "status",
+        "div.views-field-field-sighting-status .field-content",
+    ),
+    ("verified_by", "div.views-field-name-1 .username"),
+    ("verified_by_url", "div.views-field-name-1 a.username[href]"),
+    (
+        "verified_date",
+        "div.views-field-field-recorddate .field-content",
+    ),
+    ("submitted_date", "div.views-field-created .field-content"),
+    ("locality", "div.views-field-field-locality .field-content"),
+    ("checklist_regions", "div.views-field-field-region a"),
+    (
+        "coordinate_precision",
+        "div.views-field-field-coordinate-precision .field-content",
+    ),
+    ("latitude", "div.views-field-field-latitude .field-content"),
+    (
+        "longitude",
+        "div.views-field-field-longitude .field-content",
+    ),
+    (
+        "location_obscured",
+        "div.views-field-field-location-obscured",
+    ),
+    ("photos", "img, a.colorbox"),
+];
+
+impl FieldSelectors {
+    /// Built-in selectors with any given in `overrides` replacing them, keyed by field name
+    /// (or `row` and `next_page`). Unknown keys and selectors that fail to parse are errors.
+    pub fn with_overrides(overrides: &HashMap<String, String>) -> Result<Self, String> {
+        if let Some(key) = overrides
+            .keys()
+            .find(|key| !DEFAULT_SELECTORS.iter().any(|(name, _)| name == key))
+        {
+            return Err(format!("unknown field \"{}\" in selector config", key));
+        }
+
+        let compile = |key: &str| -> Result<Selector, String> {
+            let css = match overrides.get(key) {
+                Some(css) => css.as_str(),
+                None => DEFAULT_SELECTORS
+                    .iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, css)| *css)
+                    .unwrap(),
+            };
+            Selector::parse(css).map_err(|e| format!("invalid selector for {}: {}", key, e))
+        };
+
+        Ok(Self {
+            row: compile("row")?,
+            next_page: compile("next_page")?,
+            common_name: compile("common_name")?,
+            scientific_name: compile("scientific_name")?,
+            species_link: compile("species_link")?,
+            observation_date: compile("observation_date")?,
+            submitted_by: compile("submitted_by")?,
+            submitted_by_url: compile("submitted_by_url")?,
+            specimen_type: compile("specimen_type")?,
+            status: compile("status")?,
+            verified_by: compile("verified_by")?,
+            verified_by_url: compile("verified_by_url")?,
+            verified_date: compile("verified_date")?,
+            submitted_date: compile("submitted_date")?,
+            locality: compile("locality")?,
+            checklist_regions: compile("checklist_regions")?,
+            coordinate_precision: compile("coordinate_precision")?,
+            latitude: compile("latitude")?,
+            longitude: compile("longitude")?,
+            location_obscured: compile("location_obscured")?,
+            photos: compile("photos")?,
+        })
+    }
+
+    /// Parse a TOML table of `field = "selector"` overrides
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let overrides: HashMap<String, String> =
+            toml::from_str(text).map_err(|e| format!("invalid selector config: {}", e))?;
+        Self::with_overrides(&overrides)
+    }
+
+    /// Load selector overrides from a TOML file
+    pub fn load(filename: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(filename)?;
+        Ok(Self::from_toml(&text).map_err(|e| format!("{}: {}", filename, e))?)
+    }
+}
+
+impl Default for FieldSelectors {
+    fn default() -> Self {
+        Self::with_overrides(&HashMap::new()).expect("built-in selectors are valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector_config_overrides_defaults() {
+        let selectors = FieldSelectors::from_toml(
+            r#"
+            row = "article.sighting"
+            scientific_name = "span.latin"
+            "#,
+        )
+        .unwrap();
+        let defaults = FieldSelectors::default();
+
+        assert_eq!(selectors.row, Selector::parse("article.sighting").unwrap());
+        assert_eq!(
+            selectors.scientific_name,
+            Selector::parse("span.latin").unwrap()
+        );
+        assert_eq!(selectors.locality, defaults.locality);
+    }
+
+    #[test]
+    fn test_selector_config_rejects_bad_entries() {
+        let err = FieldSelectors::from_toml(r#"locality = "div[[""#).unwrap_err();
+        assert!(err.contains("locality"), "{}", err);
+
+        let err = FieldSelectors::from_toml(r#"wingspan = "span.size""#).unwrap_err();
+        assert!(err.contains("wingspan"), "{}", err);
+
+        assert!(FieldSelectors::from_toml("row = 3").is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+
+/// Page and child-sitemap locations listed in one sitemap document
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Sitemap {
+    /// `<loc>` entries under `<url>`, i.e. pages
+    pub urls: Vec<String>,
+    /// `<loc>` entries under `<sitemap>`, i.e. child sitemaps of a sitemap index
+    pub sitemaps: Vec<String>,
+}
+
+/// Parse a sitemap or sitemap index document
+pub fn parse_sitemap(xml: &str) -> Result<Sitemap, quick_xml::Error> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut sitemap = Sitemap::default();
+    let mut in_sitemap_entry = false;
+    let mut loc: Option<String> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                "sitemap" => in_sitemap_entry = true,
+                "url" => in_sitemap_entry = false,
+                "loc" => loc = Some(String::new()),
+                _ => {}
+            },
+            Event::Text(text) => {
+                if let Some(loc) = loc.as_mut() {
+                    loc.push_str(&text.xml10_content());
+                }
+            }
+            Event::GeneralRef(entity) => {
+                if let Some(loc) = loc.as_mut() {
+                    let name = entity.xml10_content();
+                    if let Some(ch) = entity.resolve_char_ref()? {
+                        loc.push(ch);
+                    } else if let Some(resolved) = resolve_predefined_entity(&name) {
+                        loc.push_str(resolved);
+                    } else {
+                        loc.push_str(&format!("&{};", name));
+                    }
+                }
+            }
+            Event::End(e) if e.local_name().as_ref() == "loc" => {
+                if let Some(loc) = loc.take() {
+                    let loc = loc.trim().to_string();
+                    if in_sitemap_entry {
+                        sitemap.sitemaps.push(loc);
+                    } else {
+                        sitemap.urls.push(loc);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(sitemap)
+}
+
+/// Extract the sighting ID from a sighting details URL
+pub fn sighting_id_from_url(url: &str) -> Option<u64> {
+    let (_, rest) = url.split_once("/sighting_details/")?;
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sitemap_index() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>https://www.butterfliesandmoths.org/sitemap.xml?page=1</loc></sitemap>
+  <sitemap><loc>https://www.butterfliesandmoths.org/sitemap.xml?page=2&amp;x=y</loc></sitemap>
+</sitemapindex>"#;
+        let sitemap = parse_sitemap(xml).unwrap();
+
+        assert!(sitemap.urls.is_empty());
+        assert_eq!(
+            sitemap.sitemaps,
+            vec![
+                "https://www.butterfliesandmoths.org/sitemap.xml?page=1",
+                "https://www.butterfliesandmoths.org/sitemap.xml?page=2&x=y",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sitemap_urls() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://www.butterfliesandmoths.org/sighting_details/123456</loc><lastmod>2024-01-16</lastmod></url>
+  <url><loc>https://www.butterfliesandmoths.org/species/Danaus-plexippus</loc></url>
+</urlset>"#;
+        let sitemap = parse_sitemap(xml).unwrap();
+
+        assert!(sitemap.sitemaps.is_empty());
+        let ids: Vec<_> = sitemap
+            .urls
+            .iter()
+            .filter_map(|url| sighting_id_from_url(url))
+            .collect();
+        assert_eq!(ids, vec![123456]);
+    }
+
+    #[test]
+    fn test_sighting_id_from_url() {
+        assert_eq!(
+            sighting_id_from_url("https://www.butterfliesandmoths.org/sighting_details/42?x=1"),
+            Some(42)
+        );
+        assert_eq!(
+            sighting_id_from_url("https://example.org/sighting_details/"),
+            None
+        );
+        assert_eq!(sighting_id_from_url("https://example.org/species/42"), None);
+    }
+}
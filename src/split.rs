@@ -0,0 +1,118 @@
+use crate::record::SightingRecord;
+use clap::ValueEnum;
+
+/// Key of the file records go to when their taxon can't be told
+pub const UNKNOWN_KEY: &str = "unknown";
+
+/// What records are split into separate output files by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SplitBy {
+    /// The genus, the first word of the scientific name
+    Genus,
+}
+
+impl SplitBy {
+    /// The name given to `--split-by`
+    pub fn name(&self) -> &'static str {
+        match self {
+            SplitBy::Genus => "genus",
+        }
+    }
+
+    /// The file key for a record, `UNKNOWN_KEY` when its taxon can't be told
+    pub fn key(&self, record: &SightingRecord) -> String {
+        match self {
+            SplitBy::Genus => genus(&record.scientific_name),
+        }
+        .unwrap_or_else(|| UNKNOWN_KEY.to_string())
+    }
+}
+
+/// Genus of a scientific name: its first word, when that is a capitalized run of letters
+/// as a genus is written, so "Danaus plexippus" gives "Danaus" and "sp. 3" gives nothing
+fn genus(scientific_name: &str) -> Option<String> {
+    let word = scientific_name.split_whitespace().next()?;
+    let mut chars = word.chars();
+    let first = chars.next()?;
+    (first.is_uppercase() && chars.all(char::is_alphabetic)).then(|| word.to_string())
+}
+
+/// Sort records into runs that share a key, keeping their order within each run, and
+/// return each key with its records
+pub fn split_records(
+    records: &mut [SightingRecord],
+    split_by: SplitBy,
+) -> Vec<(String, &[SightingRecord])> {
+    records.sort_by_cached_key(|record| split_by.key(record));
+    records
+        .chunk_by(|a, b| split_by.key(a) == split_by.key(b))
+        .map(|run| (split_by.key(&run[0]), run))
+        .collect()
+}
+
+/// `filename` with `-<key>` put before its extension, so `out/sightings.csv` becomes
+/// `out/sightings-Danaus.csv`. Works on `s3://` URLs as on paths.
+pub fn split_filename(filename: &str, key: &str) -> String {
+    let name_start = filename.rfind('/').map_or(0, |slash| slash + 1);
+    match filename[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = filename.split_at(name_start + dot);
+            format!("{}-{}{}", stem, key, extension)
+        }
+        _ => format!("{}-{}", filename, key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: u64, scientific_name: &str) -> SightingRecord {
+        SightingRecord {
+            sighting_id: Some(id),
+            scientific_name: scientific_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_split_records_by_genus() {
+        let mut records = vec![
+            record(1, "Vanessa cardui"),
+            record(2, "Danaus plexippus"),
+            record(3, ""),
+            record(4, "Vanessa atalanta"),
+            record(5, "sp. 3"),
+            record(6, "Danaus gilippus"),
+        ];
+        let split: Vec<(String, Vec<u64>)> = split_records(&mut records, SplitBy::Genus)
+            .into_iter()
+            .map(|(key, run)| (key, run.iter().filter_map(|r| r.sighting_id).collect()))
+            .collect();
+        assert_eq!(
+            split,
+            vec![
+                ("Danaus".to_string(), vec![2, 6]),
+                ("Vanessa".to_string(), vec![1, 4]),
+                ("unknown".to_string(), vec![3, 5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_filename() {
+        assert_eq!(
+            split_filename("out/sightings.csv", "Danaus"),
+            "out/sightings-Danaus.csv"
+        );
+        assert_eq!(
+            split_filename("s3://bucket/2024/sightings.ndjson", "unknown"),
+            "s3://bucket/2024/sightings-unknown.ndjson"
+        );
+        assert_eq!(
+            split_filename("out.d/sightings", "Danaus"),
+            "out.d/sightings-Danaus"
+        );
+        assert_eq!(split_filename(".hidden", "Danaus"), ".hidden-Danaus");
+    }
+}
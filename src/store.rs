@@ -0,0 +1,484 @@
+use crate::merge::{ConflictPolicy, recency_key};
+use crate::record::SightingRecord;
+use crate::schema::record_schema;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{Connection, OpenFlags, Transaction, params, params_from_iter};
+use serde_json::{Map, Value};
+
+/// Table the records are stored in, one column per schema field
+const TABLE: &str = "sightings";
+
+/// Table logging each scrape run recorded with `--record-run`
+const RUNS_TABLE: &str = "runs";
+
+/// One scrape run, as logged in the runs table
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// First and last sighting ID of a range scrape, none for a list of IDs
+    pub range: Option<(u64, u64)>,
+    /// Records the run scraped
+    pub count: usize,
+    /// Sightings the run left missing
+    pub missing_count: usize,
+}
+
+/// Column names in schema order
+fn columns() -> Vec<&'static str> {
+    record_schema()
+        .into_iter()
+        .map(|field| field.name)
+        .collect()
+}
+
+/// Create the sightings table if the database does not have it yet
+fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    let definitions: Vec<String> = record_schema()
+        .into_iter()
+        .map(|field| match field.name {
+            "sighting_id" => "sighting_id INTEGER UNIQUE".to_string(),
+            name if field.field_type == "boolean" || field.field_type == "integer" => {
+                format!("{} INTEGER NOT NULL DEFAULT 0", name)
+            }
+            name if field.field_type == "number" => format!("{} REAL", name),
+            name if field.nullable => format!("{} TEXT", name),
+            name => format!("{} TEXT NOT NULL DEFAULT ''", name),
+        })
+        .collect();
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} ({});",
+        TABLE,
+        definitions.join(", ")
+    ))
+}
+
+/// Create the runs table, and the column tying each sighting to the run that last wrote
+/// it, if the database does not have them yet
+fn create_runs_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            run_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            started_at TEXT NOT NULL,
+            finished_at TEXT NOT NULL,
+            min INTEGER,
+            max INTEGER,
+            count INTEGER NOT NULL,
+            missing_count INTEGER NOT NULL
+        );",
+        RUNS_TABLE
+    ))?;
+    // A database written before runs were recorded lacks the column
+    let has_run_id = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = 'run_id'",
+            TABLE
+        ))?
+        .exists([])?;
+    if !has_run_id {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN run_id INTEGER REFERENCES {}(run_id);",
+            TABLE, RUNS_TABLE
+        ))?;
+    }
+    Ok(())
+}
+
+/// Open the database at `filename` for writing, with `recency` defined as `recency_key`
+/// for keep-newer upserts
+fn open_for_writing(filename: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(filename)?;
+    conn.create_scalar_function(
+        "recency",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let verified_date: Option<String> = ctx.get(0)?;
+            let scraped_at: Option<String> = ctx.get(1)?;
+            Ok(recency_key(
+                verified_date.as_deref().unwrap_or_default(),
+                scraped_at.as_deref().unwrap_or_default(),
+            ))
+        },
+    )?;
+    Ok(conn)
+}
+
+/// Value stored for one column of a record
+fn sql_value(record: &SightingRecord, name: &str) -> Result<SqlValue, String> {
+    match name {
+        "sighting_id" => match record.sighting_id {
+            Some(id) => i64::try_from(id)
+                .map(SqlValue::Integer)
+                .map_err(|_| format!("sighting ID {} is too large for SQLite", id)),
+            None => Ok(SqlValue::Null),
+        },
+        "url" => Ok(record.url.clone().map_or(SqlValue::Null, SqlValue::Text)),
+        "coordinate_precision" => Ok(record
+            .coordinate_precision
+            .clone()
+            .map_or(SqlValue::Null, SqlValue::Text)),
+        "content_hash" => Ok(record
+            .content_hash
+            .clone()
+            .map_or(SqlValue::Null, SqlValue::Text)),
+        "latitude" => Ok(record.latitude.map_or(SqlValue::Null, SqlValue::Real)),
+        "longitude" => Ok(record.longitude.map_or(SqlValue::Null, SqlValue::Real)),
+        "location_obscured" => Ok(SqlValue::Integer(record.location_obscured.into())),
+        "photo_count" => Ok(SqlValue::Integer(record.photo_count.into())),
+        "has_photo" => Ok(SqlValue::Integer(record.has_photo.into())),
+        _ => Ok(SqlValue::Text(record.field_value(name).unwrap_or_default())),
+    }
+}
+
+/// Write records into the SQLite database at `filename`, creating it and its table if
+/// needed. `on_conflict` decides whether a record replaces the row already stored with
+/// its sighting ID.
+pub fn write_sqlite(
+    records: &[SightingRecord],
+    filename: &str,
+    on_conflict: ConflictPolicy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = open_for_writing(filename)?;
+    create_table(&conn)?;
+
+    let tx = conn.transaction()?;
+    insert_records(&tx, records, on_conflict, None)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Write records into the SQLite database at `filename` as `write_sqlite` does, logging
+/// `run` in the runs table and tying each row it writes to it. The run and its records
+/// are written in one transaction, so a failure leaves neither. Returns the run's ID.
+pub fn write_sqlite_run(
+    records: &[SightingRecord],
+    filename: &str,
+    run: &RunInfo,
+    on_conflict: ConflictPolicy,
+) -> Result<i64, Box<dyn std::error::Error>> {
+    let mut conn = open_for_writing(filename)?;
+    create_table(&conn)?;
+    create_runs_table(&conn)?;
+
+    let tx = conn.transaction()?;
+    let timestamp = |time: &DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let bound = |id: u64| i64::try_from(id).ok();
+    tx.execute(
+        &format!(
+            "INSERT INTO {} (started_at, finished_at, min, max, count, missing_count)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            RUNS_TABLE
+        ),
+        params![
+            timestamp(&run.started_at),
+            timestamp(&run.finished_at),
+            run.range.and_then(|(first, last)| bound(first.min(last))),
+            run.range.and_then(|(first, last)| bound(first.max(last))),
+            run.count as i64,
+            run.missing_count as i64,
+        ],
+    )?;
+    let run_id = tx.last_insert_rowid();
+    insert_records(&tx, records, on_conflict, Some(run_id))?;
+    tx.commit()?;
+    Ok(run_id)
+}
+
+/// Insert the rows of `records`, tied to `run_id` when given, settling each one whose
+/// sighting ID is already stored by `on_conflict`
+fn insert_records(
+    tx: &Transaction,
+    records: &[SightingRecord],
+    on_conflict: ConflictPolicy,
+    run_id: Option<i64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let fields = columns();
+    let mut columns = fields.clone();
+    if run_id.is_some() {
+        columns.push("run_id");
+    }
+    let values = vec!["?"; columns.len()].join(", ");
+    let sql = match on_conflict {
+        ConflictPolicy::Replace => format!(
+            "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+            TABLE,
+            columns.join(", "),
+            values
+        ),
+        ConflictPolicy::KeepExisting => format!(
+            "INSERT OR IGNORE INTO {} ({}) VALUES ({})",
+            TABLE,
+            columns.join(", "),
+            values
+        ),
+        ConflictPolicy::KeepNewer => {
+            let updates: Vec<String> = columns
+                .iter()
+                .map(|name| format!("{} = excluded.{}", name, name))
+                .collect();
+            format!(
+                "INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT(sighting_id) DO UPDATE SET {} \
+                 WHERE recency(excluded.verified_date, excluded.scraped_at) \
+                 >= recency({table}.verified_date, {table}.scraped_at)",
+                columns.join(", "),
+                values,
+                updates.join(", "),
+                table = TABLE
+            )
+        }
+    };
+    let mut insert = tx.prepare(&sql)?;
+    for record in records {
+        let mut values = fields
+            .iter()
+            .map(|name| sql_value(record, name))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(run_id) = run_id {
+            values.push(SqlValue::Integer(run_id));
+        }
+        insert.execute(params_from_iter(values))?;
+    }
+    Ok(())
+}
+
+/// Read every record from the SQLite database at `filename`, sorted by sighting ID
+pub fn read_sqlite(filename: &str) -> Result<Vec<SightingRecord>, Box<dyn std::error::Error>> {
+    let conn = Connection::open_with_flags(filename, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let columns = columns();
+    let booleans: Vec<bool> = record_schema()
+        .iter()
+        .map(|field| field.field_type == "boolean")
+        .collect();
+    let mut select = conn.prepare(&format!(
+        "SELECT {} FROM {} ORDER BY sighting_id",
+        columns.join(", "),
+        TABLE
+    ))?;
+
+    // Rows go through JSON so the record's serde defaults and types apply
+    let rows = select.query_map([], |row| {
+        let mut object = Map::new();
+        for (index, name) in columns.iter().enumerate() {
+            let value = match row.get_ref(index)? {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(n) if booleans[index] => Value::from(n != 0),
+                ValueRef::Integer(n) => Value::from(n),
+                ValueRef::Real(x) => Value::from(x),
+                ValueRef::Text(text) | ValueRef::Blob(text) => {
+                    Value::from(String::from_utf8_lossy(text).into_owned())
+                }
+            };
+            object.insert(name.to_string(), value);
+        }
+        Ok(object)
+    })?;
+
+    let mut records = Vec::new();
+    for row in rows {
+        records.push(serde_json::from_value(Value::Object(row?))?);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_round_trip_replaces_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("store.db");
+        let filename = filename.to_str().unwrap();
+        let _ = std::fs::remove_file(filename);
+        let record = |id, name: &str| SightingRecord {
+            sighting_id: id,
+            url: id.map(|id| format!("https://example.org/sighting_details/{}", id)),
+            common_name: name.to_string(),
+            scraped_at: "2025-06-01T12:00:00Z".to_string(),
+            ..Default::default()
+        };
+
+        write_sqlite(
+            &[record(Some(2), "Monarch"), record(None, "Unknown")],
+            filename,
+            ConflictPolicy::Replace,
+        )
+        .unwrap();
+        write_sqlite(
+            &[
+                SightingRecord {
+                    coordinate_precision: Some("Generalized to 10 km".to_string()),
+                    latitude: Some(41.96),
+                    longitude: Some(-82.51),
+                    location_obscured: true,
+                    ..record(Some(2), "Monarch Butterfly")
+                },
+                record(Some(1), "Luna Moth"),
+            ],
+            filename,
+            ConflictPolicy::Replace,
+        )
+        .unwrap();
+
+        let records = read_sqlite(filename).unwrap();
+        let names: Vec<(Option<u64>, &str)> = records
+            .iter()
+            .map(|record| (record.sighting_id, record.common_name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (None, "Unknown"),
+                (Some(1), "Luna Moth"),
+                (Some(2), "Monarch Butterfly"),
+            ]
+        );
+        assert_eq!(records[0].url, None);
+        assert_eq!(records[2].scraped_at, "2025-06-01T12:00:00Z");
+        assert!(records[2].location_obscured && !records[1].location_obscured);
+        assert_eq!(
+            records[2].coordinate_precision.as_deref(),
+            Some("Generalized to 10 km")
+        );
+        assert_eq!(records[1].coordinate_precision, None);
+        assert_eq!(
+            (records[2].latitude, records[2].longitude),
+            (Some(41.96), Some(-82.51))
+        );
+        assert_eq!(records[1].latitude, None);
+    }
+
+    #[test]
+    fn test_sqlite_on_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("store.db");
+        let filename = filename.to_str().unwrap();
+        let record = |id, verified_date: &str, scraped_at: &str, name: &str| SightingRecord {
+            sighting_id: Some(id),
+            verified_date: verified_date.to_string(),
+            scraped_at: scraped_at.to_string(),
+            common_name: name.to_string(),
+            ..Default::default()
+        };
+        let names = || -> Vec<String> {
+            read_sqlite(filename)
+                .unwrap()
+                .into_iter()
+                .map(|record| record.common_name)
+                .collect()
+        };
+        let saved = [
+            record(1, "2024-03-01", "2024-03-02T00:00:00Z", "saved"),
+            record(2, "2024-01-01", "2024-01-02T00:00:00Z", "saved"),
+            record(3, "01/15/2024", "2024-02-01T00:00:00Z", "saved"),
+        ];
+        write_sqlite(&saved, filename, ConflictPolicy::Replace).unwrap();
+        let incoming = [
+            record(1, "2024-02-01", "2024-06-01T00:00:00Z", "incoming"),
+            record(2, "2024-05-01", "2024-06-01T00:00:00Z", "incoming"),
+            // The same verified_date in another format, scraped later
+            record(3, "2024-01-15", "2024-06-01T00:00:00Z", "incoming"),
+            record(4, "", "", "incoming"),
+        ];
+
+        write_sqlite(&incoming, filename, ConflictPolicy::KeepExisting).unwrap();
+        assert_eq!(names(), vec!["saved", "saved", "saved", "incoming"]);
+        write_sqlite(&incoming, filename, ConflictPolicy::KeepNewer).unwrap();
+        assert_eq!(names(), vec!["saved", "incoming", "incoming", "incoming"]);
+        write_sqlite(&incoming, filename, ConflictPolicy::Replace).unwrap();
+        assert_eq!(names(), vec!["incoming"; 4]);
+    }
+
+    #[test]
+    fn test_sqlite_runs_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("store.db");
+        let filename = filename.to_str().unwrap();
+        let record = |id| SightingRecord {
+            sighting_id: Some(id),
+            ..Default::default()
+        };
+        let run = |range, count, missing_count| RunInfo {
+            started_at: "2025-06-01T12:00:00Z".parse().unwrap(),
+            finished_at: "2025-06-01T12:05:00Z".parse().unwrap(),
+            range,
+            count,
+            missing_count,
+        };
+
+        // A database from before runs were recorded gains the column
+        write_sqlite(&[record(1)], filename, ConflictPolicy::Replace).unwrap();
+        let first = write_sqlite_run(
+            &[record(1), record(2)],
+            filename,
+            &run(Some((1, 3)), 2, 1),
+            ConflictPolicy::Replace,
+        );
+        let second = write_sqlite_run(
+            &[record(2)],
+            filename,
+            &run(None, 1, 0),
+            ConflictPolicy::Replace,
+        );
+        // A descending range is stored lowest ID first
+        let third = write_sqlite_run(
+            &[],
+            filename,
+            &run(Some((5, 3)), 0, 0),
+            ConflictPolicy::Replace,
+        );
+        assert_eq!((first.unwrap(), second.unwrap(), third.unwrap()), (1, 2, 3));
+
+        // A run that fails to write leaves neither it nor its records
+        let too_large = write_sqlite_run(
+            &[record(3), record(u64::MAX)],
+            filename,
+            &run(None, 2, 0),
+            ConflictPolicy::Replace,
+        );
+        assert!(too_large.is_err());
+
+        let conn = Connection::open(filename).unwrap();
+        let runs: Vec<[Option<i64>; 5]> = conn
+            .prepare("SELECT run_id, min, max, count, missing_count FROM runs")
+            .unwrap()
+            .query_map([], |row| {
+                Ok([
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ])
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            runs,
+            vec![
+                [Some(1), Some(1), Some(3), Some(2), Some(1)],
+                [Some(2), None, None, Some(1), Some(0)],
+                [Some(3), Some(3), Some(5), Some(0), Some(0)],
+            ]
+        );
+        let finished_at: String = conn
+            .query_row("SELECT finished_at FROM runs WHERE run_id = 2", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(finished_at, "2025-06-01T12:05:00Z");
+        // Each row names the run that last wrote it
+        let sightings: Vec<(i64, Option<i64>)> = conn
+            .prepare("SELECT sighting_id, run_id FROM sightings ORDER BY sighting_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(sightings, vec![(1, Some(1)), (2, Some(2))]);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::metrics::{FieldFillStats, TimingReport};
+use crate::record::SightingRecord;
+use crate::util::status;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Regions listed in the summary
+const TOP_REGIONS: usize = 3;
+
+/// Running totals for the end-of-run summary, built one record at a time so a file of any
+/// size can be summarized without holding its records
+#[derive(Debug, Default)]
+pub struct Summary {
+    total: usize,
+    species: HashSet<String>,
+    first_date: Option<String>,
+    last_date: Option<String>,
+    regions: HashMap<String, usize>,
+    /// Count each of a record's checklist regions rather than its joined list
+    split_regions: bool,
+    specimen_types: BTreeMap<String, usize>,
+    with_photo: usize,
+    fill: FieldFillStats,
+    timings: Option<TimingReport>,
+}
+
+/// A region and the number of sightings in it
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct RegionCount {
+    pub region: String,
+    pub count: usize,
+}
+
+/// The summary as written by `summarize --json`
+#[derive(Debug, Serialize)]
+pub struct SummaryReport {
+    pub total_sightings: usize,
+    pub unique_species: usize,
+    pub first_date: Option<String>,
+    pub last_date: Option<String>,
+    pub top_regions: Vec<RegionCount>,
+    /// Sightings per specimen type, by canonical name
+    pub specimen_types: BTreeMap<String, usize>,
+    /// Sightings with at least one photo
+    pub with_photo: usize,
+    /// Percentage of sightings in which each text field was filled
+    pub fill_rates: BTreeMap<&'static str, f64>,
+    /// Request latencies and throughput, for a summary of a scrape
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<TimingReport>,
+}
+
+impl Summary {
+    /// An empty summary, counting each region of a record on its own with `split_regions`
+    pub fn new(split_regions: bool) -> Self {
+        Self {
+            split_regions,
+            ..Default::default()
+        }
+    }
+
+    /// Report the fill rates counted while parsing, which include pages whose records
+    /// were not kept, rather than those of the records added
+    pub fn with_fill_stats(mut self, fill: FieldFillStats) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// Report the request timings of the scrape that saved the records
+    pub fn with_timings(mut self, timings: TimingReport) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
+    pub fn add(&mut self, record: &SightingRecord) {
+        self.total += 1;
+        self.fill.record(record);
+        if !record.scientific_name.is_empty() && !self.species.contains(&record.scientific_name) {
+            self.species.insert(record.scientific_name.clone());
+        }
+
+        let date = &record.observation_date;
+        if !date.is_empty() {
+            if self.first_date.as_ref().is_none_or(|first| date < first) {
+                self.first_date = Some(date.clone());
+            }
+            if self.last_date.as_ref().is_none_or(|last| date > last) {
+                self.last_date = Some(date.clone());
+            }
+        }
+
+        if self.split_regions && !record.checklist_regions_list.is_empty() {
+            // A region listed twice still counts the sighting once
+            let regions: HashSet<&String> = record.checklist_regions_list.iter().collect();
+            for region in regions {
+                self.count_region(region);
+            }
+        } else if !record.checklist_regions.is_empty() {
+            // Records read from a CSV have only the joined string
+            self.count_region(&record.checklist_regions);
+        }
+
+        if record.has_photo {
+            self.with_photo += 1;
+        }
+
+        if let Some(specimen_type) = record.specimen_type() {
+            *self
+                .specimen_types
+                .entry(specimen_type.to_string())
+                .or_default() += 1;
+        }
+    }
+
+    fn count_region(&mut self, region: &str) {
+        if region.is_empty() {
+            return;
+        }
+        match self.regions.get_mut(region) {
+            Some(count) => *count += 1,
+            None => {
+                self.regions.insert(region.to_string(), 1);
+            }
+        }
+    }
+
+    /// The regions with the most sightings, ties broken by name
+    fn top_regions(&self) -> Vec<RegionCount> {
+        let mut regions: Vec<(&String, &usize)> = self.regions.iter().collect();
+        regions.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        regions
+            .into_iter()
+            .take(TOP_REGIONS)
+            .map(|(region, &count)| RegionCount {
+                region: region.clone(),
+                count,
+            })
+            .collect()
+    }
+
+    pub fn report(&self) -> SummaryReport {
+        SummaryReport {
+            total_sightings: self.total,
+            unique_species: self.species.len(),
+            first_date: self.first_date.clone(),
+            last_date: self.last_date.clone(),
+            top_regions: self.top_regions(),
+            specimen_types: self.specimen_types.clone(),
+            with_photo: self.with_photo,
+            fill_rates: self.fill.fill_rates().into_iter().collect(),
+            timings: self.timings.clone(),
+        }
+    }
+
+    pub fn print(&self) {
+        if self.total == 0 {
+            status!("No records to summarize");
+            return;
+        }
+
+        status!("\nSummary:");
+        status!("Total sightings: {}", self.total);
+        status!("Unique species: {}", self.species.len());
+        status!(
+            "With photos: {} ({:.1}%)",
+            self.with_photo,
+            self.with_photo as f64 * 100.0 / self.total as f64
+        );
+        if let (Some(first), Some(last)) = (&self.first_date, &self.last_date) {
+            status!("Date range: {} to {}", first, last);
+        }
+        status!("Top regions:");
+        for RegionCount { region, count } in self.top_regions() {
+            status!("  {}: {}", region, count);
+        }
+        if !self.specimen_types.is_empty() {
+            let mut specimen_types: Vec<(&String, &usize)> = self.specimen_types.iter().collect();
+            specimen_types.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            status!("Specimen types:");
+            for (specimen_type, count) in specimen_types {
+                status!("  {}: {}", specimen_type, count);
+            }
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a SightingRecord> for Summary {
+    fn from_iter<I: IntoIterator<Item = &'a SightingRecord>>(records: I) -> Self {
+        let mut summary = Self::default();
+        for record in records {
+            summary.add(record);
+        }
+        summary
+    }
+}
+
+pub fn print_summary(records: &[SightingRecord], split_regions: bool) {
+    let mut summary = Summary::new(split_regions);
+    records.iter().for_each(|record| summary.add(record));
+    summary.print();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_report() {
+        let record = |species: &str, date: &str, region: &str| SightingRecord {
+            scientific_name: species.to_string(),
+            observation_date: date.to_string(),
+            checklist_regions: region.to_string(),
+            specimen_type: if species.is_empty() { "" } else { "photo" }.to_string(),
+            has_photo: region == "Ontario",
+            ..Default::default()
+        };
+        let records = [
+            record("Danaus plexippus", "2024-06-01", "Ontario"),
+            record("Danaus plexippus", "2023-05-10", "Texas"),
+            record("Actias luna", "", "Ontario"),
+            record("", "2024-08-15", "Maine"),
+            record("Vanessa cardui", "2024-07-04", "Alberta"),
+        ];
+
+        let report = records.iter().collect::<Summary>().report();
+        assert_eq!(report.total_sightings, 5);
+        assert_eq!(report.unique_species, 3);
+        assert_eq!(report.first_date.as_deref(), Some("2023-05-10"));
+        assert_eq!(report.last_date.as_deref(), Some("2024-08-15"));
+        let regions: Vec<(&str, usize)> = report
+            .top_regions
+            .iter()
+            .map(|r| (r.region.as_str(), r.count))
+            .collect();
+        assert_eq!(regions, vec![("Ontario", 2), ("Alberta", 1), ("Maine", 1)]);
+        assert_eq!(report.specimen_types.len(), 1);
+        assert_eq!(report.specimen_types["Photograph"], 4);
+        assert_eq!(report.with_photo, 2);
+        assert_eq!(report.fill_rates["scientific_name"], 80.0);
+        assert_eq!(report.fill_rates["observation_date"], 80.0);
+        assert_eq!(report.fill_rates["verified_by"], 0.0);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["fill_rates"]["checklist_regions"], 100.0);
+        // Only a scrape has request timings
+        assert!(json.get("timings").is_none());
+        let timings = crate::metrics::RequestTimings {
+            latencies: vec![std::time::Duration::from_millis(200); 4],
+            retries: 1,
+            ..Default::default()
+        };
+        let report = records
+            .iter()
+            .collect::<Summary>()
+            .with_timings(timings.report(std::time::Duration::from_secs(2), 5))
+            .report();
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["timings"]["p95_ms"], 200);
+        assert_eq!(json["timings"]["retries"], 1);
+        assert_eq!(json["timings"]["records_per_sec"], 2.5);
+
+        let empty = Summary::default().report();
+        assert_eq!(empty.total_sightings, 0);
+        assert_eq!(empty.first_date, None);
+    }
+
+    #[test]
+    fn test_summary_split_regions() {
+        let record = |regions: &[&str]| SightingRecord {
+            checklist_regions: regions.join(", "),
+            checklist_regions_list: regions.iter().map(|r| r.to_string()).collect(),
+            ..Default::default()
+        };
+        let records = [
+            record(&["Ontario", "Canada"]),
+            record(&["Quebec", "Canada", "Canada"]),
+            record(&["Saint-Pierre, Miquelon"]),
+            // Read from a CSV, so only the joined string
+            SightingRecord {
+                checklist_regions: "Maine".to_string(),
+                ..Default::default()
+            },
+        ];
+        let top = |split_regions: bool| {
+            let mut summary = Summary::new(split_regions);
+            records.iter().for_each(|record| summary.add(record));
+            summary
+                .report()
+                .top_regions
+                .into_iter()
+                .map(|r| (r.region, r.count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            top(true),
+            vec![
+                ("Canada".to_string(), 2),
+                ("Maine".to_string(), 1),
+                ("Ontario".to_string(), 1)
+            ]
+        );
+        assert_eq!(top(false)[0], ("Maine".to_string(), 1));
+        let mut summary = Summary::new(true);
+        summary.add(&records[2]);
+        assert_eq!(
+            summary.report().top_regions[0].region,
+            "Saint-Pierre, Miquelon"
+        );
+    }
+}
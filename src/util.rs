@@ -1,10 +1,38 @@
+use chrono::NaiveDate;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
+/// Whether progress messages and summaries go to stderr, set when records go to stdout
+static STATUS_ON_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Send progress messages and summaries to stderr, keeping stdout for the records
+pub fn status_to_stderr() {
+    STATUS_ON_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn status_on_stderr() -> bool {
+    STATUS_ON_STDERR.load(Ordering::Relaxed)
+}
+
+/// Print a progress message or summary line: to stdout, or to stderr once
+/// `status_to_stderr` has been called
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::util::status_on_stderr() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+pub(crate) use status;
+
 pub fn print_hms(start: &Instant) {
     let millis = start.elapsed().as_millis();
     let seconds = millis / 1000;
     let (hour, minute, second) = (seconds / 3600, (seconds % 3600) / 60, seconds % 60);
-    println!(
+    status!(
         "Elapsed time: {:02}:{:02}:{:02}.{:03}",
         hour,
         minute,
@@ -12,3 +40,88 @@ pub fn print_hms(start: &Instant) {
         millis % 1000
     );
 }
+
+/// Date formats the site has been seen to use
+const DATE_FORMATS: [&str; 4] = ["%Y-%m-%d", "%m/%d/%Y", "%B %d, %Y", "%b %d, %Y"];
+
+/// Parse a date as displayed on a sighting page, or `None` if no known format matches
+pub fn parse_date(text: &str) -> Option<NaiveDate> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(text.trim(), format).ok())
+}
+
+/// Coalesce IDs into inclusive `(start, end)` runs of consecutive values, in ascending order.
+/// Duplicates are ignored and the input need not be sorted.
+pub fn coalesce_ranges(ids: &[u64]) -> Vec<(u64, u64)> {
+    let mut sorted = ids.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for id in sorted {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == id => *end = id,
+            _ => ranges.push((id, id)),
+        }
+    }
+    ranges
+}
+
+/// Format a run as `start-end`, or just `start` for a single ID
+pub fn format_range(&(start, end): &(u64, u64)) -> String {
+    if start == end {
+        start.to_string()
+    } else {
+        format!("{}-{}", start, end)
+    }
+}
+
+/// Put `prefix` in front of the file name in `path`, leaving any directory as it is
+pub fn prefix_file_name(path: &str, prefix: &str) -> String {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}{}", prefix, name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coalesce_ranges() {
+        let ids = [5002, 1000, 1001, 1002, 6001, 6000, 1001, 6002];
+        let ranges = coalesce_ranges(&ids);
+        assert_eq!(ranges, vec![(1000, 1002), (5002, 5002), (6000, 6002)]);
+
+        let formatted: Vec<String> = ranges.iter().map(format_range).collect();
+        assert_eq!(formatted.join(", "), "1000-1002, 5002, 6000-6002");
+    }
+
+    #[test]
+    fn test_coalesce_ranges_edge_cases() {
+        assert!(coalesce_ranges(&[]).is_empty());
+        assert_eq!(coalesce_ranges(&[7]), vec![(7, 7)]);
+        assert_eq!(
+            coalesce_ranges(&[u64::MAX, u64::MAX - 1, 0]),
+            vec![(0, 0), (u64::MAX - 1, u64::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_prefix_file_name() {
+        assert_eq!(
+            prefix_file_name("missing.txt", "moths-"),
+            "moths-missing.txt"
+        );
+        assert_eq!(
+            prefix_file_name("runs/dead_letter.txt", "skippers_"),
+            "runs/skippers_dead_letter.txt"
+        );
+    }
+}
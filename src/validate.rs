@@ -0,0 +1,117 @@
+use crate::metrics::{ParseWarning, check_record};
+use crate::output::read_csv;
+use crate::record::SightingRecord;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+
+/// Something wrong with one row of a saved CSV
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowProblem {
+    /// The same empty field or bad date the scraper warns about while parsing
+    Field(ParseWarning),
+    /// The row has no sighting ID
+    MissingId,
+    /// The sighting ID was already used by the row with this number
+    DuplicateId(usize),
+}
+
+impl fmt::Display for RowProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowProblem::Field(warning) => write!(f, "{}", warning),
+            RowProblem::MissingId => write!(f, "missing sighting_id"),
+            RowProblem::DuplicateId(row) => write!(f, "duplicate of row {}", row),
+        }
+    }
+}
+
+/// A problem found in a saved CSV, with its 1-based data row number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowIssue {
+    pub row: usize,
+    pub sighting_id: Option<u64>,
+    pub problem: RowProblem,
+}
+
+/// Check saved records for empty core fields, unparseable dates and missing or duplicate IDs
+pub fn validate_records(records: &[SightingRecord]) -> Vec<RowIssue> {
+    let mut issues = Vec::new();
+    let mut first_row_by_id: HashMap<u64, usize> = HashMap::new();
+
+    for (index, record) in records.iter().enumerate() {
+        let row = index + 1;
+        let mut problems: Vec<RowProblem> = check_record(record)
+            .into_iter()
+            .map(RowProblem::Field)
+            .collect();
+        match record.sighting_id {
+            None => problems.push(RowProblem::MissingId),
+            Some(sighting_id) => match first_row_by_id.entry(sighting_id) {
+                Entry::Occupied(entry) => problems.push(RowProblem::DuplicateId(*entry.get())),
+                Entry::Vacant(entry) => {
+                    entry.insert(row);
+                }
+            },
+        }
+
+        issues.extend(problems.into_iter().map(|problem| RowIssue {
+            row,
+            sighting_id: record.sighting_id,
+            problem,
+        }));
+    }
+
+    issues
+}
+
+/// Read a saved CSV back and check every row, returning the row count and the issues found
+pub fn validate_csv(filename: &str) -> Result<(usize, Vec<RowIssue>), Box<dyn std::error::Error>> {
+    let records = read_csv(filename)?;
+    Ok((records.len(), validate_records(&records)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sighting(id: Option<u64>, date: &str) -> SightingRecord {
+        SightingRecord {
+            sighting_id: id,
+            common_name: "Monarch".to_string(),
+            scientific_name: "Danaus plexippus".to_string(),
+            observation_date: date.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_records() {
+        let records = vec![
+            sighting(Some(1), "2024-01-15"),
+            sighting(Some(2), "last spring"),
+            sighting(None, "2024-01-15"),
+            sighting(Some(1), ""),
+        ];
+
+        let problems: Vec<(usize, RowProblem)> = validate_records(&records)
+            .into_iter()
+            .map(|issue| (issue.row, issue.problem))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (
+                    2,
+                    RowProblem::Field(ParseWarning::UnparseableDate("observation_date"))
+                ),
+                (3, RowProblem::MissingId),
+                (
+                    4,
+                    RowProblem::Field(ParseWarning::EmptyField("observation_date"))
+                ),
+                (4, RowProblem::DuplicateId(1)),
+            ]
+        );
+    }
+}
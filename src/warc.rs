@@ -0,0 +1,246 @@
+use chrono::{SecondsFormat, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode, Url, Version};
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// An archive of raw HTTP exchanges in WARC 1.1 format. Each record is its own gzip member,
+/// so the file can be read from any record boundary and a run killed partway leaves every
+/// record written so far intact. Once a file reaches its size cap the next exchange starts
+/// a new one, numbered `-00001`, `-00002` and so on before the `.warc.gz` extension.
+pub struct WarcWriter {
+    filename: String,
+    max_bytes: u64,
+    current: Mutex<WarcFile>,
+}
+
+/// The file exchanges are currently appended to
+struct WarcFile {
+    file: File,
+    segment: u32,
+    written: u64,
+    exchanges: u64,
+}
+
+impl WarcWriter {
+    pub fn create(filename: &str, max_bytes: u64) -> std::io::Result<Self> {
+        let current = WarcFile::create(filename, 0)?;
+        Ok(Self {
+            filename: filename.to_string(),
+            max_bytes,
+            current: Mutex::new(current),
+        })
+    }
+
+    /// Archive a request and the response it got as a `request` and a `response` record
+    pub fn write_exchange(
+        &self,
+        target_uri: &str,
+        request: &[u8],
+        response: &[u8],
+    ) -> std::io::Result<()> {
+        let response_id = record_id();
+        let mut bytes = gzip_record(
+            "response",
+            &response_id,
+            &[("WARC-Target-URI", target_uri)],
+            "application/http;msgtype=response",
+            response,
+        )?;
+        bytes.extend(gzip_record(
+            "request",
+            &record_id(),
+            &[
+                ("WARC-Target-URI", target_uri),
+                ("WARC-Concurrent-To", &response_id),
+            ],
+            "application/http;msgtype=request",
+            request,
+        )?);
+
+        let mut current = self.current.lock().unwrap();
+        if current.exchanges > 0 && current.written + bytes.len() as u64 > self.max_bytes {
+            *current = WarcFile::create(&self.filename, current.segment + 1)?;
+        }
+        current.file.write_all(&bytes)?;
+        current.written += bytes.len() as u64;
+        current.exchanges += 1;
+        Ok(())
+    }
+}
+
+impl WarcFile {
+    /// Start a segment with the `warcinfo` record describing it
+    fn create(filename: &str, segment: u32) -> std::io::Result<Self> {
+        let filename = segment_filename(filename, segment);
+        let mut file = File::create(&filename)?;
+        let info = format!(
+            "software: Sachem/{}\r\nformat: WARC File Format 1.1\r\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        let name = std::path::Path::new(&filename)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let bytes = gzip_record(
+            "warcinfo",
+            &record_id(),
+            &[("WARC-Filename", &name)],
+            "application/warc-fields",
+            info.as_bytes(),
+        )?;
+        file.write_all(&bytes)?;
+        Ok(Self {
+            file,
+            segment,
+            written: bytes.len() as u64,
+            exchanges: 0,
+        })
+    }
+}
+
+/// The file a segment is written to: `filename` itself for the first, then numbered
+fn segment_filename(filename: &str, segment: u32) -> String {
+    if segment == 0 {
+        return filename.to_string();
+    }
+    match filename.strip_suffix(".warc.gz") {
+        Some(stem) => format!("{}-{:05}.warc.gz", stem, segment),
+        None => format!("{}-{:05}", filename, segment),
+    }
+}
+
+/// A record's `WARC-Record-ID`, a random (version 4) UUID URN
+fn record_id() -> String {
+    format!("<urn:uuid:{}>", Uuid::new_v4())
+}
+
+/// One WARC record compressed as a gzip member of its own
+fn gzip_record(
+    warc_type: &str,
+    record_id: &str,
+    fields: &[(&str, &str)],
+    content_type: &str,
+    block: &[u8],
+) -> std::io::Result<Vec<u8>> {
+    let mut header = format!(
+        "WARC/1.1\r\nWARC-Type: {}\r\nWARC-Record-ID: {}\r\nWARC-Date: {}\r\n",
+        warc_type,
+        record_id,
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    for (name, value) in fields {
+        header.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    header.push_str(&format!(
+        "Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        content_type,
+        block.len()
+    ));
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(header.as_bytes())?;
+    encoder.write_all(block)?;
+    encoder.write_all(b"\r\n\r\n")?;
+    encoder.finish()
+}
+
+/// A request as it would appear on the wire, for a `request` record
+pub fn http_request(method: &Method, url: &str, headers: &HeaderMap) -> Vec<u8> {
+    let (path, host) = match Url::parse(url) {
+        Ok(url) => {
+            let path = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            };
+            (path, url.host_str().unwrap_or_default().to_string())
+        }
+        Err(_) => (url.to_string(), String::new()),
+    };
+    let mut bytes = format!("{} {} HTTP/1.1\r\nhost: {}\r\n", method, path, host).into_bytes();
+    write_headers(&mut bytes, headers);
+    bytes
+}
+
+/// A response as it would appear on the wire, for a `response` record
+pub fn http_response(
+    version: Version,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Vec<u8> {
+    let mut bytes = format!("{:?} {}\r\n", version, status).into_bytes();
+    write_headers(&mut bytes, headers);
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// Header lines followed by the blank line ending the header block
+fn write_headers(bytes: &mut Vec<u8>, headers: &HeaderMap) {
+    for (name, value) in headers {
+        bytes.extend_from_slice(name.as_str().as_bytes());
+        bytes.extend_from_slice(b": ");
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    fn read_warc(filename: &str) -> String {
+        let mut text = String::new();
+        MultiGzDecoder::new(File::open(filename).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn test_warc_records_and_rollover() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("pages.warc.gz");
+        let filename = filename.to_str().unwrap();
+
+        let url = "https://example.org/sighting_details/7?x=1";
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "text/html".parse().unwrap());
+        let request = http_request(&Method::GET, url, &HeaderMap::new());
+        let response = http_response(Version::HTTP_11, StatusCode::OK, &headers, b"<html/>");
+
+        // A cap this small rolls over after every exchange
+        let writer = WarcWriter::create(filename, 1).unwrap();
+        writer.write_exchange(url, &request, &response).unwrap();
+        writer.write_exchange(url, &request, &response).unwrap();
+
+        let first = read_warc(filename);
+        assert!(first.starts_with("WARC/1.1\r\nWARC-Type: warcinfo\r\n"));
+        assert!(first.contains("WARC-Filename: pages.warc.gz\r\n"));
+        assert!(first.contains(
+            "Content-Type: application/http;msgtype=response\r\nContent-Length: 51\r\n\r\n\
+             HTTP/1.1 200 OK\r\ncontent-type: text/html\r\n\r\n<html/>\r\n\r\n"
+        ));
+        assert!(
+            first.contains(
+                "GET /sighting_details/7?x=1 HTTP/1.1\r\nhost: example.org\r\n\r\n\r\n\r\n"
+            )
+        );
+        assert_eq!(first.matches("WARC-Type: response").count(), 1);
+        for line in first.lines().filter(|l| l.starts_with("WARC-Record-ID: ")) {
+            let urn = &line["WARC-Record-ID: <urn:uuid:".len()..line.len() - 1];
+            assert_eq!(Uuid::parse_str(urn).unwrap().get_version_num(), 4);
+        }
+
+        let second = read_warc(dir.path().join("pages-00001.warc.gz").to_str().unwrap());
+        assert!(second.contains("WARC-Filename: pages-00001.warc.gz\r\n"));
+        assert_eq!(second.matches("WARC-Type: request").count(), 1);
+    }
+}
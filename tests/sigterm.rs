@@ -0,0 +1,125 @@
+#![cfg(unix)]
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SIGHTING_HTML: &str = include_str!("fixtures/sighting.html");
+
+/// Serve every sighting page from `listener`: even IDs get the fixture, odd IDs a 404.
+/// Each requested ID goes to `requested`, and `served` hears about every response.
+fn serve_sightings(
+    listener: TcpListener,
+    requested: Arc<Mutex<Vec<u64>>>,
+    served: mpsc::Sender<()>,
+) {
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { break };
+        let mut request = [0u8; 4096];
+        let n = stream.read(&mut request).unwrap_or(0);
+        let request = String::from_utf8_lossy(&request[..n]);
+        let sighting_id = request
+            .split_whitespace()
+            .nth(1)
+            .and_then(|path| path.rsplit('/').next())
+            .and_then(|id| id.parse::<u64>().ok());
+        let response = match sighting_id {
+            Some(id) if id % 2 == 0 => format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                SIGHTING_HTML.len(),
+                SIGHTING_HTML
+            ),
+            _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string(),
+        };
+        let _ = stream.write_all(response.as_bytes());
+        if let Some(id) = sighting_id {
+            requested.lock().unwrap().push(id);
+        }
+        let _ = served.send(());
+    }
+}
+
+#[test]
+fn test_sigterm_saves_partial_results() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    let (served_tx, served_rx) = mpsc::channel();
+    {
+        let requested = Arc::clone(&requested);
+        thread::spawn(move || serve_sightings(listener, requested, served_tx));
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.csv");
+    let missing = dir.path().join("missing.txt");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_Sachem"))
+        .args(["--min", "1", "--max", "1000"])
+        .args(["--concurrent", "1", "--delay", "50", "--retries", "0"])
+        .args(["--base-url", &base_url])
+        .arg("--output")
+        .arg(&output)
+        .arg("--missing")
+        .arg(&missing)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Once a few pages are served the run is under way, so its signal handler is in place
+    for _ in 0..4 {
+        served_rx
+            .recv_timeout(Duration::from_secs(30))
+            .expect("the scraper stopped requesting pages");
+    }
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("the scraper kept running after SIGTERM");
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert!(status.success(), "the scraper exited with {}", status);
+
+    // The run stopped well short of --max, and what it had was saved
+    let requested = requested.lock().unwrap().clone();
+    assert!(requested.len() >= 4 && requested.len() < 1000);
+
+    let mut reader = csv::Reader::from_path(&output).unwrap();
+    assert_eq!(reader.headers().unwrap().get(0), Some("sighting_id"));
+    let saved: HashSet<u64> = reader
+        .records()
+        .map(|row| row.unwrap()[0].parse().unwrap())
+        .collect();
+    let found: HashSet<u64> = requested.iter().copied().filter(|id| id % 2 == 0).collect();
+    assert!(!saved.is_empty());
+    assert_eq!(saved, found);
+
+    let missing = std::fs::read_to_string(&missing).unwrap();
+    for id in requested.iter().filter(|id| *id % 2 == 1) {
+        assert!(
+            missing
+                .lines()
+                .any(|line| line.split(',').next() == Some(&id.to_string())),
+            "sighting {} is not in the missing file:\n{}",
+            id,
+            missing
+        );
+    }
+}